@@ -0,0 +1,119 @@
+//! Exponential backoff for reconnecting to the server.
+//!
+//! The delay starts at `bot.reconnect.initial_delay` seconds and is multiplied
+//! by `bot.reconnect.multiplier` after every attempt, up to
+//! `bot.reconnect.max_delay`. Each delay is randomly adjusted by up to
+//! `bot.reconnect.jitter` (a fraction of the delay) in either direction so
+//! that a netsplit doesn't have every bot reconnecting in lockstep.
+
+use std::time::Duration;
+
+use rand;
+
+use environment::Env;
+use environment::secs_to_duration;
+
+pub struct Backoff {
+    enabled: bool,
+    initial: f64,
+    max: f64,
+    multiplier: f64,
+    jitter: f64,
+    reset_after: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Creates a `Backoff` configured from the `bot.reconnect` section.
+    pub fn from_env(env: &Env) -> Backoff {
+        let number = |key: &str, or: f64| {
+            env.conf_number(&format!("bot.reconnect.{}", key)).unwrap_or(or)
+        };
+
+        let initial = number("initial_delay", 5.0).max(0.0);
+
+        Backoff {
+            enabled: env.conf_bool("bot.reconnect.enabled").unwrap_or(true),
+            initial: initial,
+            max: number("max_delay", 300.0).max(initial),
+            multiplier: number("multiplier", 2.0).max(1.0),
+            jitter: number("jitter", 0.2).max(0.0).min(1.0),
+            reset_after: secs_to_duration(number("reset_after", 60.0)),
+            attempts: 0,
+        }
+    }
+
+    /// Whether the bot should reconnect at all.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Called when a connection ends. A connection that stayed up for long
+    /// enough is considered to have succeeded, and the delay starts over.
+    pub fn connection_lasted(&mut self, up: Duration) {
+        if up >= self.reset_after {
+            self.attempts = 0;
+        }
+    }
+
+    /// Computes the delay before the next attempt and counts the attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.base_delay();
+        self.attempts = self.attempts.saturating_add(1);
+
+        let spread = (rand::random::<f64>() * 2.0 - 1.0) * self.jitter;
+        secs_to_duration(delay * (1.0 + spread))
+    }
+
+    /// The delay for the current attempt, before jitter is applied.
+    fn base_delay(&self) -> f64 {
+        let growth = self.multiplier.powi(self.attempts.min(64) as i32);
+        (self.initial * growth).min(self.max)
+    }
+}
+
+#[cfg(test)]
+fn test_backoff() -> Backoff {
+    Backoff {
+        enabled: true,
+        initial: 5.0,
+        max: 60.0,
+        multiplier: 2.0,
+        jitter: 0.0,
+        reset_after: Duration::from_secs(60),
+        attempts: 0,
+    }
+}
+
+#[test]
+fn backoff_grows_to_max() {
+    let mut b = test_backoff();
+    let delays: Vec<u64> = (0..6).map(|_| b.next_delay().as_secs()).collect();
+    assert_eq!(delays, vec![5, 10, 20, 40, 60, 60]);
+}
+
+#[test]
+fn backoff_resets_after_stable_connection() {
+    let mut b = test_backoff();
+    b.next_delay();
+    b.next_delay();
+
+    b.connection_lasted(Duration::from_secs(10));
+    assert_eq!(b.next_delay().as_secs(), 20);
+
+    b.connection_lasted(Duration::from_secs(600));
+    assert_eq!(b.next_delay().as_secs(), 5);
+}
+
+#[test]
+fn backoff_jitter_stays_in_range() {
+    let mut b = test_backoff();
+    b.jitter = 0.5;
+
+    for _ in 0..100 {
+        b.attempts = 0;
+        let d = b.next_delay();
+        assert!(d >= Duration::from_millis(2500));
+        assert!(d <= Duration::from_millis(7500));
+    }
+}
@@ -1,9 +1,13 @@
 //! The main bot entry point.
 
+use std::borrow::Cow;
 use std::collections::VecDeque;
+use std::fs;
 use std::io;
+use std::io::prelude::*;
 use std::mem;
 use std::net::ToSocketAddrs;
+use std::rc::Rc;
 use std::str;
 use std::thread;
 use std::time;
@@ -21,6 +25,7 @@ use futures::task;
 
 use tokio_core::reactor::Core;
 use tokio_core::reactor::Handle;
+use tokio_core::reactor::Timeout;
 use tokio_core::net::TcpStream;
 use tokio_core::net::TcpStreamNew;
 use tokio_io::AsyncRead;
@@ -29,16 +34,42 @@ use tokio_io::codec::Decoder;
 use tokio_io::codec::Encoder;
 use tokio_io::codec::Framed;
 
+use native_tls;
+use tokio_tls::TlsConnector;
+
+use backoff::Backoff;
+use commands;
+use commands::CommandRegistry;
 use environment::Env;
 use irc;
+use irc::text;
 use network;
+use network::Output;
+use ratelimit::TokenBucket;
+use scheduler::Scheduler;
+use storage::Storage;
+use watchdog::Action;
+use watchdog::Watchdog;
 
 pub struct Bot<S> {
     _env: Env,
-    _handle: Handle,
+    registry: Rc<CommandRegistry>,
+    handle: Handle,
     sock: Sock<S>,
     bot_state: BotState,
     net: network::Network,
+    watchdog: Watchdog,
+    watchdog_timer: Option<Timeout>,
+    net_timer: Option<(time::Instant, Timeout)>,
+    scheduler: Scheduler,
+}
+
+/// How a connection ended, if it wasn't an error.
+pub enum Ended {
+    /// The server closed the connection.
+    Disconnected,
+    /// We sent a `QUIT`, and shouldn't reconnect.
+    Quit,
 }
 
 enum BotState {
@@ -49,34 +80,149 @@ enum BotState {
 }
 
 impl<S: AsyncRead + AsyncWrite + Sized> Bot<S> {
-    fn new(env: Env, handle: Handle, raw_sock: S) -> Bot<S> {
-        let mut sock = Sock::new(raw_sock);
+    fn new(env: Env, registry: Rc<CommandRegistry>, handle: Handle, raw_sock: S) -> Bot<S> {
+        let mut sock = Sock::new(&env, handle.clone(), raw_sock);
         let net = network::Network::register(env.clone(), &mut sock);
+        let watchdog = Watchdog::from_env(&env, time::Instant::now());
 
         Bot {
             _env: env,
-            _handle: handle,
+            registry: registry,
+            handle: handle.clone(),
             sock: sock,
             bot_state: BotState::Start,
             net: net,
+            watchdog: watchdog,
+            watchdog_timer: None,
+            net_timer: None,
+            scheduler: Scheduler::new(handle.clone()),
         }
     }
 }
 
 impl<S> Bot<S> {
-    fn handle_line(&mut self, line: String) {
-        match irc::Message::parse(&line[..]) {
-            Ok(m) => self.net.handle_message(&mut self.sock, m),
-            Err(e) => error!("could not parse IRC message: {}", e),
+    fn handle_line(&mut self, line: String) -> io::Result<()> {
+        self.watchdog.activity(time::Instant::now());
+
+        match irc::OwnedMessage::parse(&line[..]) {
+            Ok(m) => {
+                if let irc::Command::Pong(ref server, ref token) = m.command {
+                    self.handle_pong(server, token.as_ref().map(|t| &t[..]));
+                }
+                try!(self.net.handle_message(&mut self.sock, &m));
+                commands::handle_message(&self.registry, &mut self.net, &mut self.sock, &m);
+            },
+            Err(e) => error!("could not parse IRC message {:?}: {}", line, e),
         };
+
+        Ok(())
+    }
+
+    fn handle_pong(&mut self, server: &str, token: Option<&str>) {
+        if let Some(lag) = self.watchdog.pong(server, token, time::Instant::now()) {
+            debug!("lag is {}.{:03}s", lag.as_secs(), lag.subsec_nanos() / 1_000_000);
+            self.net.set_lag(lag);
+        }
+    }
+
+    /// Checks on the watchdog timer, sending a `PING` or giving up on the
+    /// connection if it's time to.
+    fn poll_watchdog(&mut self) -> Poll<(), io::Error> {
+        loop {
+            match self.watchdog.check(time::Instant::now()) {
+                Action::Wait(deadline) => {
+                    if self.watchdog_timer.is_none() {
+                        let timer = try!(Timeout::new_at(deadline, &self.handle));
+                        self.watchdog_timer = Some(timer);
+                    }
+
+                    match self.watchdog_timer.as_mut().map(|t| t.poll()) {
+                        Some(Ok(Async::Ready(()))) => {
+                            self.watchdog_timer = None;
+                        },
+                        Some(Err(e)) => return Err(e),
+                        _ => return Ok(Async::NotReady),
+                    }
+                },
+
+                Action::Ping(token) => {
+                    self.watchdog_timer = None;
+                    debug!("connection idle, checking on the server");
+                    self.sock.send_message(&irc::Message::new("PING", vec![&token]));
+                },
+
+                Action::TimedOut => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "ping timeout"
+                    ));
+                },
+            }
+        }
+    }
+
+    /// Wakes the network up when it has something scheduled, like checking
+    /// whether our nick is free yet.
+    fn poll_net_timer(&mut self) -> Poll<(), io::Error> {
+        loop {
+            let deadline = match self.net.next_tick() {
+                Some(deadline) => deadline,
+                None => {
+                    self.net_timer = None;
+                    return Ok(Async::NotReady);
+                },
+            };
+
+            let now = time::Instant::now();
+            if deadline <= now {
+                self.net_timer = None;
+                self.net.tick(&mut self.sock, now);
+                continue;
+            }
+
+            let stale = match self.net_timer {
+                Some((at, _)) => at != deadline,
+                None => true,
+            };
+            if stale {
+                let timer = try!(Timeout::new_at(deadline, &self.handle));
+                self.net_timer = Some((deadline, timer));
+            }
+
+            match self.net_timer.as_mut().map(|t| t.1.poll()) {
+                Some(Ok(Async::Ready(()))) => {
+                    self.net_timer = None;
+                    continue;
+                },
+                Some(Err(e)) => return Err(e),
+                _ => return Ok(Async::NotReady),
+            }
+        }
+    }
+
+    /// Lets plugins do whatever they have scheduled, once we're connected
+    /// enough to do it.
+    fn poll_scheduler(&mut self) -> Poll<(), io::Error> {
+        if self.net.current_nick().is_none() {
+            return Ok(Async::NotReady);
+        }
+
+        loop {
+            match try!(self.scheduler.poll(self.registry.next_due())) {
+                Async::Ready(now) => self.registry.tick(&self.net, &mut self.sock, now),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
     }
 }
 
 impl<S: AsyncRead + AsyncWrite> Future for Bot<S> {
-    type Item = ();
+    type Item = Ended;
     type Error = io::Error;
 
-    fn poll(&mut self) -> Poll<(), io::Error> {
+    fn poll(&mut self) -> Poll<Ended, io::Error> {
+        try!(self.poll_watchdog());
+
         loop {
             match mem::replace(&mut self.bot_state, BotState::Invalid) {
                 BotState::Invalid => {
@@ -92,7 +238,7 @@ impl<S: AsyncRead + AsyncWrite> Future for Bot<S> {
                     match self.sock.poll() {
                         Ok(Async::Ready(Some(s))) => {
                             self.bot_state = BotState::Start;
-                            self.handle_line(s);
+                            try!(self.handle_line(s));
                         },
                         Ok(Async::Ready(None)) => {
                             info!("end of input. finishing");
@@ -100,8 +246,15 @@ impl<S: AsyncRead + AsyncWrite> Future for Bot<S> {
                         },
                         Ok(Async::NotReady) => {
                             self.bot_state = BotState::Start;
+                            // anything this sends wakes the socket back up
+                            try!(self.poll_net_timer());
+                            try!(self.poll_scheduler());
                             return Ok(Async::NotReady);
                         },
+                        Err(ref e) if self.net.is_quitting() => {
+                            debug!("connection error after quitting: {}", e);
+                            self.bot_state = BotState::Finish;
+                        },
                         Err(e) => {
                             return Err(e);
                         },
@@ -110,18 +263,30 @@ impl<S: AsyncRead + AsyncWrite> Future for Bot<S> {
 
                 BotState::Finish => {
                     self.bot_state = BotState::Finish;
-                    return Ok(Async::Ready(()));
+                    if self.net.is_quitting() {
+                        return Ok(Async::Ready(Ended::Quit));
+                    }
+                    return Ok(Async::Ready(Ended::Disconnected));
                 },
             }
         }
     }
 }
 
+/// The framed connection to the server, along with the outgoing queues.
+/// Lines from `send` wait for a token from the flood control bucket, while
+/// lines from `send_urgent` are sent right away.
 struct Sock<S> {
     sock: Framed<S, IrcCodec>,
     sock_state: SockState,
+    urgent_buf: VecDeque<String>,
     out_buf: VecDeque<String>,
+    bucket: TokenBucket,
+    flood_timer: Option<Timeout>,
+    handle: Handle,
     parked: Option<task::Task>,
+    source_len: usize,
+    max_lines: Option<usize>,
 }
 
 enum SockState {
@@ -134,12 +299,18 @@ enum SockState {
 }
 
 impl<S: AsyncRead + AsyncWrite + Sized> Sock<S> {
-    fn new(sock: S) -> Sock<S> {
+    fn new(env: &Env, handle: Handle, sock: S) -> Sock<S> {
         Sock {
-            sock: sock.framed(IrcCodec),
+            sock: sock.framed(IrcCodec::from_env(env)),
             sock_state: SockState::Start,
+            urgent_buf: VecDeque::new(),
             out_buf: VecDeque::new(),
+            bucket: TokenBucket::from_env(env, time::Instant::now()),
+            flood_timer: None,
+            handle: handle,
             parked: None,
+            source_len: text::DEFAULT_SOURCE_LEN,
+            max_lines: env.conf_integer("irc.max_reply_lines").map(|n| n.max(1) as usize),
         }
     }
 }
@@ -149,12 +320,85 @@ impl<S> Sock<S> {
         self.out_buf.push_back(line);
         self.parked.take().map(|t| t.unpark());
     }
+
+    fn send_urgent(&mut self, line: String) {
+        self.urgent_buf.push_back(line);
+        self.parked.take().map(|t| t.unpark());
+    }
+
+    /// Whether there's a line that could be sent right now.
+    fn can_send(&mut self) -> bool {
+        !self.urgent_buf.is_empty() ||
+            (!self.out_buf.is_empty() && self.bucket.has_token(time::Instant::now()))
+    }
+
+    /// Takes the next line that's allowed to be sent, along with whether it
+    /// came from the urgent queue.
+    fn next_line(&mut self) -> Option<(String, bool)> {
+        let now = time::Instant::now();
+
+        if let Some(line) = self.urgent_buf.pop_front() {
+            self.bucket.take_anyway(now);
+            return Some((line, true));
+        }
+
+        if !self.out_buf.is_empty() && self.bucket.try_take(now) {
+            return self.out_buf.pop_front().map(|line| (line, false));
+        }
+
+        None
+    }
+
+    /// Waits for the flood control bucket to have a token, if there are lines
+    /// waiting for one. Ready means a queued line can be sent now.
+    fn poll_flood_timer(&mut self) -> Poll<(), io::Error> {
+        if self.out_buf.is_empty() {
+            self.flood_timer = None;
+            return Ok(Async::NotReady);
+        }
+
+        loop {
+            let now = time::Instant::now();
+            let at = self.bucket.next_token_at(now);
+
+            if at <= now {
+                self.flood_timer = None;
+                return Ok(Async::Ready(()));
+            }
+
+            if self.flood_timer.is_none() {
+                self.flood_timer = Some(try!(Timeout::new_at(at, &self.handle)));
+            }
+
+            match self.flood_timer.as_mut().map(|t| t.poll()) {
+                Some(Ok(Async::Ready(()))) => self.flood_timer = None,
+                Some(Err(e)) => return Err(e),
+                _ => return Ok(Async::NotReady),
+            }
+        }
+    }
 }
 
 impl<S> network::Output for Sock<S> {
     fn send(&mut self, line: String) {
         Sock::send(self, line);
     }
+
+    fn send_urgent(&mut self, line: String) {
+        Sock::send_urgent(self, line);
+    }
+
+    fn source_len(&self) -> usize {
+        self.source_len
+    }
+
+    fn set_source(&mut self, nick: &str, user: Option<&str>, host: Option<&str>) {
+        self.source_len = text::source_len(nick, user, host);
+    }
+
+    fn max_lines(&self) -> Option<usize> {
+        self.max_lines
+    }
 }
 
 impl<S: AsyncRead + AsyncWrite> Stream for Sock<S> {
@@ -170,10 +414,10 @@ impl<S: AsyncRead + AsyncWrite> Stream for Sock<S> {
                 },
 
                 SockState::Start => {
-                    if self.out_buf.is_empty() {
-                        self.sock_state = SockState::Receiving;
-                    } else {
+                    if self.can_send() {
                         self.sock_state = SockState::Sending;
+                    } else {
+                        self.sock_state = SockState::Receiving;
                     }
                 },
 
@@ -188,6 +432,9 @@ impl<S: AsyncRead + AsyncWrite> Stream for Sock<S> {
                         },
                         Ok(Async::NotReady) => {
                             self.sock_state = SockState::Start;
+                            if let Async::Ready(()) = try!(self.poll_flood_timer()) {
+                                continue;
+                            }
                             self.parked = Some(task::park());
                             return Ok(Async::NotReady);
                         },
@@ -198,13 +445,17 @@ impl<S: AsyncRead + AsyncWrite> Stream for Sock<S> {
                 },
 
                 SockState::Sending => {
-                    if let Some(line) = self.out_buf.pop_front() {
+                    if let Some((line, urgent)) = self.next_line() {
                         match self.sock.start_send(line) {
                             Ok(AsyncSink::Ready) => {
                                 self.sock_state = SockState::Sending;
                             },
                             Ok(AsyncSink::NotReady(line)) => {
-                                self.out_buf.push_front(line);
+                                if urgent {
+                                    self.urgent_buf.push_front(line);
+                                } else {
+                                    self.out_buf.push_front(line);
+                                }
                                 self.sock_state = SockState::Sending;
                                 self.parked = Some(task::park());
                                 return Ok(Async::NotReady);
@@ -242,7 +493,31 @@ impl<S: AsyncRead + AsyncWrite> Stream for Sock<S> {
     }
 }
 
-struct IrcCodec;
+struct IrcCodec {
+    /// The services we might send passwords to, like `NickServ`.
+    services: Vec<String>,
+}
+
+impl IrcCodec {
+    fn from_env(env: &Env) -> IrcCodec {
+        let mut services = vec!["NickServ".to_string()];
+        services.extend(env.conf_str("irc.regain.service").map(|s| s.to_string()));
+        IrcCodec { services: services }
+    }
+
+    /// Hides credentials in an outgoing line so that it can be logged.
+    fn redact<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut words = line.splitn(3, ' ');
+        match (words.next(), words.next()) {
+            (Some(verb), Some(_)) if verb.eq_ignore_ascii_case("AUTHENTICATE") =>
+                Cow::Borrowed("AUTHENTICATE ***"),
+            (Some(verb), Some(target)) if verb.eq_ignore_ascii_case("PRIVMSG") &&
+                    self.services.iter().any(|s| s.eq_ignore_ascii_case(target)) =>
+                Cow::Owned(format!("PRIVMSG {} ***", target)),
+            _ => Cow::Borrowed(line),
+        }
+    }
+}
 
 impl Decoder for IrcCodec {
     type Item = String;
@@ -285,7 +560,7 @@ impl Encoder for IrcCodec {
     type Error = io::Error;
 
     fn encode(&mut self, item: String, dst: &mut BytesMut) -> Result<(), io::Error> {
-        debug!(" <-- {}", item);
+        debug!(" <-- {}", self.redact(&item));
         dst.put(item);
         dst.put(b'\r');
         dst.put(b'\n');
@@ -293,6 +568,9 @@ impl Encoder for IrcCodec {
     }
 }
 
+/// Runs the bot until it can't (or shouldn't) reconnect. Each connection gets
+/// a fresh `Bot` and `network::Network`, and so registers and joins channels
+/// again, but anything created here outlives individual connections.
 pub fn run(env: Env, mut reactor: Core) -> io::Result<()> {
     let handle = reactor.handle();
 
@@ -300,29 +578,151 @@ pub fn run(env: Env, mut reactor: Core) -> io::Result<()> {
     info!("sleeping for {} seconds before attempting connection", wait);
     thread::sleep(time::Duration::new(wait, 0));
 
-    let connect = try!(start_connect(env.clone(), reactor.handle()));
+    let storage = try!(Storage::open(&env).map_err(|e| io::Error::new(
+        io::ErrorKind::Other,
+        format!("couldn't open storage: {}", e)
+    )));
+    let registry = try!(commands::load(&env, &storage).map_err(|e| io::Error::new(
+        io::ErrorKind::Other,
+        format!("couldn't set up commands: {}", e)
+    )));
+    let registry = Rc::new(registry);
+
+    let mut backoff = Backoff::from_env(&env);
+
+    loop {
+        let started = time::Instant::now();
+
+        let result = run_connection(env.clone(), registry.clone(), &mut reactor);
+        match result {
+            Ok(Ended::Quit) => {
+                info!("quit from the server, not reconnecting");
+                return Ok(());
+            },
+            Ok(Ended::Disconnected) => info!("disconnected from server"),
+            Err(ref e) => error!("connection failed: {}", e),
+        }
+
+        if !backoff.enabled() {
+            return result.map(|_| ());
+        }
+
+        backoff.connection_lasted(started.elapsed());
+        let delay = backoff.next_delay();
+        info!("reconnecting in {}.{:03} seconds",
+            delay.as_secs(), delay.subsec_nanos() / 1_000_000);
+        try!(reactor.run(try!(Timeout::new(delay, &handle))));
+    }
+}
+
+/// Connects to the server and runs a single session of the bot to completion.
+fn run_connection(env: Env, registry: Rc<CommandRegistry>, reactor: &mut Core)
+-> io::Result<Ended> {
+    let handle = reactor.handle();
+    let connect = try!(start_connect(env.clone(), handle.clone()));
+
+    if !use_tls(&env) {
+        let bot = connect.and_then(move |sock| {
+            info!("connected! starting the bot...");
+            Bot::new(env, registry, handle, sock)
+        });
+
+        return reactor.run(bot);
+    }
+
+    let tls = try!(tls_connector(&env));
+    let domain = try!(conf_host(&env)).to_string();
 
     let bot = connect.and_then(move |sock| {
-        info!("connected! starting the bot...");
-        Bot::new(env, handle, sock)
+        info!("connected! starting TLS handshake with {}", domain);
+        tls.connect(&domain, sock).map_err(tls_error)
+    }).and_then(move |sock| {
+        info!("TLS handshake complete! starting the bot...");
+        Bot::new(env, registry, handle, sock)
     });
 
     reactor.run(bot)
 }
 
-fn start_connect(env: Env, handle: Handle) -> io::Result<TcpStreamNew> {
-    let host = match env.conf_str("irc.host") {
-        Some(host) => host,
-        None => {
+fn use_tls(env: &Env) -> bool {
+    env.conf_bool("irc.tls").unwrap_or(false)
+}
+
+fn conf_host(env: &Env) -> io::Result<&str> {
+    env.conf_str("irc.host").ok_or_else(|| io::Error::new(
+        io::ErrorKind::Other,
+        "config value irc.host wrong type or missing"
+    ))
+}
+
+fn tls_error(e: native_tls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    try!(try!(fs::File::open(path)).read_to_end(&mut data));
+    Ok(data)
+}
+
+/// Builds the TLS connector described by the `irc.tls_*` settings:
+///
+///   * `irc.tls_ca_file`: a PEM bundle of certificates to trust in addition
+///     to the system roots.
+///   * `irc.tls_client_cert` and `irc.tls_client_key`: a PEM certificate and
+///     PKCS#8 private key to present to the server, for CertFP and SASL
+///     EXTERNAL.
+///   * `irc.tls_insecure`: skips certificate and hostname verification
+///     entirely. Only ever use this for testing against a local server.
+fn tls_connector(env: &Env) -> io::Result<TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+
+    if let Some(path) = env.conf_str("irc.tls_ca_file") {
+        let pem = try!(read_file(path));
+        let certs = try!(native_tls::Certificate::stack_from_pem(&pem[..])
+            .map_err(tls_error));
+        debug!("trusting {} certificate(s) from {}", certs.len(), path);
+        for cert in certs {
+            builder.add_root_certificate(cert);
+        }
+    }
+
+    match (env.conf_str("irc.tls_client_cert"), env.conf_str("irc.tls_client_key")) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = try!(read_file(cert_path));
+            let key = try!(read_file(key_path));
+            let identity = try!(native_tls::Identity::from_pkcs8(&cert[..], &key[..])
+                .map_err(tls_error));
+            debug!("using client certificate {}", cert_path);
+            builder.identity(identity);
+        },
+        (None, None) => { },
+        _ => {
             return Err(io::Error::new(
                 io::ErrorKind::Other,
-                "config value irc.host wrong type or missing"
+                "irc.tls_client_cert and irc.tls_client_key must be set together"
             ));
         },
-    };
+    }
+
+    if env.conf_bool("irc.tls_insecure").unwrap_or(false) {
+        warn!("TLS certificate verification is disabled!");
+        builder.danger_accept_invalid_certs(true);
+        builder.danger_accept_invalid_hostnames(true);
+    }
+
+    builder.build().map(TlsConnector::from).map_err(tls_error)
+}
+
+fn start_connect(env: Env, handle: Handle) -> io::Result<TcpStreamNew> {
+    let host = try!(conf_host(&env));
 
     let port = match env.conf_integer("irc.port") {
         Some(port) => port as u16,
+        None if use_tls(&env) => {
+            debug!("using default TLS port of 6697");
+            6697
+        },
         None => {
             debug!("using default port of 6667");
             6667
@@ -345,3 +745,144 @@ fn start_connect(env: Env, handle: Handle) -> io::Result<TcpStreamNew> {
 
     Ok(TcpStream::connect(&addr, &handle))
 }
+
+#[test]
+fn bot_redacts_credentials() {
+    let codec = IrcCodec::from_env(&::environment::from_str(r##"
+        [irc.regain]
+        method = "ghost"
+        service = "NickOp"
+    "##));
+
+    assert_eq!(codec.redact("AUTHENTICATE bWlhdQBtaWF1AGh1bnRlcjI="), "AUTHENTICATE ***");
+    assert_eq!(codec.redact("PRIVMSG NickOp :GHOST miau hunter2"), "PRIVMSG NickOp ***");
+    assert_eq!(codec.redact("PRIVMSG nickop :REGAIN miau hunter2"), "PRIVMSG nickop ***");
+    assert_eq!(codec.redact("PRIVMSG NickServ :GHOST miau hunter2"), "PRIVMSG NickServ ***");
+    assert_eq!(codec.redact("PRIVMSG #miau-dev :hunter2"), "PRIVMSG #miau-dev :hunter2");
+    assert_eq!(codec.redact("AUTHENTICATE"), "AUTHENTICATE");
+}
+
+#[cfg(test)]
+fn testdata(name: &str) -> String {
+    format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[test]
+fn bot_tls_config_errors() {
+    let cert_only = format!("[irc]\ntls_client_cert = {:?}", testdata("localhost.crt"));
+    let missing_ca = format!("[irc]\ntls_ca_file = {:?}", testdata("missing.crt"));
+
+    assert!(tls_connector(&::environment::from_str(&cert_only)).is_err());
+    assert!(tls_connector(&::environment::from_str(&missing_ca)).is_err());
+}
+
+/// Makes a TLS connection to a local listener using the self-signed
+/// certificate in `testdata`, with the client set up from `config`.
+#[cfg(test)]
+fn tls_handshake(config: &str) -> io::Result<Vec<u8>> {
+    use tokio_core::net::TcpListener;
+    use tokio_io::io::read_exact;
+    use tokio_io::io::write_all;
+    use tokio_tls::TlsAcceptor;
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let cert = read_file(&testdata("localhost.crt")).unwrap();
+    let key = read_file(&testdata("localhost.key")).unwrap();
+    let identity = native_tls::Identity::from_pkcs8(&cert[..], &key[..]).unwrap();
+    let acceptor = TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = listener.incoming().into_future().map_err(|(e, _)| e)
+        .and_then(move |(conn, _)| {
+            let (sock, _) = conn.unwrap();
+            acceptor.accept(sock).map_err(tls_error)
+        })
+        .and_then(|sock| write_all(sock, b"PING :miau\r\n"));
+
+    let tls = try!(tls_connector(&::environment::from_str(config)));
+    let client = TcpStream::connect(&addr, &handle)
+        .and_then(move |sock| tls.connect("localhost", sock).map_err(tls_error))
+        .and_then(|sock| read_exact(sock, vec![0; 12]))
+        .map(|(_, line)| line);
+
+    // the server's side fails too if the client gives up on the handshake
+    core.run(client.join(server.then(|_| Ok(())))).map(|(line, ())| line)
+}
+
+#[test]
+fn bot_tls_handshake() {
+    let trusted = format!("[irc]\ntls_ca_file = {:?}", testdata("localhost.crt"));
+    assert_eq!(tls_handshake(&trusted).unwrap(), b"PING :miau\r\n");
+    assert_eq!(tls_handshake("[irc]\ntls_insecure = true").unwrap(), b"PING :miau\r\n");
+    assert!(tls_handshake("[irc]").is_err());
+}
+
+/// A connection that never has anything to read, and keeps whatever is
+/// written to it.
+#[cfg(test)]
+struct TestConn(Rc<::std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Read for TestConn {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::WouldBlock, "nothing to read"))
+    }
+}
+
+#[cfg(test)]
+impl Write for TestConn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl AsyncRead for TestConn { }
+
+#[cfg(test)]
+impl AsyncWrite for TestConn {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[test]
+fn bot_urgent_lines_skip_the_queue() {
+    use futures::future;
+
+    let mut core = Core::new().unwrap();
+    let written = Rc::new(::std::cell::RefCell::new(Vec::new()));
+    let env = ::environment::from_str("[irc.flood]\nburst = 3\nrate = 0.01");
+    let mut sock = Sock::new(&env, core.handle(), TestConn(written.clone()));
+
+    let mut poll = |sock: &mut Sock<TestConn>| {
+        core.run(future::poll_fn(|| {
+            assert!(!sock.poll().unwrap().is_ready());
+            Ok::<_, io::Error>(Async::Ready(()))
+        })).unwrap();
+        let lines = str::from_utf8(&written.borrow()).unwrap().lines().map(String::from)
+            .collect::<Vec<_>>();
+        written.borrow_mut().clear();
+        lines
+    };
+
+    for i in 0..10 {
+        sock.PRIVMSG("#miau-dev", &format!("meow {}", i));
+    }
+    assert_eq!(poll(&mut sock).len(), 3);
+
+    // the bucket is empty and seven lines are waiting, but these go anyway
+    sock.PONG("irc.test");
+    sock.send_message(&irc::Message::new("CAP", vec!["END"]));
+    sock.PRIVMSG("#miau-dev", "one more");
+    assert_eq!(poll(&mut sock), vec!["PONG irc.test", "CAP END"]);
+    assert_eq!(sock.out_buf.len(), 8);
+}
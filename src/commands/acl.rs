@@ -0,0 +1,280 @@
+//! Who's allowed to run which commands.
+//!
+//! Everyone has one of four levels, from lowest to highest: `anyone`, `op`,
+//! `admin` and `owner`. Owners and admins are listed in the config, either by
+//! `nick!user@host` globs or by services account, and anyone with channel
+//! operator status is an `op` in that channel:
+//!
+//! ```toml
+//! [acl]
+//! owners = [ "account:aji" ]
+//! admins = [ "*!*@staff.example.net", "account:rarity" ]
+//!
+//! [acl.commands]
+//! say = "op"
+//!
+//! [acl.channels."#miau-dev"]
+//! admins = [ "account:applejack" ]
+//! commands = { say = "anyone" }
+//! ```
+//!
+//! Each command says what level it needs, which `acl.commands` can change,
+//! and a channel's own `commands` can change again for that channel. Admins
+//! listed for a channel are only admins there.
+
+use std::collections::BTreeMap;
+
+use commands::Command;
+use commands::Context;
+use environment::Env;
+use irc::casemap::CaseMapping;
+
+/// How much someone is trusted.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Level {
+    Anyone,
+    Op,
+    Admin,
+    Owner,
+}
+
+impl Level {
+    /// Looks up a level by the name used in the config.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match &name.to_ascii_lowercase()[..] {
+            "anyone" => Some(Level::Anyone),
+            "op" => Some(Level::Op),
+            "admin" => Some(Level::Admin),
+            "owner" => Some(Level::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Anyone => "anyone",
+            Level::Op => "op",
+            Level::Admin => "admin",
+            Level::Owner => "owner",
+        }
+    }
+}
+
+/// A way of recognizing someone: a `nick!user@host` glob, or `account:name`.
+enum Mask {
+    Host(String),
+    Account(String),
+}
+
+pub struct Acl {
+    owners: Vec<Mask>,
+    admins: Vec<Mask>,
+    commands: BTreeMap<String, Level>,
+    channels: Vec<(String, ChannelAcl)>,
+}
+
+struct ChannelAcl {
+    admins: Vec<Mask>,
+    commands: BTreeMap<String, Level>,
+}
+
+fn masks_in(value: Option<&::toml::Value>) -> Vec<Mask> {
+    let masks = match value.and_then(|v| v.as_array()) {
+        Some(masks) => masks,
+        None => return Vec::new(),
+    };
+
+    masks.iter().filter_map(|m| m.as_str()).map(|m| {
+        if m.starts_with("account:") {
+            Mask::Account(m["account:".len()..].to_string())
+        } else {
+            Mask::Host(m.to_string())
+        }
+    }).collect()
+}
+
+fn levels_in(value: Option<&::toml::Value>) -> BTreeMap<String, Level> {
+    let table = match value.and_then(|v| v.as_table()) {
+        Some(table) => table,
+        None => return BTreeMap::new(),
+    };
+
+    table.iter().filter_map(|(cmd, level)| {
+        match level.as_str().and_then(Level::from_name) {
+            Some(level) => Some((cmd.to_ascii_lowercase(), level)),
+            None => {
+                warn!("ignoring access level for {}, it should be one of anyone, op, admin \
+                       or owner", cmd);
+                None
+            },
+        }
+    }).collect()
+}
+
+impl Acl {
+    pub fn from_env(env: &Env) -> Acl {
+        let channels = env.conf_table("acl.channels").map(|t| {
+            t.iter().map(|(chan, acl)| (chan.clone(), ChannelAcl {
+                admins: masks_in(acl.get("admins")),
+                commands: levels_in(acl.get("commands")),
+            })).collect()
+        });
+
+        Acl {
+            owners: masks_in(env.conf("acl.owners")),
+            admins: masks_in(env.conf("acl.admins")),
+            commands: levels_in(env.conf("acl.commands")),
+            channels: channels.unwrap_or_else(Vec::new),
+        }
+    }
+
+    fn channel(&self, chan: Option<&str>, casemap: CaseMapping) -> Option<&ChannelAcl> {
+        chan.and_then(|chan| {
+            self.channels.iter().find(|&&(ref name, _)| casemap.equals(name, chan))
+        }).map(|&(_, ref acl)| acl)
+    }
+
+    /// The level needed to run a command wherever `ctx` is.
+    pub fn required(&self, command: &Command, ctx: &Context) -> Level {
+        let name = command.name().to_ascii_lowercase();
+        let chan = self.channel(ctx.channel_name(), casemap_of(ctx));
+
+        chan.and_then(|c| c.commands.get(&name))
+            .or_else(|| self.commands.get(&name))
+            .cloned()
+            .unwrap_or_else(|| command.level())
+    }
+
+    /// The level of whoever issued the command in `ctx`.
+    pub fn level_of(&self, ctx: &Context) -> Level {
+        let casemap = casemap_of(ctx);
+        let mask = ctx.sender_mask();
+        let account = ctx.sender_account();
+        let matches = |masks: &[Mask]| masks.iter().any(|m| match *m {
+            Mask::Host(ref glob) => mask.as_ref().map(|s| glob_match(casemap, glob, s)),
+            Mask::Account(ref name) => account.map(|a| casemap.equals(name, a)),
+        }.unwrap_or(false));
+        let chan = self.channel(ctx.channel_name(), casemap);
+
+        if matches(&self.owners) {
+            Level::Owner
+        } else if matches(&self.admins) || chan.map(|c| matches(&c.admins)).unwrap_or(false) {
+            Level::Admin
+        } else if ctx.sender_is_op() {
+            Level::Op
+        } else {
+            Level::Anyone
+        }
+    }
+
+    /// Whether whoever issued the command in `ctx` can run `command`.
+    pub fn permits(&self, command: &Command, ctx: &Context) -> bool {
+        self.level_of(ctx) >= self.required(command, ctx)
+    }
+}
+
+fn casemap_of(ctx: &Context) -> CaseMapping {
+    ctx.network().map(|n| n.server_info().casemapping()).unwrap_or_default()
+}
+
+/// Matches a glob, where `*` is any number of characters and `?` is exactly
+/// one, ignoring case.
+fn glob_match(casemap: CaseMapping, glob: &str, s: &str) -> bool {
+    let p: Vec<char> = casemap.fold(glob).chars().collect();
+    let s: Vec<char> = casemap.fold(s).chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    // where the last * was, and how much of s it's taken so far
+    let mut star = None;
+
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((star_pi, star_si)) = star {
+            // let the * take one more character and try again
+            pi = star_pi + 1;
+            si = star_si + 1;
+            star = Some((star_pi, star_si + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[test]
+fn acl_globs() {
+    let map = CaseMapping::Rfc1459;
+    assert!(glob_match(map, "*!*@*.example.net", "aji!a@staff.example.net"));
+    assert!(glob_match(map, "AJI!?@*", "aji!a@host"));
+    assert!(glob_match(map, "[aji]!*@*", "{AJI}!a@host"));
+    assert!(!glob_match(map, "*!*@*.example.net", "aji!a@example.net"));
+    assert!(!glob_match(map, "aji!?@*", "aji!ab@host"));
+    assert!(glob_match(map, "*", ""));
+    assert!(glob_match(map, "a*b*c", "aXbYbZc"));
+}
+
+#[test]
+fn acl_levels() {
+    assert!(Level::Owner > Level::Admin && Level::Admin > Level::Op && Level::Op > Level::Anyone);
+    assert_eq!(Level::from_name("Admin"), Some(Level::Admin));
+    assert_eq!(Level::from_name("root"), None);
+    assert_eq!(Level::Op.name(), "op");
+}
+
+#[test]
+fn acl_enforced() {
+    use commands::replies_from;
+    use commands::CommandRegistry;
+    use commands::registry::TestPlugin;
+
+    let config = r##"
+        [irc]
+        nick = "miau"
+        channels = []
+
+        [acl]
+        owners = ["account:aji"]
+        admins = ["*!*@staff.example"]
+
+        [acl.commands]
+        echo = "admin"
+
+        [acl.channels."#ops"]
+        commands = { echo = "op" }
+
+        [acl.channels."#friends"]
+        admins = ["rarity!*@*"]
+    "##;
+
+    let mut registry = CommandRegistry::from_env(&::environment::from_str(config));
+    registry.register(Box::new(TestPlugin("test", vec![("echo", &[])]))).unwrap();
+
+    let out = replies_from(&registry, config, &[
+        ":[miau]!m@h JOIN #ops",
+        ":irc.test 353 [miau] = #ops :[miau] @rarity pinkie",
+        ":aji!a@h JOIN #ops",
+        ":aji!a@h ACCOUNT aji",
+        ":pinkie!p@h PRIVMSG #miau-dev :!echo 1",
+        ":pinkie!p@staff.example PRIVMSG #miau-dev :!echo 2",
+        ":aji!a@h PRIVMSG #miau-dev :!echo 3",
+        ":pinkie!p@h PRIVMSG #ops :!echo 4",
+        ":rarity!r@h PRIVMSG #ops :!echo 5",
+        ":rarity!r@h PRIVMSG #friends :!echo 6",
+        ":rarity!r@h PRIVMSG #miau-dev :!echo 7",
+    ]);
+
+    assert_eq!(&out[1..], &[
+        "PRIVMSG #miau-dev :pinkie: you need admin access to use echo",
+        "PRIVMSG #miau-dev :pinkie: echo 2",
+        "PRIVMSG #miau-dev :aji: echo 3",
+        "PRIVMSG #ops :pinkie: you need op access to use echo",
+        "PRIVMSG #ops :rarity: echo 5",
+        "PRIVMSG #friends :rarity: echo 6",
+        "PRIVMSG #miau-dev :rarity: you need admin access to use echo",
+    ]);
+}
@@ -0,0 +1,169 @@
+//! Deciding whether a message is meant for us, and if so, which part of it is
+//! the command.
+//!
+//! A message in a channel is a command if it's addressed to us, like
+//! `miau: version`, `miau, version` or `@miau version`, or if it starts with
+//! one of the command prefixes, like `!version`. Private messages are always
+//! commands. The prefixes can be changed, and channels can have their own:
+//!
+//! ```toml
+//! [commands]
+//! prefixes = [ "!", "miau " ]
+//!
+//! [commands.channels."#shared"]
+//! prefixes = [ "~" ]
+//!
+//! [commands.channels."#busy"]
+//! addressed_only = true
+//! ```
+//!
+//! With `addressed_only`, prefixes are ignored, so that bots sharing a
+//! channel don't all answer `!help`. It can be set for every channel in
+//! `[commands]` too.
+
+use environment::Env;
+use irc::casemap::CaseMapping;
+
+pub struct Addressing {
+    prefixes: Vec<String>,
+    addressed_only: bool,
+    channels: Vec<(String, ChannelRules)>,
+}
+
+struct ChannelRules {
+    prefixes: Option<Vec<String>>,
+    addressed_only: Option<bool>,
+}
+
+fn prefixes_in(value: Option<&::toml::Value>) -> Option<Vec<String>> {
+    value.and_then(|v| v.as_array()).map(|a| {
+        let mut prefixes: Vec<String> = a.iter()
+            .filter_map(|p| p.as_str())
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string())
+            .collect();
+        // so that "!!" is tried before "!"
+        prefixes.sort_by(|a, b| b.len().cmp(&a.len()));
+        prefixes
+    })
+}
+
+impl Addressing {
+    pub fn from_env(env: &Env) -> Addressing {
+        let channels = env.conf_table("commands.channels").map(|t| {
+            t.iter().map(|(chan, rules)| (chan.clone(), ChannelRules {
+                prefixes: prefixes_in(rules.get("prefixes")),
+                addressed_only: rules.get("addressed_only").and_then(|v| v.as_bool()),
+            })).collect()
+        });
+
+        Addressing {
+            prefixes: prefixes_in(env.conf("commands.prefixes"))
+                .unwrap_or_else(|| vec!["!".to_string()]),
+            addressed_only: env.conf_bool("commands.addressed_only").unwrap_or(false),
+            channels: channels.unwrap_or_else(Vec::new),
+        }
+    }
+
+    /// Finds the command in a message, returning the command's name and its
+    /// arguments. `chan` is the channel the message was sent to, or `None` if
+    /// it was sent to us privately.
+    pub fn command<'a>(&self, text: &'a str, me: &str, chan: Option<&str>,
+                       casemap: CaseMapping) -> Option<(&'a str, &'a str)> {
+        let rules = chan.and_then(|chan| {
+            self.channels.iter().find(|&&(ref name, _)| casemap.equals(name, chan))
+        }).map(|&(_, ref rules)| rules);
+
+        let prefixes = rules.and_then(|r| r.prefixes.as_ref()).unwrap_or(&self.prefixes);
+        let addressed_only = rules.and_then(|r| r.addressed_only).unwrap_or(self.addressed_only);
+
+        let spec = match strip_nick(text, me, casemap) {
+            Some(rest) => rest,
+            None if chan.is_some() && addressed_only => return None,
+            None => match prefixes.iter().find(|p| text.starts_with(&p[..])) {
+                // "! nice" is just someone being excited
+                Some(p) if text[p.len()..].starts_with(char::is_whitespace) => return None,
+                Some(p) => &text[p.len()..],
+                None if chan.is_none() => text,
+                None => return None,
+            },
+        };
+
+        let spec = spec.trim_left();
+        let end = spec.find(char::is_whitespace).unwrap_or(spec.len());
+        if end == 0 {
+            return None;
+        }
+        Some((&spec[..end], spec[end..].trim_left()))
+    }
+}
+
+/// Strips our nick off the front of a message that's addressed to us, as in
+/// `miau: hi`, `miau,hi`, `miau hi` or `@miau hi`.
+fn strip_nick<'a>(text: &'a str, me: &str, casemap: CaseMapping) -> Option<&'a str> {
+    let text = if text.starts_with('@') { &text[1..] } else { text };
+    let end = match text.find(|c: char| c.is_whitespace() || c == ':' || c == ',') {
+        Some(end) => end,
+        None => return None,
+    };
+
+    if !casemap.equals(&text[..end], me) {
+        return None;
+    }
+
+    let rest = &text[end..];
+    if rest.starts_with(':') || rest.starts_with(',') {
+        Some(&rest[1..])
+    } else {
+        Some(rest)
+    }
+}
+
+#[cfg(test)]
+fn addressing(config: &str) -> Addressing {
+    Addressing::from_env(&::environment::from_str(config))
+}
+
+#[test]
+fn addressing_defaults() {
+    let a = addressing("");
+    let map = CaseMapping::Rfc1459;
+    let chan = Some("#miau-dev");
+
+    assert_eq!(a.command("!version", "[miau]", chan, map), Some(("version", "")));
+    assert_eq!(a.command("{MIAU}: help  me ", "[miau]", chan, map), Some(("help", "me ")));
+    assert_eq!(a.command("[miau],help", "[miau]", chan, map), Some(("help", "")));
+    assert_eq!(a.command("[miau]:help", "[miau]", chan, map), Some(("help", "")));
+    assert_eq!(a.command("@[miau] help", "[miau]", chan, map), Some(("help", "")));
+    assert_eq!(a.command("[miau] help", "[miau]", chan, map), Some(("help", "")));
+    assert_eq!(a.command("[miau]x help", "[miau]", chan, map), None);
+    assert_eq!(a.command("[miau]:", "[miau]", chan, map), None);
+    assert_eq!(a.command("! version", "[miau]", chan, map), None);
+    assert_eq!(a.command("version", "[miau]", chan, map), None);
+    assert_eq!(a.command("version", "[miau]", None, map), Some(("version", "")));
+    assert_eq!(a.command("!version", "[miau]", None, map), Some(("version", "")));
+}
+
+#[test]
+fn addressing_configured() {
+    let a = addressing(r##"
+        [commands]
+        prefixes = ["!", "!!", "meow "]
+
+        [commands.channels."#Shared"]
+        prefixes = ["~"]
+
+        [commands.channels."#busy"]
+        addressed_only = true
+    "##);
+    let map = CaseMapping::Rfc1459;
+
+    assert_eq!(a.command("!!version", "miau", Some("#a"), map), Some(("version", "")));
+    assert_eq!(a.command("meow help", "miau", Some("#a"), map), Some(("help", "")));
+    assert_eq!(a.command("~help", "miau", Some("#a"), map), None);
+    assert_eq!(a.command("~help", "miau", Some("#shared"), map), Some(("help", "")));
+    assert_eq!(a.command("!help", "miau", Some("#shared"), map), None);
+    assert_eq!(a.command("!help", "miau", Some("#busy"), map), None);
+    assert_eq!(a.command("miau: help", "miau", Some("#busy"), map), Some(("help", "")));
+    assert_eq!(a.command("help", "miau", None, map), Some(("help", "")));
+}
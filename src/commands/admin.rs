@@ -0,0 +1,273 @@
+//! Commands for running the bot from IRC, for owners and admins.
+
+use commands::Args;
+use commands::Command;
+use commands::Context;
+use commands::Kind;
+use commands::Level;
+use commands::Plugin;
+use commands::Spec;
+use irc::Message;
+
+pub struct Admin;
+
+impl Plugin for Admin {
+    fn name(&self) -> &str {
+        "admin"
+    }
+
+    fn commands(&self) -> Vec<Box<Command>> {
+        vec![
+            Box::new(Join),
+            Box::new(Part),
+            Box::new(Nick),
+            Box::new(Quit),
+            Box::new(Raw),
+            Box::new(Say),
+        ]
+    }
+}
+
+struct Join;
+
+impl Command for Join {
+    fn name(&self) -> &str {
+        "join"
+    }
+
+    fn help(&self) -> &str {
+        "makes the bot join a channel"
+    }
+
+    fn level(&self) -> Level {
+        Level::Admin
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().required("channel", Kind::Channel).optional("key", Kind::Word))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let chan = args.get_str("channel").unwrap_or("");
+        if let Some((_, out)) = ctx.connection() {
+            match args.get_str("key") {
+                Some(key) => out.send_message(&Message::new("JOIN", vec![chan, key])),
+                None => out.JOIN(chan),
+            }
+        }
+    }
+}
+
+struct Part;
+
+impl Command for Part {
+    fn name(&self) -> &str {
+        "part"
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &["leave"]
+    }
+
+    fn help(&self) -> &str {
+        "makes the bot leave a channel, or the one this is used in"
+    }
+
+    fn level(&self) -> Level {
+        Level::Admin
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().optional("channel", Kind::Channel).optional("reason", Kind::Rest))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let chan = match args.get_str("channel").or(ctx.channel_name()) {
+            Some(chan) => chan.to_string(),
+            None => return ctx.reply_error("which channel?"),
+        };
+        if let Some((_, out)) = ctx.connection() {
+            out.PART(&chan, args.get_str("reason"));
+        }
+    }
+}
+
+struct Nick;
+
+impl Command for Nick {
+    fn name(&self) -> &str {
+        "nick"
+    }
+
+    fn help(&self) -> &str {
+        "changes the bot's nick until it reconnects"
+    }
+
+    fn level(&self) -> Level {
+        Level::Admin
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().required("nick", Kind::Nick))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let nick = args.get_str("nick").unwrap_or("");
+        if let Some((net, out)) = ctx.connection() {
+            net.change_nick(out, nick);
+        }
+    }
+}
+
+struct Quit;
+
+impl Command for Quit {
+    fn name(&self) -> &str {
+        "quit"
+    }
+
+    fn help(&self) -> &str {
+        "disconnects the bot for good"
+    }
+
+    fn level(&self) -> Level {
+        Level::Owner
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().optional("reason", Kind::Rest))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        if let Some((net, out)) = ctx.connection() {
+            net.quit(out, args.get_str("reason"));
+        }
+    }
+}
+
+struct Raw;
+
+impl Command for Raw {
+    fn name(&self) -> &str {
+        "raw"
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &["quote"]
+    }
+
+    fn help(&self) -> &str {
+        "sends a line to the server as it is"
+    }
+
+    fn level(&self) -> Level {
+        Level::Owner
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().required("line", Kind::Rest))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let line = args.get_str("line").unwrap_or("");
+        let m = match Message::parse(line) {
+            Ok(m) => m,
+            Err(e) => return ctx.reply_error(&format!("that's not a valid line: {}", e)),
+        };
+        if let Some((net, out)) = ctx.connection() {
+            // so that the disconnect is known to be on purpose
+            if m.verb.eq_ignore_ascii_case("QUIT") {
+                return net.quit(out, m.args.get(0).cloned());
+            }
+            out.send_message(&m);
+        }
+    }
+}
+
+struct Say;
+
+impl Command for Say {
+    fn name(&self) -> &str {
+        "say"
+    }
+
+    fn help(&self) -> &str {
+        "sends a message to a channel or user as the bot"
+    }
+
+    fn level(&self) -> Level {
+        Level::Admin
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().required("target", Kind::Word).required("message", Kind::Rest))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let target = args.get_str("target").unwrap_or("");
+        let message = args.get_str("message").unwrap_or("");
+        if let Some((_, out)) = ctx.connection() {
+            out.PRIVMSG(target, message);
+        }
+    }
+}
+
+#[test]
+fn admin_commands() {
+    use commands::handle_message;
+    use commands::load;
+    use commands::replies_from;
+    use irc::OwnedMessage;
+    use network::Network;
+    use storage::Storage;
+
+    let config = r##"
+        [irc]
+        nick = "miau"
+        channels = []
+
+        [acl]
+        owners = ["aji!*@*"]
+        admins = ["rarity!*@*"]
+    "##;
+    let registry = load(&::environment::from_str(config), &Storage::memory()).unwrap();
+
+    let out = replies_from(&registry, config, &[
+        ":pinkie!p@h PRIVMSG #miau-dev :!join #party",
+        ":rarity!r@h PRIVMSG #miau-dev :!join #boutique hunter2",
+        ":rarity!r@h PRIVMSG #miau-dev :!part",
+        ":rarity!r@h PRIVMSG [miau] :part #boutique see you",
+        ":rarity!r@h PRIVMSG [miau] :part",
+        ":rarity!r@h PRIVMSG #miau-dev :!say #boutique hello  there",
+        ":rarity!r@h PRIVMSG #miau-dev :!raw PRIVMSG aji :hi",
+        ":aji!a@h PRIVMSG #miau-dev :!raw PRIVMSG aji :hi",
+        ":aji!a@h PRIVMSG #miau-dev :!raw :",
+        ":rarity!r@h PRIVMSG #miau-dev :!nick meow",
+        ":aji!a@h PRIVMSG #miau-dev :!quit bye now",
+    ]);
+
+    assert_eq!(out, vec![
+        "PRIVMSG #miau-dev :pinkie: you need admin access to use join",
+        "JOIN #boutique hunter2",
+        "PART #miau-dev",
+        "PART #boutique :see you",
+        "NOTICE rarity :which channel?",
+        "PRIVMSG #boutique :hello  there",
+        "PRIVMSG #miau-dev :rarity: you need owner access to use raw",
+        "PRIVMSG aji hi",
+        "PRIVMSG #miau-dev :aji: that's not a valid line: invalid source",
+        "NICK meow",
+        "QUIT :bye now",
+    ]);
+
+    // a raw QUIT is a clean quit too, and shouldn't be followed by a reconnect
+    let mut out = Vec::new();
+    let mut net = Network::register(::environment::from_str(config), &mut out);
+    for line in &[":irc.test 001 [miau] :hi", ":aji!a@h PRIVMSG #miau-dev :!raw quit :bye"] {
+        let m = OwnedMessage::parse(line).unwrap();
+        net.handle_message(&mut out, &m).unwrap();
+        handle_message(&registry, &mut net, &mut out, &m);
+    }
+    assert_eq!(out.last().map(|l| &l[..]), Some("QUIT bye"));
+    assert!(net.is_quitting());
+}
@@ -0,0 +1,530 @@
+//! The arguments a command was given, and checking them against what the
+//! command expects.
+//!
+//! Arguments are split into words on whitespace, except inside double quotes,
+//! so `"hello world"` is one word. A backslash inside quotes takes the next
+//! character literally. Commands that describe their arguments with a
+//! [`Spec`](struct.Spec.html) get them checked and converted before they run,
+//! and whoever ran the command gets told what's wrong if that fails.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+
+use network::ServerInfo;
+
+/// What a command expects its arguments to look like.
+#[derive(Default)]
+pub struct Spec {
+    params: Vec<Param>,
+    flags: Vec<&'static str>,
+}
+
+struct Param {
+    name: &'static str,
+    kind: Kind,
+    optional: bool,
+}
+
+/// The kinds of values arguments can have.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Kind {
+    /// Any single word.
+    Word,
+    /// A whole number.
+    Int,
+    /// A length of time, like `90s`, `10m` or `1h30m`.
+    Duration,
+    /// Something that could be a nickname.
+    Nick,
+    /// A channel name, by the server's `CHANTYPES`.
+    Channel,
+    /// Everything from here to the end of the line, as it was written. This
+    /// only makes sense as the last argument.
+    Rest,
+}
+
+/// Used to signal that the arguments didn't match what the command expects.
+#[derive(Debug, PartialEq)]
+pub enum ArgError {
+    /// A required argument wasn't given.
+    Missing(&'static str),
+    /// An argument wasn't the right kind of value.
+    Invalid { name: &'static str, kind: Kind, value: String },
+    /// A `--flag` the command doesn't have.
+    UnknownFlag(String),
+    /// There were more arguments than the command takes.
+    TooMany(String),
+    /// A quoted argument was never closed.
+    UnclosedQuote,
+}
+
+/// The text after a command's name, split into words, along with the values
+/// of any arguments described by the command's `Spec`.
+pub struct Args<'a> {
+    text: &'a str,
+    words: Vec<String>,
+    values: Vec<(&'static str, Value)>,
+    flags: BTreeSet<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Value {
+    Text(String),
+    Int(i64),
+    Duration(Duration),
+}
+
+struct Token {
+    text: String,
+    start: usize,
+    quoted: bool,
+}
+
+impl Token {
+    fn is_flag(&self) -> bool {
+        !self.quoted && self.text.starts_with("--")
+    }
+}
+
+/// Splits arguments into words. With `strict`, an unclosed quote is an error,
+/// and otherwise it just runs to the end of the line.
+fn tokenize(s: &str, strict: bool) -> Result<Vec<Token>, ArgError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    loop {
+        while chars.peek().map(|&(_, c)| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+
+        let start = match chars.peek() {
+            Some(&(i, _)) => i,
+            None => break,
+        };
+
+        let mut text = String::new();
+        let quoted = chars.peek().map(|&(_, c)| c == '"').unwrap_or(false);
+
+        if quoted {
+            chars.next();
+            let mut closed = false;
+            while let Some((_, c)) = chars.next() {
+                match c {
+                    '"' => { closed = true; break; },
+                    '\\' => { chars.next().map(|(_, c)| text.push(c)); },
+                    _ => text.push(c),
+                }
+            }
+            if !closed && strict {
+                return Err(ArgError::UnclosedQuote);
+            }
+        }
+
+        while let Some(&(_, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            text.push(c);
+            chars.next();
+        }
+
+        tokens.push(Token { text: text, start: start, quoted: quoted });
+    }
+
+    Ok(tokens)
+}
+
+impl Spec {
+    pub fn new() -> Spec {
+        Spec::default()
+    }
+
+    /// Adds an argument that has to be given.
+    pub fn required(mut self, name: &'static str, kind: Kind) -> Spec {
+        self.params.push(Param { name: name, kind: kind, optional: false });
+        self
+    }
+
+    /// Adds an argument that can be left out. It's skipped if the word in
+    /// its place isn't the right kind, or is needed for a required argument
+    /// later on.
+    pub fn optional(mut self, name: &'static str, kind: Kind) -> Spec {
+        self.params.push(Param { name: name, kind: kind, optional: true });
+        self
+    }
+
+    /// Adds a `--flag`, which can appear anywhere before a `Rest` argument.
+    pub fn flag(mut self, name: &'static str) -> Spec {
+        self.flags.push(name);
+        self
+    }
+
+    /// Describes the arguments, like `[--all] <nick> [message...]`.
+    pub fn usage(&self) -> String {
+        let flags = self.flags.iter().map(|f| format!("[--{}]", f));
+        let params = self.params.iter().map(|p| {
+            let dots = if p.kind == Kind::Rest { "..." } else { "" };
+            if p.optional {
+                format!("[{}{}]", p.name, dots)
+            } else {
+                format!("<{}{}>", p.name, dots)
+            }
+        });
+        flags.chain(params).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Checks `text` against the spec. `info` is used to tell what a channel
+    /// looks like.
+    pub fn parse<'a>(&self, text: &'a str, info: &ServerInfo) -> Result<Args<'a>, ArgError> {
+        let tokens = try!(tokenize(text, true));
+        let mut args = Args::empty(text.trim());
+        let mut flags_done = false;
+        let mut p = 0;
+        let mut t = 0;
+
+        'tokens: while t < tokens.len() {
+            let tok = &tokens[t];
+
+            if !flags_done && tok.is_flag() {
+                t += 1;
+                if tok.text == "--" {
+                    flags_done = true;
+                } else if self.flags.contains(&&tok.text[2..]) {
+                    args.flags.insert(tok.text[2..].to_string());
+                } else {
+                    return Err(ArgError::UnknownFlag(tok.text.clone()));
+                }
+                continue;
+            }
+
+            while let Some(param) = self.params.get(p) {
+                p += 1;
+
+                if param.kind == Kind::Rest {
+                    let rest = text[tok.start..].trim_right();
+                    args.words.extend(tokens[t..].iter().map(|t| t.text.clone()));
+                    args.values.push((param.name, Value::Text(rest.to_string())));
+                    break 'tokens;
+                }
+
+                let value = param.kind.parse(&tok.text, info);
+
+                if param.optional {
+                    let left = tokens[t..].iter().filter(|t| flags_done || !t.is_flag()).count();
+                    let needed = self.params[p..].iter().filter(|p| !p.optional).count();
+                    if value.is_none() || left <= needed {
+                        continue;
+                    }
+                }
+
+                match value {
+                    Some(value) => {
+                        args.words.push(tok.text.clone());
+                        args.values.push((param.name, value));
+                        t += 1;
+                        continue 'tokens;
+                    },
+                    None => return Err(ArgError::Invalid {
+                        name: param.name,
+                        kind: param.kind,
+                        value: tok.text.clone(),
+                    }),
+                }
+            }
+
+            return Err(ArgError::TooMany(tok.text.clone()));
+        }
+
+        match self.params[p..].iter().find(|p| !p.optional) {
+            Some(param) => Err(ArgError::Missing(param.name)),
+            None => Ok(args),
+        }
+    }
+}
+
+impl Kind {
+    fn parse(&self, s: &str, info: &ServerInfo) -> Option<Value> {
+        match *self {
+            Kind::Word | Kind::Rest => Some(Value::Text(s.to_string())),
+            Kind::Int => s.parse().ok().map(Value::Int),
+            Kind::Duration => parse_duration(s).map(Value::Duration),
+            Kind::Nick if is_nick(s) => Some(Value::Text(s.to_string())),
+            Kind::Channel if is_channel(s, info) => Some(Value::Text(s.to_string())),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match *self {
+            Kind::Word | Kind::Rest => "a word",
+            Kind::Int => "a number",
+            Kind::Duration => "a length of time, like 10m",
+            Kind::Nick => "a nickname",
+            Kind::Channel => "a channel",
+        }
+    }
+}
+
+/// Whether `s` could be someone's nick.
+pub fn is_nick(s: &str) -> bool {
+    let special = |c: char| "[]\\`_^{|}".contains(c);
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || special(c) => { },
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || special(c) || c == '-')
+}
+
+fn is_channel(s: &str, info: &ServerInfo) -> bool {
+    info.is_channel(s) && !s.contains(|c| c == ',' || c == '\x07')
+}
+
+/// Parses a length of time like `90s`, `10m`, `1h30m` or `2days`.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total: u64 = 0;
+    let mut rest = s.trim();
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let letters = rest[digits..].find(|c: char| !c.is_ascii_alphabetic())
+            .map(|i| digits + i)
+            .unwrap_or(rest.len());
+
+        let n: u64 = match rest[..digits].parse() {
+            Ok(n) => n,
+            Err(_) => return None,
+        };
+
+        let unit = match &rest[digits..letters].to_ascii_lowercase()[..] {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+            "d" | "day" | "days" => 24 * 60 * 60,
+            "w" | "week" | "weeks" => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+
+        total = match n.checked_mul(unit).and_then(|secs| total.checked_add(secs)) {
+            Some(total) => total,
+            None => return None,
+        };
+        rest = &rest[letters..];
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+/// Describes a length of time in words, to the nearest two units, like
+/// `2 hours, 5 minutes` or `3 days`.
+pub fn describe_duration(d: Duration) -> String {
+    const UNITS: &'static [(&'static str, u64)] = &[
+        ("week", 7 * 24 * 60 * 60),
+        ("day", 24 * 60 * 60),
+        ("hour", 60 * 60),
+        ("minute", 60),
+        ("second", 1),
+    ];
+
+    let mut left = d.as_secs();
+    let mut parts = Vec::new();
+    for &(unit, secs) in UNITS {
+        let n = left / secs;
+        left %= secs;
+        if n > 0 {
+            parts.push(format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" }));
+        } else if !parts.is_empty() {
+            // "1 week, 3 seconds" is more precise than anyone wants
+            break;
+        }
+        if parts.len() == 2 {
+            break;
+        }
+    }
+
+    if parts.is_empty() {
+        "0 seconds".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArgError::Missing(name) => write!(f, "missing <{}>", name),
+            ArgError::Invalid { name, kind, ref value } =>
+                write!(f, "<{}> should be {}, not {:?}", name, kind.describe(), value),
+            ArgError::UnknownFlag(ref flag) => write!(f, "unknown option {}", flag),
+            ArgError::TooMany(ref value) => write!(f, "unexpected {:?}", value),
+            ArgError::UnclosedQuote => write!(f, "missing a closing quote"),
+        }
+    }
+}
+
+impl<'a> Args<'a> {
+    fn empty(text: &'a str) -> Args<'a> {
+        Args {
+            text: text,
+            words: Vec::new(),
+            values: Vec::new(),
+            flags: BTreeSet::new(),
+        }
+    }
+
+    /// Splits `text` into words, for commands that don't have a `Spec`.
+    pub fn parse(text: &'a str) -> Args<'a> {
+        let mut args = Args::empty(text.trim());
+        let tokens = tokenize(text, false).unwrap_or_else(|_| Vec::new());
+        args.words = tokens.into_iter().map(|t| t.text).collect();
+        args
+    }
+
+    /// All of the arguments, as they were given.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// The `i`th word of the arguments, if there are that many. Flags aren't
+    /// counted.
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.words.get(i).map(|w| &w[..])
+    }
+
+    /// How many words there are.
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// All of the words.
+    pub fn words(&self) -> &[String] {
+        &self.words
+    }
+
+    fn value(&self, name: &str) -> Option<&Value> {
+        self.values.iter().find(|&&(n, _)| n == name).map(|&(_, ref v)| v)
+    }
+
+    /// The named argument, if it was given and is a `Word`, `Nick`, `Channel`
+    /// or `Rest`.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.value(name) {
+            Some(&Value::Text(ref s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The named argument, if it was given and is an `Int`.
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.value(name) {
+            Some(&Value::Int(n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// The named argument, if it was given and is a `Duration`.
+    pub fn get_duration(&self, name: &str) -> Option<Duration> {
+        match self.value(name) {
+            Some(&Value::Duration(d)) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Whether the named `--flag` was given.
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+}
+
+#[test]
+fn args_parse() {
+    let args = Args::parse("  aji   \"hello there\" \"unclosed ");
+    assert_eq!(args.text(), "aji   \"hello there\" \"unclosed");
+    assert_eq!(args.len(), 3);
+    assert_eq!(args.get(0), Some("aji"));
+    assert_eq!(args.get(3), None);
+    assert_eq!(args.words(), &["aji", "hello there", "unclosed "]);
+    assert!(Args::parse(" ").is_empty());
+}
+
+#[test]
+fn args_spec() {
+    let info = ServerInfo::new();
+    let spec = Spec::new()
+        .flag("all")
+        .required("who", Kind::Nick)
+        .optional("count", Kind::Int)
+        .optional("message", Kind::Rest);
+
+    assert_eq!(spec.usage(), "[--all] <who> [count] [message...]");
+
+    let args = spec.parse("aji 3 hello  \"there\" --all ", &info).unwrap();
+    assert_eq!(args.get_str("who"), Some("aji"));
+    assert_eq!(args.get_int("count"), Some(3));
+    assert_eq!(args.get_str("message"), Some("hello  \"there\" --all"));
+    assert!(!args.has_flag("all"));
+
+    let args = spec.parse("--all \"[aji]\" hello", &info).unwrap();
+    assert!(args.has_flag("all"));
+    assert_eq!(args.get_str("who"), Some("[aji]"));
+    assert_eq!(args.get_int("count"), None);
+    assert_eq!(args.get_str("message"), Some("hello"));
+    assert_eq!(args.words(), &["[aji]", "hello"]);
+
+    assert_eq!(spec.parse("", &info).err(), Some(ArgError::Missing("who")));
+    assert_eq!(spec.parse("--none aji", &info).err(), Some(ArgError::UnknownFlag("--none".into())));
+    assert_eq!(spec.parse("#aji", &info).err(), Some(ArgError::Invalid {
+        name: "who", kind: Kind::Nick, value: "#aji".into()
+    }));
+    assert_eq!(spec.parse("aji \"hi", &info).err(), Some(ArgError::UnclosedQuote));
+}
+
+#[test]
+fn args_optional_before_required() {
+    let info = ServerInfo::new();
+    let spec = Spec::new()
+        .optional("where", Kind::Channel)
+        .required("when", Kind::Duration)
+        .required("what", Kind::Word);
+
+    assert_eq!(spec.usage(), "[where] <when> <what>");
+
+    let args = spec.parse("#miau 10m deploy", &info).unwrap();
+    assert_eq!(args.get_str("where"), Some("#miau"));
+    assert_eq!(args.get_duration("when"), Some(Duration::from_secs(600)));
+
+    let args = spec.parse("1h30m deploy", &info).unwrap();
+    assert_eq!(args.get_str("where"), None);
+    assert_eq!(args.get_duration("when"), Some(Duration::from_secs(5400)));
+
+    assert_eq!(spec.parse("#miau deploy", &info).err().map(|e| e.to_string()),
+        Some("<when> should be a length of time, like 10m, not \"#miau\"".to_string()));
+    assert_eq!(spec.parse("10m deploy now", &info).err(), Some(ArgError::TooMany("now".into())));
+}
+
+#[test]
+fn args_durations() {
+    assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+    assert_eq!(parse_duration("2h30m"), Some(Duration::from_secs(9000)));
+    assert_eq!(parse_duration("1day"), Some(Duration::from_secs(86400)));
+    assert_eq!(parse_duration("2W"), Some(Duration::from_secs(1209600)));
+    assert_eq!(parse_duration("10"), None);
+    assert_eq!(parse_duration("m"), None);
+    assert_eq!(parse_duration("10x"), None);
+    assert_eq!(parse_duration("99999999999999999999w"), None);
+
+    assert_eq!(describe_duration(Duration::from_secs(0)), "0 seconds");
+    assert_eq!(describe_duration(Duration::from_secs(61)), "1 minute, 1 second");
+    assert_eq!(describe_duration(Duration::from_secs(7500)), "2 hours, 5 minutes");
+    assert_eq!(describe_duration(Duration::from_secs(3 * 86400 + 59)), "3 days");
+    assert_eq!(describe_duration(Duration::from_secs(694861)), "1 week, 1 day");
+}
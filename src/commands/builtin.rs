@@ -0,0 +1,194 @@
+//! Commands that are always around, for checking up on the bot itself.
+
+use commands::Args;
+use commands::Command;
+use commands::Context;
+use commands::Kind;
+use commands::Plugin;
+use commands::Spec;
+
+pub struct Core;
+
+impl Plugin for Core {
+    fn name(&self) -> &str {
+        "core"
+    }
+
+    fn commands(&self) -> Vec<Box<Command>> {
+        vec![Box::new(Help), Box::new(Version), Box::new(Status)]
+    }
+}
+
+/// Command lists longer than this are sent privately, rather than to the
+/// channel the command was issued in.
+const PUBLIC_LIST_LEN: usize = 200;
+
+struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &str {
+        "help"
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &["commands"]
+    }
+
+    fn help(&self) -> &str {
+        "lists commands, or describes one of them"
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().optional("command", Kind::Word))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let line = {
+            let registry = match ctx.registry() {
+                Some(registry) => registry,
+                None => return,
+            };
+
+            match args.get_str("command") {
+                Some(name) => match registry.find_available(name, &*ctx) {
+                    Some(cmd) => Ok(describe(cmd)),
+                    None => Err(format!("no such command: {}", name)),
+                },
+                None => {
+                    let names: Vec<&str> = registry.available(&*ctx).iter()
+                        .map(|c| c.name())
+                        .collect();
+                    Ok(format!("commands: {}. use help <command> for more", names.join(", ")))
+                },
+            }
+        };
+
+        match line {
+            Ok(ref line) if line.len() > PUBLIC_LIST_LEN => ctx.reply_private(line),
+            Ok(ref line) => ctx.reply(line),
+            Err(ref line) => ctx.reply_error(line),
+        }
+    }
+}
+
+/// Describes a command in a line, like `help [command]: lists commands`.
+fn describe(cmd: &Command) -> String {
+    let mut line = cmd.name().to_string();
+    if !cmd.usage().is_empty() {
+        line.push(' ');
+        line.push_str(&cmd.usage());
+    }
+    line.push_str(": ");
+    line.push_str(cmd.help());
+    if !cmd.aliases().is_empty() {
+        line.push_str(&format!(" (also {})", cmd.aliases().join(", ")));
+    }
+    line
+}
+
+struct Version;
+
+impl Command for Version {
+    fn name(&self) -> &str {
+        "version"
+    }
+
+    fn help(&self) -> &str {
+        "shows which version of the bot is running"
+    }
+
+    fn run(&self, ctx: &mut Context, _args: &Args) {
+        ctx.reply(&format!("i am {} v{}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+}
+
+struct Status;
+
+impl Command for Status {
+    fn name(&self) -> &str {
+        "status"
+    }
+
+    fn help(&self) -> &str {
+        "shows the state of the connection to the server"
+    }
+
+    fn run(&self, ctx: &mut Context, _args: &Args) {
+        let lag = ctx.network().and_then(|net| net.lag());
+        match lag {
+            Some(lag) => ctx.reply(&format!("connected, lag is {}.{:03}s",
+                lag.as_secs(),
+                lag.subsec_nanos() / 1_000_000
+            )),
+            None => ctx.reply("connected, lag hasn't been measured yet"),
+        }
+    }
+}
+
+#[cfg(test)]
+const TEST_CONFIG: &'static str = r##"
+    [irc]
+    nick = "miau"
+    channels = []
+
+    [plugins.channels."#quiet"]
+    disabled = ["test"]
+"##;
+
+#[test]
+fn help_lists_commands() {
+    use commands::replies_from;
+    use commands::CommandRegistry;
+    use commands::registry::TestPlugin;
+
+    let mut registry = CommandRegistry::from_env(&::environment::from_str(TEST_CONFIG));
+    registry.register(Box::new(Core)).unwrap();
+    registry.register(Box::new(TestPlugin("test", vec![("echo", &["say"])]))).unwrap();
+
+    let help = |line: &str| replies_from(&registry, TEST_CONFIG, &[line]);
+
+    assert_eq!(help(":aji!a@h PRIVMSG #miau-dev :!help"), vec![
+        "PRIVMSG #miau-dev :aji: commands: echo, help, status, version. use help <command> for more",
+    ]);
+    assert_eq!(help(":aji!a@h PRIVMSG #quiet :!help"), vec![
+        "PRIVMSG #quiet :aji: commands: help, status, version. use help <command> for more",
+    ]);
+    assert_eq!(help(":aji!a@h PRIVMSG #miau-dev :!help SAY"), vec![
+        "PRIVMSG #miau-dev :aji: echo: a test command (also say)",
+    ]);
+    assert_eq!(help(":aji!a@h PRIVMSG miau :commands help"), vec![
+        "NOTICE aji :help [command]: lists commands, or describes one of them (also commands)",
+    ]);
+    assert_eq!(help(":aji!a@h PRIVMSG #quiet :!help echo"), vec![
+        "PRIVMSG #quiet :aji: no such command: echo",
+    ]);
+    assert_eq!(help(":aji!a@h PRIVMSG #quiet :!help echo status"), vec![
+        "PRIVMSG #quiet :aji: unexpected \"status\" (usage: help [command])",
+    ]);
+}
+
+#[test]
+fn help_sends_long_lists_privately() {
+    use commands::replies_from;
+    use commands::CommandRegistry;
+    use commands::registry::TestPlugin;
+
+    let mut registry = CommandRegistry::from_env(&::environment::from_str(TEST_CONFIG));
+    registry.register(Box::new(Core)).unwrap();
+    registry.register(Box::new(TestPlugin("test", vec![
+        ("a-command-with-a-very-long-name", &[]),
+        ("another-command-with-a-very-long-name", &[]),
+        ("yet-another-command-with-a-very-long-name", &[]),
+        ("the-last-command-with-a-very-long-name", &[]),
+    ]))).unwrap();
+
+    let replies = replies_from(&registry, TEST_CONFIG, &[":aji!a@h PRIVMSG #miau-dev :!help"]);
+    assert_eq!(replies.len(), 1);
+    assert!(replies[0].starts_with("NOTICE aji :commands: a-command-with-a-very-long-name, "));
+
+    let replies = replies_from(&registry, TEST_CONFIG, &[":aji!a@h PRIVMSG #quiet :!help"]);
+    assert!(replies[0].starts_with("PRIVMSG #quiet :aji: commands: help, "));
+}
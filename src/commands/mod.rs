@@ -0,0 +1,460 @@
+//! Commands, and the plugins that provide them.
+//!
+//! Every command belongs to a [`Plugin`](trait.Plugin.html), and every plugin
+//! the bot knows about is registered with a
+//! [`CommandRegistry`](struct.CommandRegistry.html) when the bot starts up, in
+//! [`load`](fn.load.html). Plugins can be turned off everywhere or in
+//! particular channels with the `[plugins]` section of the config, and who can
+//! run which commands is decided by the [`acl`](acl/index.html) module.
+
+use std::time::Duration;
+
+use irc::Command as IrcCommand;
+use irc::OwnedMessage;
+use irc::OwnedSource;
+
+use environment::Env;
+use network::Channel;
+use network::Network;
+use network::Output;
+use storage::Storage;
+
+pub use self::acl::Acl;
+pub use self::acl::Level;
+pub use self::addressing::Addressing;
+pub use self::args::ArgError;
+pub use self::args::Args;
+pub use self::args::Kind;
+pub use self::args::Spec;
+pub use self::args::describe_duration;
+pub use self::args::is_nick;
+pub use self::args::parse_duration;
+pub use self::registry::CommandRegistry;
+pub use self::registry::Error;
+
+pub mod acl;
+pub mod addressing;
+mod admin;
+mod args;
+mod builtin;
+mod registry;
+mod remind;
+mod seen;
+mod tell;
+
+/// A command that can be run from IRC.
+pub trait Command {
+    /// The name the command is run by.
+    fn name(&self) -> &str;
+
+    /// Other names the command can be run by.
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+
+    /// A one line description of what the command does.
+    fn help(&self) -> &str;
+
+    /// What the command's arguments should look like. Commands that have a
+    /// spec only run if their arguments match it. Commands without one get
+    /// whatever they were given, split into words.
+    fn args(&self) -> Option<Spec> {
+        None
+    }
+
+    /// The level someone needs to be to run the command, unless the config
+    /// says otherwise.
+    fn level(&self) -> Level {
+        Level::Anyone
+    }
+
+    /// The arguments the command takes, like `<nick> [message...]`.
+    fn usage(&self) -> String {
+        self.args().map(|spec| spec.usage()).unwrap_or_default()
+    }
+
+    /// Runs the command.
+    fn run(&self, ctx: &mut Context, args: &Args);
+}
+
+/// A named group of commands that can be enabled and disabled together.
+pub trait Plugin {
+    /// The name the plugin is referred to by in the config.
+    fn name(&self) -> &str;
+
+    /// The commands the plugin provides. This is only called once, when the
+    /// plugin is registered.
+    fn commands(&self) -> Vec<Box<Command>>;
+
+    /// Called with every message from the server, after the network has
+    /// handled it, for plugins that keep track of what people are doing.
+    fn observe(&self, _net: &Network, _out: &mut Output, _m: &OwnedMessage) {
+    }
+
+    /// The Unix time the plugin next needs `tick` to be called, if ever.
+    fn next_due(&self) -> Option<u64> {
+        None
+    }
+
+    /// Called once it's `next_due`, with the current Unix time, to do
+    /// whatever the plugin had scheduled.
+    fn tick(&self, _net: &Network, _out: &mut Output, _now: u64) {
+    }
+}
+
+/// Builds the registry of every plugin the bot has, configured from `env`.
+/// Plugins keep whatever they need to remember in `storage`.
+pub fn load(env: &Env, storage: &Storage) -> Result<CommandRegistry, Error> {
+    let mut registry = CommandRegistry::from_env(env);
+    try!(registry.register(Box::new(builtin::Core)));
+    try!(registry.register(Box::new(admin::Admin)));
+    try!(registry.register(Box::new(seen::Seen::new(storage))));
+    try!(registry.register(Box::new(tell::Tell::new(env, storage))));
+    try!(registry.register(Box::new(remind::Remind::new(env, storage))));
+    registry.check_config();
+    Ok(registry)
+}
+
+/// Describes how long ago a Unix time was, like `2 hours, 5 minutes ago`.
+fn describe_ago(then: u64, now: u64) -> String {
+    let ago = now.saturating_sub(then);
+    if ago < 60 {
+        "just now".to_string()
+    } else {
+        format!("{} ago", describe_duration(Duration::from_secs(ago)))
+    }
+}
+
+/// Finds and runs the named command in the given command handling context.
+pub fn handle_command(registry: &CommandRegistry, ctx: &mut Context, cmd: &str, args: &str) {
+    registry.run(ctx, cmd, args);
+}
+
+/// Passes a message from the server on to plugins, and to commands if it's a
+/// `PRIVMSG`. Our own messages coming back with `echo-message` are skipped, so
+/// that we don't act on what we said ourselves.
+pub fn handle_message<T: Output>(registry: &CommandRegistry, net: &mut Network, out: &mut T,
+                                 m: &OwnedMessage) {
+    if net.is_echo(m) {
+        return;
+    }
+
+    registry.observe(net, out, m);
+    if let IrcCommand::Privmsg(..) = m.command {
+        handle_irc(registry, net, out, m);
+    }
+}
+
+/// Helper method for handling messages that come from an IRC network. This method may or may
+/// not actually call `handle_command`, since the message may not be formatted with the
+/// correct command syntax.
+pub fn handle_irc<T: Output>(registry: &CommandRegistry, net: &mut Network, out: &mut T,
+                             m: &OwnedMessage) {
+    // quick sanity check, this should be a PRIVMSG
+    let (target, text) = match m.command {
+        IrcCommand::Privmsg(ref target, ref text) => (&target[..], &text[..]),
+        _ => {
+            warn!("handle_irc called with something other than PRIVMSG: {:?}", m);
+            return;
+        }
+    };
+
+    let my_nick = match net.current_nick() {
+        Some(s) => s,
+        None => {
+            warn!("handle_irc called while we have no nickname");
+            return;
+        }
+    };
+
+    let info = net.server_info();
+    let chan = Some(info.split_statusmsg(target).1).filter(|c| info.is_channel(c));
+
+    let command = registry.addressing().command(text, my_nick, chan, info.casemapping());
+    let (cmd, args) = match command {
+        Some(command) => command,
+        None => return,
+    };
+
+    let mut ctx = IrcContext::new(registry, net, out, m, target);
+    handle_command(registry, &mut ctx, cmd, args);
+}
+
+/// A trait defining the context in which commands are handled. Commands must interact with the
+/// world through an implementation of Context.
+pub trait Context {
+    /// A normal response to a command. These are generally guaranteed to be seen by whoever or
+    /// whatever issued the command.
+    fn reply(&mut self, line: &str);
+
+    /// An error message. These might be treated or formatted differently, depending on context.
+    /// By default, errors are treated identically to normal replies.
+    fn reply_error(&mut self, line: &str) {
+        self.reply(line);
+    }
+
+    /// A warning message. These are ignored by default, but might be treated similarly to an
+    /// error, depending on context.
+    fn reply_warn(&mut self, _line: &str) {
+        // warnings aren't printed by default
+    }
+
+    /// A response that only whoever issued the command should see, for things too long to
+    /// put in a channel. By default, these are treated identically to normal replies.
+    fn reply_private(&mut self, line: &str) {
+        self.reply(line);
+    }
+
+    /// The commands that are available, if this context knows.
+    fn registry(&self) -> Option<&CommandRegistry> {
+        None
+    }
+
+    /// The network the command came from, if it came from one.
+    fn network(&self) -> Option<&Network> {
+        None
+    }
+
+    /// The network the command came from and the connection to it, for
+    /// commands that need to do more than reply.
+    fn connection(&mut self) -> Option<(&mut Network, &mut Output)> {
+        None
+    }
+
+    /// The nickname of whoever issued the command, if it came from IRC.
+    fn sender(&self) -> Option<&str> {
+        None
+    }
+
+    /// The full `nick!user@host` of whoever issued the command, if we know it.
+    fn sender_mask(&self) -> Option<String> {
+        None
+    }
+
+    /// The services account whoever issued the command is logged in to, if
+    /// we know it.
+    fn sender_account(&self) -> Option<&str> {
+        None
+    }
+
+    /// The name of the channel the command was issued in, if any.
+    fn channel_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// The channel the command was issued in, if any.
+    fn channel(&self) -> Option<&Channel> {
+        None
+    }
+
+    /// Whether whoever issued the command is an operator in the channel it
+    /// was issued in.
+    fn sender_is_op(&self) -> bool {
+        match (self.channel(), self.sender()) {
+            (Some(chan), Some(sender)) => chan.member(sender).map(|m| m.is_op()).unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+struct IrcContext<'m, T: 'm> {
+    registry: &'m CommandRegistry,
+    net: &'m mut Network,
+    out: &'m mut T,
+    m: &'m OwnedMessage,
+    sender: &'m str,
+    chan: Option<&'m str>,
+    reply_to: &'m str,
+    reply_prefix: Option<&'m str>
+}
+
+impl<'m, T: Output> IrcContext<'m, T> {
+    fn new(registry: &'m CommandRegistry, net: &'m mut Network, out: &'m mut T,
+           m: &'m OwnedMessage, target: &'m str) -> IrcContext<'m, T> {
+        let sender = m.src.short_name();
+
+        // messages to just the ops of a channel are still in the channel,
+        // but replies should go to the same people
+        let chan = net.server_info().split_statusmsg(target).1;
+
+        if net.server_info().is_channel(chan) {
+            IrcContext {
+                registry: registry,
+                net: net,
+                out: out,
+                m: m,
+                sender: sender,
+                chan: Some(chan),
+                reply_to: target,
+                reply_prefix: Some(sender),
+            }
+        } else {
+            IrcContext {
+                registry: registry,
+                net: net,
+                out: out,
+                m: m,
+                sender: sender,
+                chan: None,
+                reply_to: sender,
+                reply_prefix: None,
+            }
+        }
+    }
+}
+
+impl<'m, T: Output> Context for IrcContext<'m, T> {
+    fn reply(&mut self, line: &str) {
+        match self.reply_prefix {
+            Some(prefix) => {
+                let full_line = format!("{}: {}", prefix, line);
+                self.out.PRIVMSG(self.reply_to, &full_line);
+            },
+            None => {
+                self.out.NOTICE(self.reply_to, line);
+            }
+        }
+    }
+
+    fn reply_warn(&mut self, line: &str) {
+        // print warning messages for contexts without a prefix. right now this
+        // is just private messages.
+        if self.reply_prefix.is_none() {
+            self.reply(line);
+        }
+    }
+
+    fn reply_private(&mut self, line: &str) {
+        self.out.NOTICE(self.sender, line);
+    }
+
+    fn registry(&self) -> Option<&CommandRegistry> {
+        Some(self.registry)
+    }
+
+    fn network(&self) -> Option<&Network> {
+        Some(self.net)
+    }
+
+    fn connection(&mut self) -> Option<(&mut Network, &mut Output)> {
+        Some((self.net, self.out))
+    }
+
+    fn sender(&self) -> Option<&str> {
+        Some(self.sender)
+    }
+
+    fn sender_mask(&self) -> Option<String> {
+        let user = self.net.channels().and_then(|c| c.user(self.sender));
+        let (nick, user, host) = match self.m.src {
+            OwnedSource::User(ref nick, Some(ref u), Some(ref h)) => (nick, &u[..], &h[..]),
+            OwnedSource::User(ref nick, _, _) => match user.map(|u| (u.user(), u.host())) {
+                Some((Some(u), Some(h))) => (nick, u, h),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(format!("{}!{}@{}", nick, user, host))
+    }
+
+    fn sender_account(&self) -> Option<&str> {
+        if self.net.has_cap("account-tag") {
+            return self.m.tags.get("account");
+        }
+        self.net.channels().and_then(|c| c.user(self.sender)).and_then(|u| u.account())
+    }
+
+    fn channel_name(&self) -> Option<&str> {
+        self.chan
+    }
+
+    fn channel(&self) -> Option<&Channel> {
+        self.chan.and_then(|c| self.net.channel(c))
+    }
+}
+
+/// Feeds lines to a freshly registered network with the given config, and
+/// returns whatever commands sent in response.
+#[cfg(test)]
+fn replies_with_config(config: &str, lines: &[&str]) -> Vec<String> {
+    let env = ::environment::from_str(config);
+    let registry = load(&env, &Storage::memory()).expect("duplicate commands");
+    replies_from(&registry, config, lines)
+}
+
+#[cfg(test)]
+fn replies_from(registry: &CommandRegistry, config: &str, lines: &[&str]) -> Vec<String> {
+    let env = ::environment::from_str(config);
+    let mut out = Vec::new();
+    let mut net = Network::register(env, &mut out);
+    net.handle_message(&mut out, &OwnedMessage::parse(":irc.test 001 [miau] :hi").unwrap()).unwrap();
+    out.clear();
+
+    for line in lines {
+        let m = OwnedMessage::parse(line).unwrap();
+        net.handle_message(&mut out, &m).unwrap();
+        handle_message(registry, &mut net, &mut out, &m);
+    }
+    out
+}
+
+/// A registry with just the one plugin, for testing plugins on their own.
+/// The plugin is made with the config, some storage, and `test_clock`.
+#[cfg(test)]
+fn registry_with<P, F>(config: &str, storage: &Storage, plugin: F) -> CommandRegistry
+where P: Plugin + 'static, F: FnOnce(&Env, &Storage, fn() -> u64) -> P {
+    let env = ::environment::from_str(config);
+    let mut registry = CommandRegistry::from_env(&env);
+    registry.register(Box::new(plugin(&env, storage, test_clock))).expect("duplicate commands");
+    registry
+}
+
+#[cfg(test)]
+thread_local!(static TEST_NOW: ::std::cell::Cell<u64> = ::std::cell::Cell::new(1000000));
+
+/// The time for plugins in tests, which starts at 1000000 and only changes
+/// with `set_test_time`. Each test has its own.
+#[cfg(test)]
+fn test_clock() -> u64 {
+    TEST_NOW.with(|now| now.get())
+}
+
+#[cfg(test)]
+fn set_test_time(now: u64) {
+    TEST_NOW.with(|n| n.set(now));
+}
+
+#[cfg(test)]
+fn replies_to(lines: &[&str]) -> Vec<String> {
+    replies_with_config("[irc]\nnick = \"miau\"\nchannels = []", lines)
+}
+
+#[test]
+fn commands_ignore_echoes() {
+    let version = format!("i am {} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let config = "[irc]\nnick = \"miau\"\nchannels = []\ncaps = [\"echo-message\"]";
+
+    assert_eq!(replies_with_config(config, &[
+        ":irc.test CAP [miau] NEW :echo-message",
+        ":irc.test CAP [miau] ACK :echo-message",
+        ":[MIAU]!m@h PRIVMSG #miau-dev :!version",
+        ":aji!a@h PRIVMSG #miau-dev :!version",
+    ]), vec![
+        "CAP REQ echo-message".to_string(),
+        format!("PRIVMSG #miau-dev :aji: {}", version),
+    ]);
+}
+
+#[test]
+fn commands_addressed_by_nick() {
+    let version = format!("i am {} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let expected = vec![format!("PRIVMSG #miau-dev :aji: {}", version)];
+
+    assert_eq!(replies_to(&[":aji!a@h PRIVMSG #miau-dev :[miau]: version"]), expected);
+    assert_eq!(replies_to(&[":aji!a@h PRIVMSG #miau-dev :{MIAU}, version"]), expected);
+    assert_eq!(replies_to(&[":aji!a@h PRIVMSG #miau-dev :!version"]), expected);
+    assert!(replies_to(&[":aji!a@h PRIVMSG #miau-dev :[miau]x version"]).is_empty());
+    assert!(replies_to(&[":aji!a@h PRIVMSG #miau-dev :version"]).is_empty());
+    assert_eq!(replies_to(&[":aji!a@h PRIVMSG #miau-dev :[miau]:version"]), expected);
+    assert_eq!(replies_to(&[":aji!a@h PRIVMSG #miau-dev :@[miau] version"]), expected);
+}
@@ -0,0 +1,435 @@
+//! Looking up commands by name, and deciding where they're allowed to run.
+//!
+//! Plugins are enabled everywhere unless the config says otherwise:
+//!
+//! ```toml
+//! [plugins]
+//! disabled = [ "games" ]
+//!
+//! [plugins.channels."#miau-dev"]
+//! enabled = [ "games" ]
+//! disabled = [ "core" ]
+//! ```
+//!
+//! A channel's own list always wins over `plugins.disabled`. Private messages
+//! only go by `plugins.disabled`.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+
+use commands::Acl;
+use commands::Addressing;
+use commands::Args;
+use commands::Command;
+use commands::Context;
+use commands::Plugin;
+use environment::Env;
+use irc::Command as IrcCommand;
+use irc::OwnedMessage;
+use irc::casemap::CaseMapping;
+use network::Network;
+use network::Output;
+use network::ServerInfo;
+
+pub struct CommandRegistry {
+    plugins: Vec<Box<Plugin>>,
+    commands: Vec<Entry>,
+    names: BTreeMap<String, usize>,
+    disabled: BTreeSet<String>,
+    channels: Vec<(String, ChannelRules)>,
+    acl: Acl,
+    addressing: Addressing,
+}
+
+struct Entry {
+    plugin: usize,
+    command: Box<Command>,
+}
+
+#[derive(Default)]
+struct ChannelRules {
+    enabled: BTreeSet<String>,
+    disabled: BTreeSet<String>,
+}
+
+/// Used to signal that a plugin couldn't be registered.
+#[derive(Debug)]
+pub enum Error {
+    /// Two plugins have the same name.
+    DuplicatePlugin(String),
+    /// A command name or alias is already taken by another command.
+    DuplicateCommand { name: String, plugin: String, other: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DuplicatePlugin(ref name) =>
+                write!(f, "plugin {} is registered twice", name),
+            Error::DuplicateCommand { ref name, ref plugin, ref other } =>
+                write!(f, "plugin {} wants command {}, but {} already has it", plugin, name, other),
+        }
+    }
+}
+
+fn names_in(value: Option<&::toml::Value>) -> BTreeSet<String> {
+    value.and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|n| n.as_str()).map(|n| n.to_string()).collect())
+        .unwrap_or_else(BTreeSet::new)
+}
+
+impl CommandRegistry {
+    pub fn from_env(env: &Env) -> CommandRegistry {
+        let channels = env.conf_table("plugins.channels").map(|t| {
+            t.iter().map(|(chan, rules)| (chan.clone(), ChannelRules {
+                enabled: names_in(rules.get("enabled")),
+                disabled: names_in(rules.get("disabled")),
+            })).collect()
+        });
+
+        CommandRegistry {
+            plugins: Vec::new(),
+            commands: Vec::new(),
+            names: BTreeMap::new(),
+            disabled: names_in(env.conf("plugins.disabled")),
+            channels: channels.unwrap_or_else(Vec::new),
+            acl: Acl::from_env(env),
+            addressing: Addressing::from_env(env),
+        }
+    }
+
+    /// Adds a plugin and all of its commands. Nothing is added if any of the
+    /// names are already taken.
+    pub fn register(&mut self, plugin: Box<Plugin>) -> Result<(), Error> {
+        if self.plugins.iter().any(|p| p.name() == plugin.name()) {
+            return Err(Error::DuplicatePlugin(plugin.name().to_string()));
+        }
+
+        let index = self.plugins.len();
+        let commands = plugin.commands();
+        let mut names = BTreeMap::new();
+
+        for (i, command) in commands.iter().enumerate() {
+            let all = Some(command.name()).into_iter().chain(command.aliases().iter().cloned());
+            for name in all {
+                let name = name.to_ascii_lowercase();
+                let other = match self.names.get(&name) {
+                    Some(&j) => Some(self.plugin_name(&self.commands[j])),
+                    None if names.contains_key(&name) => Some(plugin.name()),
+                    None => None,
+                };
+                if let Some(other) = other {
+                    return Err(Error::DuplicateCommand {
+                        name: name,
+                        plugin: plugin.name().to_string(),
+                        other: other.to_string(),
+                    });
+                }
+                names.insert(name, self.commands.len() + i);
+            }
+        }
+
+        debug!("registered plugin {} with {} command(s)", plugin.name(), commands.len());
+        self.names.extend(names);
+        self.commands.extend(commands.into_iter().map(|c| Entry { plugin: index, command: c }));
+        self.plugins.push(plugin);
+        Ok(())
+    }
+
+    /// Complains about plugins named in the config that don't exist, since
+    /// they're probably typos.
+    pub fn check_config(&self) {
+        let chans = self.channels.iter().flat_map(|&(_, ref r)| r.enabled.iter().chain(&r.disabled));
+        for name in self.disabled.iter().chain(chans) {
+            if !self.plugins.iter().any(|p| p.name() == name) {
+                warn!("config mentions plugin {}, but there's no such plugin", name);
+            }
+        }
+    }
+
+    fn plugin_name(&self, entry: &Entry) -> &str {
+        self.plugins[entry.plugin].name()
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.names.get(&name.to_ascii_lowercase()).map(|&i| &self.commands[i])
+    }
+
+    /// Looks up a command by its name or one of its aliases.
+    pub fn find(&self, name: &str) -> Option<&Command> {
+        self.entry(name).map(|e| &*e.command)
+    }
+
+    /// The name of the plugin a command belongs to.
+    pub fn plugin_of(&self, name: &str) -> Option<&str> {
+        self.entry(name).map(|e| self.plugin_name(e))
+    }
+
+    /// Every command, in the order they were registered.
+    pub fn commands<'a>(&'a self) -> Box<Iterator<Item=&'a Command> + 'a> {
+        Box::new(self.commands.iter().map(|e| &*e.command))
+    }
+
+    /// Whether a plugin's commands can be run in the given channel, or in
+    /// private if there's no channel.
+    pub fn is_enabled(&self, plugin: &str, chan: Option<&str>, casemap: CaseMapping) -> bool {
+        let rules = chan.and_then(|chan| {
+            self.channels.iter().find(|&&(ref name, _)| casemap.equals(name, chan))
+        });
+
+        match rules {
+            Some(&(_, ref r)) if r.enabled.contains(plugin) => true,
+            Some(&(_, ref r)) if r.disabled.contains(plugin) => false,
+            _ => !self.disabled.contains(plugin),
+        }
+    }
+
+    /// Whether a plugin's commands can be run wherever `ctx` is.
+    pub fn is_enabled_for(&self, plugin: &str, ctx: &Context) -> bool {
+        let casemap = ctx.network().map(|n| n.server_info().casemapping()).unwrap_or_default();
+        self.is_enabled(plugin, ctx.channel_name(), casemap)
+    }
+
+    /// Shows a message from the server to every plugin that's enabled where
+    /// it happened. Messages that aren't in a channel, like `QUIT`, are shown
+    /// to every plugin that isn't disabled everywhere.
+    pub fn observe(&self, net: &Network, out: &mut Output, m: &OwnedMessage) {
+        let info = net.server_info();
+        let chan = match m.command {
+            IrcCommand::Privmsg(ref target, _) |
+            IrcCommand::Notice(ref target, _) => Some(info.split_statusmsg(target).1),
+            IrcCommand::Join(ref chan, _) |
+            IrcCommand::Part(ref chan, _) |
+            IrcCommand::Kick(ref chan, _, _) => Some(&chan[..]),
+            _ => None,
+        }.filter(|c| info.is_channel(c));
+
+        for plugin in &self.plugins {
+            if self.is_enabled(plugin.name(), chan, info.casemapping()) {
+                plugin.observe(net, out, m);
+            }
+        }
+    }
+
+    /// The soonest any plugin has something scheduled, as a Unix time.
+    /// Plugins that are disabled everywhere don't count.
+    pub fn next_due(&self) -> Option<u64> {
+        self.plugins.iter()
+            .filter(|p| !self.disabled.contains(p.name()))
+            .filter_map(|p| p.next_due())
+            .min()
+    }
+
+    /// Lets every plugin that has something due by `now` do it.
+    pub fn tick(&self, net: &Network, out: &mut Output, now: u64) {
+        for plugin in &self.plugins {
+            if self.disabled.contains(plugin.name()) {
+                continue;
+            }
+            if plugin.next_due().map(|due| due <= now).unwrap_or(false) {
+                plugin.tick(net, out, now);
+            }
+        }
+    }
+
+    /// How to tell which messages are commands.
+    pub fn addressing(&self) -> &Addressing {
+        &self.addressing
+    }
+
+    /// Who's allowed to run which commands.
+    pub fn acl(&self) -> &Acl {
+        &self.acl
+    }
+
+    /// Whether the command can be run wherever `ctx` is, by whoever issued
+    /// it.
+    fn allowed(&self, entry: &Entry, ctx: &Context) -> bool {
+        self.is_enabled_for(self.plugin_name(entry), ctx) && self.acl.permits(&*entry.command, ctx)
+    }
+
+    /// The commands that can be run wherever `ctx` is, sorted by name.
+    pub fn available<'a>(&'a self, ctx: &Context) -> Vec<&'a Command> {
+        let mut commands: Vec<&Command> = self.commands.iter()
+            .filter(|e| self.allowed(e, ctx))
+            .map(|e| &*e.command)
+            .collect();
+        commands.sort_by_key(|c| c.name().to_ascii_lowercase());
+        commands
+    }
+
+    /// Looks up a command by name, but only if it can be run wherever `ctx`
+    /// is.
+    pub fn find_available(&self, name: &str, ctx: &Context) -> Option<&Command> {
+        self.entry(name).filter(|e| self.allowed(e, ctx)).map(|e| &*e.command)
+    }
+
+    /// Runs the named command, if there is one and it's allowed to run here
+    /// and `text` matches its arguments.
+    pub fn run(&self, ctx: &mut Context, name: &str, text: &str) {
+        let entry = match self.entry(name) {
+            Some(entry) => entry,
+            None => {
+                ctx.reply_warn(&format!("unknown command: {}", name));
+                return;
+            },
+        };
+
+        if !self.is_enabled_for(self.plugin_name(entry), ctx) {
+            ctx.reply_warn(&format!("{} is disabled here", name));
+            return;
+        }
+
+        let needed = self.acl.required(&*entry.command, ctx);
+        if self.acl.level_of(ctx) < needed {
+            info!("{} tried to use {} without {} access",
+                ctx.sender_mask().unwrap_or_else(|| ctx.sender().unwrap_or("?").to_string()),
+                name, needed.name());
+            ctx.reply_error(&format!("you need {} access to use {}", needed.name(), name));
+            return;
+        }
+
+        let parsed = match entry.command.args() {
+            Some(spec) => {
+                let default_info;
+                let info = match ctx.network() {
+                    Some(net) => net.server_info(),
+                    None => { default_info = ServerInfo::new(); &default_info },
+                };
+                spec.parse(text, info).map_err(|e| (e, spec.usage()))
+            },
+            None => Ok(Args::parse(text)),
+        };
+
+        match parsed {
+            Ok(args) => entry.command.run(ctx, &args),
+            Err((e, usage)) => {
+                let name = entry.command.name();
+                ctx.reply_error(&format!("{} (usage: {} {})", e, name, usage));
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+pub struct TestPlugin(pub &'static str, pub Vec<(&'static str, &'static [&'static str])>);
+
+#[cfg(test)]
+pub struct TestCommand(&'static str, &'static [&'static str]);
+
+#[cfg(test)]
+impl Plugin for TestPlugin {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn commands(&self) -> Vec<Box<Command>> {
+        self.1.iter().map(|&(name, aliases)| Box::new(TestCommand(name, aliases)) as Box<Command>)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Command for TestCommand {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn aliases(&self) -> &[&str] {
+        self.1
+    }
+
+    fn help(&self) -> &str {
+        "a test command"
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        ctx.reply(&format!("{} {}", self.0, args.text()));
+    }
+}
+
+#[cfg(test)]
+struct TestContext {
+    chan: Option<&'static str>,
+    replies: Vec<String>,
+}
+
+#[cfg(test)]
+impl Context for TestContext {
+    fn reply(&mut self, line: &str) {
+        self.replies.push(line.to_string());
+    }
+
+    fn reply_warn(&mut self, line: &str) {
+        self.replies.push(format!("warning: {}", line));
+    }
+
+    fn channel_name(&self) -> Option<&str> {
+        self.chan
+    }
+}
+
+#[cfg(test)]
+fn run_in(registry: &CommandRegistry, chan: Option<&'static str>, line: &str) -> Vec<String> {
+    let mut ctx = TestContext { chan: chan, replies: Vec::new() };
+    let mut parts = line.splitn(2, ' ');
+    let name = parts.next().unwrap_or("");
+    registry.run(&mut ctx, name, parts.next().unwrap_or(""));
+    ctx.replies
+}
+
+#[test]
+fn registry_finds_commands() {
+    let mut registry = CommandRegistry::from_env(&::environment::from_str(""));
+    registry.register(Box::new(TestPlugin("test", vec![("echo", &["say", "Repeat"])]))).unwrap();
+
+    assert_eq!(run_in(&registry, None, "echo hi"), vec!["echo hi"]);
+    assert_eq!(run_in(&registry, None, "SAY hi"), vec!["echo hi"]);
+    assert_eq!(run_in(&registry, None, "repeat"), vec!["echo "]);
+    assert_eq!(run_in(&registry, None, "nope"), vec!["warning: unknown command: nope"]);
+    assert_eq!(registry.plugin_of("say"), Some("test"));
+    assert_eq!(registry.commands().map(|c| c.name()).collect::<Vec<_>>(), vec!["echo"]);
+}
+
+#[test]
+fn registry_rejects_duplicates() {
+    let mut registry = CommandRegistry::from_env(&::environment::from_str(""));
+    registry.register(Box::new(TestPlugin("one", vec![("echo", &["say"])]))).unwrap();
+
+    match registry.register(Box::new(TestPlugin("one", vec![]))) {
+        Err(Error::DuplicatePlugin(ref name)) if name == "one" => { },
+        other => panic!("expected a duplicate plugin, got {:?}", other),
+    }
+
+    let err = registry.register(Box::new(TestPlugin("two", vec![("hi", &[]), ("Say", &[])])));
+    assert_eq!(err.unwrap_err().to_string(), "plugin two wants command say, but one already has it");
+
+    let err = registry.register(Box::new(TestPlugin("three", vec![("hi", &["hi"])])));
+    assert_eq!(err.unwrap_err().to_string(), "plugin three wants command hi, but three already has it");
+
+    // nothing from the failed plugins should have stuck around
+    assert!(registry.find("hi").is_none());
+    registry.register(Box::new(TestPlugin("two", vec![("hi", &[])]))).unwrap();
+}
+
+#[test]
+fn registry_per_channel() {
+    let env = ::environment::from_str(r##"
+        [plugins]
+        disabled = ["games"]
+
+        [plugins.channels."#Games"]
+        enabled = ["games"]
+        disabled = ["test"]
+    "##);
+    let mut registry = CommandRegistry::from_env(&env);
+    registry.register(Box::new(TestPlugin("test", vec![("echo", &[])]))).unwrap();
+    registry.register(Box::new(TestPlugin("games", vec![("dice", &[])]))).unwrap();
+
+    assert_eq!(run_in(&registry, Some("#miau-dev"), "echo"), vec!["echo "]);
+    assert_eq!(run_in(&registry, Some("#miau-dev"), "dice"), vec!["warning: dice is disabled here"]);
+    assert_eq!(run_in(&registry, None, "dice"), vec!["warning: dice is disabled here"]);
+    assert_eq!(run_in(&registry, Some("#games"), "dice"), vec!["dice "]);
+    assert_eq!(run_in(&registry, Some("#GAMES"), "echo"), vec!["warning: echo is disabled here"]);
+}
@@ -0,0 +1,781 @@
+//! Reminding people about things later.
+//!
+//! Reminders are set with a length of time or a time of day in UTC:
+//!
+//! ```text
+//! remind me in 2h to deploy
+//! remind pinkie in 1 hour and 30 minutes the party's starting
+//! remind #miau-dev at 17:00 UTC standup
+//! remind me at 2026-12-25 09:00 presents!
+//! ```
+//!
+//! They're saved, so ones that come due while the bot is restarting are sent
+//! as soon as it's back. How many each person can have waiting is limited:
+//!
+//! ```toml
+//! [remind]
+//! max_per_user = 10
+//! ```
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use commands::Args;
+use commands::Command;
+use commands::Context;
+use commands::Kind;
+use commands::Level;
+use commands::Plugin;
+use commands::Spec;
+use commands::describe_duration;
+use commands::is_nick;
+use commands::parse_duration;
+use environment::Env;
+use irc::casemap::CaseMapping;
+use network::Network;
+use network::Output;
+use scheduler::unix_time;
+use storage::Error;
+use storage::Namespace;
+use storage::Storage;
+use storage::Value;
+
+const DEFAULT_MAX_PER_USER: i64 = 10;
+
+/// How far ahead reminders can be set.
+const MAX_DELAY: u64 = 366 * 24 * 60 * 60;
+
+/// How long to wait before trying again if storage isn't working.
+const RETRY_DELAY: u64 = 60;
+
+/// Where the next reminder's id is kept. Reminders themselves are kept under
+/// `reminder/<due> <id>`, so that the next one due comes first.
+const NEXT_ID: &'static str = "next-id";
+const REMINDER_PREFIX: &'static str = "reminder/";
+
+struct Reminder {
+    id: u64,
+    due: u64,
+    from: String,
+    /// Who set the reminder, casefolded.
+    sender: String,
+    /// Where the reminder is sent, a channel or a nick.
+    target: String,
+    /// Who the reminder is for, or `None` if it's for a whole channel.
+    nick: Option<String>,
+    text: String,
+}
+
+impl Value for Reminder {
+    fn encode(&self) -> Vec<u8> {
+        (
+            (self.id, self.due),
+            (self.from.clone(), self.sender.clone()),
+            (self.target.clone(), self.nick.clone()),
+            self.text.clone(),
+        ).encode()
+    }
+
+    fn decode(raw: &[u8]) -> Option<Reminder> {
+        Value::decode(raw).map(|((id, due), (from, sender), (target, nick), text)| Reminder {
+            id: id,
+            due: due,
+            from: from,
+            sender: sender,
+            target: target,
+            nick: nick,
+            text: text,
+        })
+    }
+}
+
+impl Reminder {
+    fn key(&self) -> String {
+        format!("{}{:012} {:010}", REMINDER_PREFIX, self.due, self.id)
+    }
+
+    fn is_for_sender(&self, casemap: CaseMapping) -> bool {
+        self.nick.as_ref().map(|nick| casemap.fold(nick) == self.sender).unwrap_or(false)
+    }
+
+    /// Who the reminder is for, from the point of view of whoever set it.
+    fn whom(&self, casemap: CaseMapping) -> &str {
+        match self.nick {
+            Some(_) if self.is_for_sender(casemap) => "you",
+            Some(ref nick) => nick,
+            None => &self.target,
+        }
+    }
+
+    /// The line to send when the reminder is due.
+    fn line(&self, casemap: CaseMapping, now: u64) -> String {
+        let mut line = match self.nick {
+            Some(_) if self.is_for_sender(casemap) => format!("reminder: {}", self.text),
+            Some(_) => format!("{} asked me to remind you: {}", self.from, self.text),
+            None => format!("reminder from {}: {}", self.from, self.text),
+        };
+
+        let late = now.saturating_sub(self.due);
+        if late >= 60 {
+            line.push_str(&format!(" (sorry, that's {} late)",
+                describe_duration(Duration::from_secs(late))));
+        }
+        line
+    }
+}
+
+/// The reminders, and when the next one is due.
+struct Reminders {
+    ns: Namespace,
+    clock: fn() -> u64,
+    max_per_user: usize,
+    next_due: Cell<Option<u64>>,
+}
+
+impl Reminders {
+    /// Every reminder, soonest first.
+    fn all(&self) -> Result<Vec<Reminder>, Error> {
+        let all: Vec<(String, Reminder)> = try!(self.ns.scan(REMINDER_PREFIX));
+        Ok(all.into_iter().map(|(_, r)| r).collect())
+    }
+
+    fn refresh(&self) {
+        let next = match self.all() {
+            Ok(all) => all.first().map(|r| r.due),
+            Err(e) => {
+                warn!("couldn't look up reminders: {}", e);
+                Some((self.clock)() + RETRY_DELAY)
+            },
+        };
+        self.next_due.set(next);
+    }
+
+    /// Saves a reminder, giving it an id, unless whoever set it already has
+    /// too many.
+    fn add(&self, mut reminder: Reminder) -> Result<u64, Error> {
+        let id = try!(self.ns.transaction(|tx| {
+            let all: Vec<(String, Reminder)> = try!(tx.scan(REMINDER_PREFIX));
+            if all.iter().filter(|&&(_, ref r)| r.sender == reminder.sender).count()
+                    >= self.max_per_user {
+                return Err(Error::Aborted(format!("you already have {} reminders set",
+                    self.max_per_user)));
+            }
+
+            reminder.id = try!(tx.get(NEXT_ID)).unwrap_or(1);
+            tx.put(NEXT_ID, &(reminder.id + 1));
+            tx.put(&reminder.key(), &reminder);
+            Ok(reminder.id)
+        }));
+        self.refresh();
+        Ok(id)
+    }
+
+    /// Forgets a reminder, if `sender` set it or `any` is true.
+    fn cancel(&self, id: u64, sender: &str, any: bool) -> Result<(), Error> {
+        try!(self.ns.transaction(|tx| {
+            let all: Vec<(String, Reminder)> = try!(tx.scan(REMINDER_PREFIX));
+            match all.iter().find(|&&(_, ref r)| r.id == id) {
+                Some(&(ref key, ref r)) if any || r.sender == sender => {
+                    tx.delete(key);
+                    Ok(())
+                },
+                Some(_) => Err(Error::Aborted(format!("#{} isn't yours to cancel", id))),
+                None => Err(Error::Aborted(format!("there's no reminder #{}", id))),
+            }
+        }));
+        self.refresh();
+        Ok(())
+    }
+
+    /// Sends every reminder that's due by `now`.
+    fn fire(&self, net: &Network, out: &mut Output, now: u64) {
+        let due: Vec<Reminder> = match self.all() {
+            Ok(all) => all.into_iter().take_while(|r| r.due <= now).collect(),
+            Err(e) => {
+                warn!("couldn't look up reminders: {}", e);
+                self.next_due.set(Some(now + RETRY_DELAY));
+                return;
+            },
+        };
+
+        // forget them first, so that they aren't sent over and over if
+        // something's wrong with storage
+        let cleared = self.ns.transaction(|tx| {
+            for reminder in &due {
+                tx.delete(&reminder.key());
+            }
+            Ok(())
+        });
+        if let Err(e) = cleared {
+            warn!("couldn't clear reminders: {}", e);
+            self.next_due.set(Some(now + RETRY_DELAY));
+            return;
+        }
+
+        let casemap = net.server_info().casemapping();
+        for reminder in &due {
+            debug!("sending reminder #{} to {}", reminder.id, reminder.target);
+            match reminder.nick {
+                Some(ref nick) if net.server_info().is_channel(&reminder.target) => {
+                    out.PRIVMSG(&reminder.target, &format!("{}: {}", nick,
+                        reminder.line(casemap, now)));
+                },
+                _ => out.PRIVMSG(&reminder.target, &reminder.line(casemap, now)),
+            }
+        }
+
+        self.refresh();
+    }
+}
+
+pub struct Remind {
+    reminders: Rc<Reminders>,
+}
+
+impl Remind {
+    pub fn new(env: &Env, storage: &Storage) -> Remind {
+        Remind::with_clock(env, storage, unix_time)
+    }
+
+    fn with_clock(env: &Env, storage: &Storage, clock: fn() -> u64) -> Remind {
+        let max = env.conf_integer("remind.max_per_user").unwrap_or(DEFAULT_MAX_PER_USER);
+        let reminders = Reminders {
+            ns: storage.namespace("remind"),
+            clock: clock,
+            max_per_user: max.max(0) as usize,
+            next_due: Cell::new(None),
+        };
+        reminders.refresh();
+        Remind { reminders: Rc::new(reminders) }
+    }
+}
+
+impl Plugin for Remind {
+    fn name(&self) -> &str {
+        "remind"
+    }
+
+    fn commands(&self) -> Vec<Box<Command>> {
+        vec![
+            Box::new(RemindCommand(self.reminders.clone())),
+            Box::new(RemindersCommand(self.reminders.clone())),
+        ]
+    }
+
+    fn next_due(&self) -> Option<u64> {
+        self.reminders.next_due.get()
+    }
+
+    fn tick(&self, net: &Network, out: &mut Output, now: u64) {
+        self.reminders.fire(net, out, now);
+    }
+}
+
+struct RemindCommand(Rc<Reminders>);
+
+impl Command for RemindCommand {
+    fn name(&self) -> &str {
+        "remind"
+    }
+
+    fn help(&self) -> &str {
+        "reminds you, someone else or a channel about something later. times are in UTC"
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().required("who", Kind::Word).required("when", Kind::Rest))
+    }
+
+    fn usage(&self) -> String {
+        "<me|nick|#channel> <in 2h|at 17:00> <message...>".to_string()
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let who = args.get_str("who").unwrap_or("");
+        let now = (self.0.clock)();
+        let (due, text) = match parse_when(args.get_str("when").unwrap_or(""), now) {
+            Ok(when) => when,
+            Err(e) => return ctx.reply_error(&format!("{} (usage: remind {})", e, self.usage())),
+        };
+
+        let (reminder, casemap) = {
+            let (net, sender) = match (ctx.network(), ctx.sender()) {
+                (Some(net), Some(sender)) => (net, sender),
+                _ => return,
+            };
+            let chan = ctx.channel_name();
+
+            let (target, nick) = if who.eq_ignore_ascii_case("me") {
+                (chan.unwrap_or(sender), Some(sender))
+            } else if net.server_info().is_channel(who) {
+                if net.channel(who).is_none() {
+                    let line = format!("i'm not in {}", who);
+                    return ctx.reply_error(&line);
+                }
+                (who, None)
+            } else if is_nick(who) {
+                (chan.unwrap_or(who), Some(who))
+            } else {
+                let line = format!("{} isn't a nick or a channel", who);
+                return ctx.reply_error(&line);
+            };
+
+            let casemap = net.server_info().casemapping();
+            (Reminder {
+                id: 0,
+                due: due,
+                from: sender.to_string(),
+                sender: casemap.fold(sender),
+                target: target.to_string(),
+                nick: nick.map(|n| n.to_string()),
+                text: text.to_string(),
+            }, casemap)
+        };
+
+        let whom = reminder.whom(casemap).to_string();
+        match self.0.add(reminder) {
+            Ok(id) => ctx.reply(&format!("ok, i'll remind {} in {} (#{})",
+                whom, describe_duration(Duration::from_secs(due - now)), id)),
+            Err(Error::Aborted(why)) => ctx.reply_error(&why),
+            Err(e) => {
+                warn!("couldn't save a reminder: {}", e);
+                ctx.reply_error("i can't take reminders right now, sorry");
+            },
+        }
+    }
+}
+
+struct RemindersCommand(Rc<Reminders>);
+
+impl Command for RemindersCommand {
+    fn name(&self) -> &str {
+        "reminders"
+    }
+
+    fn help(&self) -> &str {
+        "lists the reminders you've set, or cancels one by its number"
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().optional("cancel", Kind::Word).optional("id", Kind::Int))
+    }
+
+    fn usage(&self) -> String {
+        "[cancel <id>]".to_string()
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let sender = match (ctx.network(), ctx.sender()) {
+            (Some(net), Some(sender)) => net.server_info().casemapping().fold(sender),
+            _ => return,
+        };
+
+        let action = args.get_str("cancel").map(|a| a.to_ascii_lowercase());
+        match (action, args.get_int("id")) {
+            (None, _) => self.list(ctx, &sender),
+            (Some(ref action), Some(id)) if action == "cancel" => {
+                let admin = ctx.registry().map(|r| r.acl().level_of(&*ctx) >= Level::Admin);
+                match self.0.cancel(id as u64, &sender, admin.unwrap_or(false)) {
+                    Ok(()) => ctx.reply(&format!("cancelled #{}", id)),
+                    Err(Error::Aborted(why)) => ctx.reply_error(&why),
+                    Err(e) => {
+                        warn!("couldn't cancel reminder #{}: {}", id, e);
+                        ctx.reply_error("i can't cancel reminders right now, sorry");
+                    },
+                }
+            },
+            (Some(ref action), None) if action == "cancel" =>
+                ctx.reply_error("which one? (usage: reminders cancel <id>)"),
+            (Some(action), _) =>
+                ctx.reply_error(&format!("i can list or cancel reminders, not {}", action)),
+        }
+    }
+}
+
+impl RemindersCommand {
+    fn list(&self, ctx: &mut Context, sender: &str) {
+        let casemap = match ctx.network() {
+            Some(net) => net.server_info().casemapping(),
+            None => return,
+        };
+        let mine: Vec<Reminder> = match self.0.all() {
+            Ok(all) => all.into_iter().filter(|r| r.sender == sender).collect(),
+            Err(e) => {
+                warn!("couldn't look up reminders: {}", e);
+                return ctx.reply_error("i can't find your reminders right now, sorry");
+            },
+        };
+
+        if mine.is_empty() {
+            return ctx.reply("you don't have any reminders set");
+        }
+        let now = (self.0.clock)();
+        for r in &mine {
+            let left = describe_duration(Duration::from_secs(r.due.saturating_sub(now)));
+            ctx.reply_private(&format!("#{} in {} for {}: {}", r.id, left, r.whom(casemap),
+                r.text));
+        }
+    }
+}
+
+/// Splits the first word off of `s`.
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_left();
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    (&s[..end], s[end..].trim_left())
+}
+
+/// Reads a length of time off the front of `s`, written like `2h`, `2 hours`
+/// or `an hour`, returning it in seconds.
+fn duration_at(s: &str) -> Option<(u64, &str)> {
+    let (first, rest) = split_word(s);
+    if let Some(d) = parse_duration(first) {
+        return Some((d.as_secs(), rest));
+    }
+
+    let n = match &first.to_ascii_lowercase()[..] {
+        "a" | "an" => "1",
+        n if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => first,
+        _ => return None,
+    };
+    let (unit, rest) = split_word(rest);
+    parse_duration(&format!("{}{}", n, unit.trim_right_matches(','))).map(|d| (d.as_secs(), rest))
+}
+
+/// Reads a time of day like `17:00` or `9:30:15`, in seconds since midnight.
+fn parse_time(s: &str) -> Option<u64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+
+    let mut secs = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let n: u64 = match part.parse() {
+            Ok(n) if part.len() <= 2 => n,
+            _ => return None,
+        };
+        let max = if i == 0 { 24 } else { 60 };
+        if n >= max {
+            return None;
+        }
+        secs = secs * 60 + n;
+    }
+    if parts.len() == 2 {
+        secs *= 60;
+    }
+    Some(secs)
+}
+
+/// Reads a date like `2026-12-25`, in days since the Unix epoch. Years
+/// before 1970 or after 9999 aren't accepted.
+fn parse_date(s: &str) -> Option<u64> {
+    let parts: Vec<&str> = s.split('-').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let (y, m, d): (i64, u32, u32) = match (parts[0].parse(), parts[1].parse(), parts[2].parse()) {
+        (Ok(y), Ok(m), Ok(d)) => (y, m, d),
+        _ => return None,
+    };
+    if y < 1970 || y > 9999 {
+        return None;
+    }
+
+    let leap = y % 4 == 0 && (y % 100 != 0 || y % 400 == 0);
+    let month_len = match m {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if d < 1 || d > month_len {
+        return None;
+    }
+
+    // from Howard Hinnant's days_from_civil
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = if m > 2 { m - 3 } else { m + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some((era * 146097 + doe - 719468) as u64)
+}
+
+/// Works out when a reminder is for and what it says, from something like
+/// `in 2 hours to deploy` or `at 17:00 UTC standup`.
+fn parse_when(s: &str, now: u64) -> Result<(u64, &str), String> {
+    let (word, mut rest) = split_word(s);
+
+    let due = match &word.to_ascii_lowercase()[..] {
+        "in" => {
+            let mut total = None;
+            loop {
+                // "1 hour and 30 minutes"
+                let (next, after) = split_word(rest);
+                let and = next.eq_ignore_ascii_case("and");
+                if total.is_some() && and && duration_at(after).is_some() {
+                    rest = after;
+                    continue;
+                }
+                match duration_at(rest) {
+                    Some((secs, after)) => {
+                        total = Some(total.unwrap_or(0u64).saturating_add(secs));
+                        rest = after;
+                    },
+                    None => break,
+                }
+            }
+            match total {
+                Some(secs) => now.saturating_add(secs),
+                None => return Err("how long from now? like 10m or 2 hours".to_string()),
+            }
+        },
+
+        "at" => {
+            let (first, after) = split_word(rest);
+            let (date, time, after) = match parse_date(first) {
+                Some(date) => {
+                    let (time, after) = split_word(after);
+                    (Some(date), time, after)
+                },
+                None => (None, first, after),
+            };
+
+            let secs = match parse_time(time) {
+                Some(secs) => secs,
+                None => return Err("what time? like 17:00, in UTC".to_string()),
+            };
+            let (zone, after_zone) = split_word(after);
+            rest = match &zone.to_ascii_uppercase()[..] {
+                "UTC" | "GMT" | "Z" => after_zone,
+                _ => after,
+            };
+
+            match date {
+                Some(days) => {
+                    let due = days.checked_mul(24 * 60 * 60).and_then(|d| d.checked_add(secs));
+                    match due {
+                        Some(due) if due > now => due,
+                        Some(_) => return Err("that's already happened".to_string()),
+                        None => return Err("that's too far away".to_string()),
+                    }
+                },
+                None => {
+                    let today = now - now % (24 * 60 * 60) + secs;
+                    if today <= now { today + 24 * 60 * 60 } else { today }
+                },
+            }
+        },
+
+        _ => return Err("when? like in 10m, or at 17:00".to_string()),
+    };
+
+    if due - now > MAX_DELAY {
+        return Err("that's too far away, i can only remember things for a year".to_string());
+    }
+
+    let (to, after) = split_word(rest);
+    if to.eq_ignore_ascii_case("to") && !after.is_empty() {
+        rest = after;
+    }
+    if rest.is_empty() {
+        return Err("what should i remind about?".to_string());
+    }
+
+    Ok((due, rest))
+}
+
+#[test]
+fn remind_parses_times() {
+    // 2026-10-18 12:00:00 UTC
+    let today = parse_date("2026-10-18").unwrap() * 86400;
+    let now = today + 12 * 3600;
+
+    assert_eq!(parse_date("1970-01-01"), Some(0));
+    assert_eq!(parse_date("2000-03-01"), Some(11017));
+    assert_eq!(parse_date("2024-02-29"), Some(19782));
+    assert_eq!(parse_date("2026-02-29"), None);
+    assert_eq!(parse_date("1969-12-31"), None);
+    assert_eq!(parse_date("10000-01-01"), None);
+    assert_eq!(parse_date("100000000000000000-01-01"), None);
+    assert_eq!(parse_time("9:30:15"), Some(34215));
+    assert_eq!(parse_time("24:00"), None);
+
+    assert_eq!(parse_when("in 2h to deploy", now), Ok((now + 7200, "deploy")));
+    assert_eq!(parse_when("IN 1 hour and 30 minutes the party", now), Ok((now + 5400, "the party")));
+    assert_eq!(parse_when("in an hour, 5m to  stretch", now), Ok((now + 3900, "stretch")));
+    assert_eq!(parse_when("at 17:00 UTC standup", now), Ok((today + 17 * 3600, "standup")));
+    assert_eq!(parse_when("at 9:30 to eat", now), Ok((today + 86400 + 34200, "eat")));
+    assert_eq!(parse_when("at 2026-12-25 09:00 presents!", now),
+        Ok((parse_date("2026-12-25").unwrap() * 86400 + 9 * 3600, "presents!")));
+    assert_eq!(parse_when("at 17:00 utcnow is a word", now), Ok((today + 17 * 3600, "utcnow is a word")));
+
+    assert!(parse_when("in 5 to deploy", now).is_err());
+    assert!(parse_when("at 25:00 dance", now).is_err());
+    assert!(parse_when("tomorrow dance", now).is_err());
+    assert!(parse_when("in 400d dance", now).is_err());
+    assert_eq!(parse_when("at 2020-01-01 10:00 dance", now), Err("that's already happened".to_string()));
+    assert!(parse_when("at 100000000000000000-01-01 10:00 x", now).is_err());
+    assert!(parse_when("at 1000000000000-01-01 10:00 x", now).is_err());
+    assert!(parse_when("at 9999-12-31 23:59 x", now).is_err());
+    assert_eq!(parse_when("in 2h to", now), Ok((now + 7200, "to")));
+    assert_eq!(parse_when("in 2h", now), Err("what should i remind about?".to_string()));
+}
+
+#[cfg(test)]
+const TEST_CONFIG: &'static str = r##"
+    [irc]
+    nick = "miau"
+    channels = []
+
+    [remind]
+    max_per_user = 2
+
+    [acl]
+    admins = ["rarity!*@*"]
+"##;
+
+#[cfg(test)]
+fn test_registry(storage: &Storage) -> ::commands::CommandRegistry {
+    ::commands::registry_with(TEST_CONFIG, storage, Remind::with_clock)
+}
+
+/// Sends whatever reminders are due at `now`.
+#[cfg(test)]
+fn fire_at(registry: &::commands::CommandRegistry, now: u64) -> Vec<String> {
+    let mut out = Vec::new();
+    let net = Network::register(::environment::from_str(TEST_CONFIG), &mut Vec::new());
+    ::commands::set_test_time(now);
+    registry.tick(&net, &mut out, now);
+    out
+}
+
+#[test]
+fn remind_sets_reminders() {
+    use commands::replies_from;
+
+    let out = replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":[miau]!m@h JOIN #miau-dev",
+        ":aji!a@h PRIVMSG #miau-dev :!remind me in 2h to deploy",
+        ":aji!a@h PRIVMSG #miau-dev :!remind #miau-dev in 1 hour standup",
+        ":pinkie!p@h PRIVMSG [miau] :remind aji in 10m hi",
+        ":pinkie!p@h PRIVMSG [miau] :remind PINKIE in 1d cake",
+        ":aji!a@h PRIVMSG #miau-dev :!reminders",
+    ]);
+
+    // after the MODE sent when joining
+    assert_eq!(&out[1..], &[
+        "PRIVMSG #miau-dev :aji: ok, i'll remind you in 2 hours (#1)",
+        "PRIVMSG #miau-dev :aji: ok, i'll remind #miau-dev in 1 hour (#2)",
+        "NOTICE pinkie :ok, i'll remind aji in 10 minutes (#3)",
+        "NOTICE pinkie :ok, i'll remind you in 1 day (#4)",
+        "NOTICE aji :#2 in 1 hour for #miau-dev: standup",
+        "NOTICE aji :#1 in 2 hours for you: deploy",
+    ]);
+}
+
+#[test]
+fn remind_rejects_bad_reminders() {
+    use commands::replies_from;
+
+    assert_eq!(replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":rarity!r@h PRIVMSG [miau] :remind #elsewhere in 1h hello",
+        ":rarity!r@h PRIVMSG [miau] :remind a,b,c in 1m spam",
+        ":rarity!r@h PRIVMSG [miau] :remind me soon hello",
+        ":rarity!r@h PRIVMSG [miau] :remind me in 2h",
+    ]), vec![
+        "NOTICE rarity :i'm not in #elsewhere",
+        "NOTICE rarity :a,b,c isn't a nick or a channel",
+        "NOTICE rarity :when? like in 10m, or at 17:00 \
+            (usage: remind <me|nick|#channel> <in 2h|at 17:00> <message...>)",
+        "NOTICE rarity :what should i remind about? \
+            (usage: remind <me|nick|#channel> <in 2h|at 17:00> <message...>)",
+    ]);
+}
+
+#[test]
+fn remind_limits_reminders() {
+    use commands::replies_from;
+
+    assert_eq!(replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":aji!a@h PRIVMSG [miau] :remind me in 1h one",
+        ":AJI!a@h PRIVMSG [miau] :remind me in 2h two",
+        ":aji!a@h PRIVMSG [miau] :remind pinkie in 3h three",
+        ":pinkie!p@h PRIVMSG [miau] :remind aji in 4h four",
+    ]), vec![
+        "NOTICE aji :ok, i'll remind you in 1 hour (#1)",
+        "NOTICE AJI :ok, i'll remind you in 2 hours (#2)",
+        "NOTICE aji :you already have 2 reminders set",
+        "NOTICE pinkie :ok, i'll remind aji in 4 hours (#3)",
+    ]);
+}
+
+#[test]
+fn remind_cancels_reminders() {
+    use commands::replies_from;
+
+    assert_eq!(replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":aji!a@h PRIVMSG [miau] :remind me in 1h one",
+        ":pinkie!p@h PRIVMSG [miau] :remind me in 2h two",
+        ":pinkie!p@h PRIVMSG [miau] :reminders cancel 1",
+        ":pinkie!p@h PRIVMSG [miau] :reminders cancel 2",
+        ":rarity!r@h PRIVMSG [miau] :reminders cancel 1",
+        ":rarity!r@h PRIVMSG [miau] :reminders cancel 9",
+        ":rarity!r@h PRIVMSG [miau] :reminders cancel",
+        ":aji!a@h PRIVMSG [miau] :reminders",
+    ]), vec![
+        "NOTICE aji :ok, i'll remind you in 1 hour (#1)",
+        "NOTICE pinkie :ok, i'll remind you in 2 hours (#2)",
+        "NOTICE pinkie :#1 isn't yours to cancel",
+        "NOTICE pinkie :cancelled #2",
+        "NOTICE rarity :cancelled #1",
+        "NOTICE rarity :there's no reminder #9",
+        "NOTICE rarity :which one? (usage: reminders cancel <id>)",
+        "NOTICE aji :you don't have any reminders set",
+    ]);
+}
+
+#[test]
+fn remind_fires_reminders() {
+    use commands::replies_from;
+
+    let registry = test_registry(&Storage::memory());
+    replies_from(&registry, TEST_CONFIG, &[
+        ":pinkie!p@h PRIVMSG [miau] :remind aji in 10m hi",
+        ":aji!a@h PRIVMSG [miau] :remind me in 20m to deploy",
+    ]);
+
+    assert_eq!(registry.next_due(), Some(1000600));
+    assert!(fire_at(&registry, 1000599).is_empty());
+    assert_eq!(fire_at(&registry, 1000600), vec!["PRIVMSG aji :pinkie asked me to remind you: hi"]);
+    assert_eq!(registry.next_due(), Some(1001200));
+    assert_eq!(fire_at(&registry, 1001200), vec!["PRIVMSG aji :reminder: deploy"]);
+    assert_eq!(registry.next_due(), None);
+}
+
+#[test]
+fn remind_sends_overdue_reminders_after_restart() {
+    use commands::replies_from;
+
+    let storage = Storage::memory();
+    replies_from(&test_registry(&storage), TEST_CONFIG, &[
+        ":[miau]!m@h JOIN #miau-dev",
+        ":aji!a@h PRIVMSG #miau-dev :!remind me in 2h to deploy",
+        ":aji!a@h PRIVMSG #miau-dev :!remind #miau-dev in 1 hour standup",
+    ]);
+
+    let now = 1000000 + 3 * 3600;
+    ::commands::set_test_time(now);
+    let registry = test_registry(&storage);
+    assert_eq!(registry.next_due(), Some(1003600));
+    assert_eq!(fire_at(&registry, now), vec![
+        "PRIVMSG #miau-dev :reminder from aji: standup (sorry, that's 2 hours late)",
+        "PRIVMSG #miau-dev :aji: reminder: deploy (sorry, that's 1 hour late)",
+    ]);
+    assert_eq!(registry.next_due(), None);
+}
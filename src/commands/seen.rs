@@ -0,0 +1,313 @@
+//! Remembering when people were last around, and what they were doing.
+//!
+//! What people say to us in private, and anything that happens in secret
+//! (`+s`) or private (`+p`) channels, isn't remembered, so that it can't be
+//! repeated to someone who asks.
+
+use commands::Args;
+use commands::Command;
+use commands::Context;
+use commands::Kind;
+use commands::Plugin;
+use commands::Spec;
+use commands::describe_ago;
+use irc::Command as IrcCommand;
+use irc::OwnedMessage;
+use irc::OwnedSource;
+use network::Network;
+use network::Output;
+use scheduler::unix_time;
+use storage::Namespace;
+use storage::Storage;
+use storage::Value;
+
+/// Messages longer than this are cut short when repeated.
+const MAX_QUOTE_LEN: usize = 150;
+
+pub struct Seen {
+    ns: Namespace,
+    clock: fn() -> u64,
+}
+
+impl Seen {
+    pub fn new(storage: &Storage) -> Seen {
+        Seen::with_clock(storage, unix_time)
+    }
+
+    fn with_clock(storage: &Storage, clock: fn() -> u64) -> Seen {
+        Seen { ns: storage.namespace("seen"), clock: clock }
+    }
+
+    fn record(&self, net: &Network, nick: &str, chan: Option<&str>, activity: Activity) {
+        let key = net.server_info().casemapping().fold(nick);
+        let sighting = Sighting {
+            time: (self.clock)(),
+            nick: nick.to_string(),
+            chan: chan.map(|c| c.to_string()),
+            activity: activity,
+        };
+
+        if let Err(e) = self.ns.put(&key, &sighting) {
+            warn!("couldn't remember seeing {}: {}", nick, e);
+        }
+    }
+}
+
+/// The last thing someone was seen doing.
+struct Sighting {
+    time: u64,
+    nick: String,
+    chan: Option<String>,
+    activity: Activity,
+}
+
+enum Activity {
+    Message(String),
+    Join,
+    Part(Option<String>),
+    Quit(Option<String>),
+    NickTo(String),
+    NickFrom(String),
+}
+
+impl Value for Sighting {
+    fn encode(&self) -> Vec<u8> {
+        let (kind, detail) = match self.activity {
+            Activity::Message(ref text) => ("message", Some(text)),
+            Activity::Join => ("join", None),
+            Activity::Part(ref reason) => ("part", reason.as_ref()),
+            Activity::Quit(ref reason) => ("quit", reason.as_ref()),
+            Activity::NickTo(ref nick) => ("nick-to", Some(nick)),
+            Activity::NickFrom(ref nick) => ("nick-from", Some(nick)),
+        };
+        (self.time, self.nick.clone(), self.chan.clone(), kind.to_string(), detail.cloned())
+            .encode()
+    }
+
+    fn decode(raw: &[u8]) -> Option<Sighting> {
+        let (time, nick, chan, kind, detail): (u64, String, Option<String>, String, Option<String>)
+            = match Value::decode(raw) {
+                Some(parts) => parts,
+                None => return None,
+            };
+
+        let activity = match (&kind[..], detail) {
+            ("message", Some(text)) => Activity::Message(text),
+            ("join", _) => Activity::Join,
+            ("part", reason) => Activity::Part(reason),
+            ("quit", reason) => Activity::Quit(reason),
+            ("nick-to", Some(nick)) => Activity::NickTo(nick),
+            ("nick-from", Some(nick)) => Activity::NickFrom(nick),
+            _ => return None,
+        };
+
+        Some(Sighting { time: time, nick: nick, chan: chan, activity: activity })
+    }
+}
+
+impl Plugin for Seen {
+    fn name(&self) -> &str {
+        "seen"
+    }
+
+    fn commands(&self) -> Vec<Box<Command>> {
+        vec![Box::new(SeenCommand { ns: self.ns.clone(), clock: self.clock })]
+    }
+
+    fn observe(&self, net: &Network, _out: &mut Output, m: &OwnedMessage) {
+        let nick = match m.src {
+            OwnedSource::User(ref nick, _, _) => &nick[..],
+            _ => return,
+        };
+        let info = net.server_info();
+        let secret = |chan: &str| net.channel(chan)
+            .map(|c| c.has_mode('s') || c.has_mode('p'))
+            .unwrap_or(false);
+
+        match m.command {
+            IrcCommand::Privmsg(ref target, ref text) => {
+                let chan = info.split_statusmsg(target).1;
+                // what people say to us in private stays private
+                if info.is_channel(chan) && !secret(chan) {
+                    self.record(net, nick, Some(chan), Activity::Message(text.clone()));
+                }
+            },
+            IrcCommand::Join(ref chan, _) if !secret(chan) =>
+                self.record(net, nick, Some(chan), Activity::Join),
+            IrcCommand::Part(ref chan, ref reason) if !secret(chan) =>
+                self.record(net, nick, Some(chan), Activity::Part(reason.clone())),
+            IrcCommand::Quit(ref reason) =>
+                self.record(net, nick, None, Activity::Quit(reason.clone())),
+            IrcCommand::Nick(ref new) => {
+                self.record(net, nick, None, Activity::NickTo(new.clone()));
+                self.record(net, new, None, Activity::NickFrom(nick.to_string()));
+            },
+            _ => {},
+        }
+    }
+}
+
+struct SeenCommand {
+    ns: Namespace,
+    clock: fn() -> u64,
+}
+
+impl Command for SeenCommand {
+    fn name(&self) -> &str {
+        "seen"
+    }
+
+    fn help(&self) -> &str {
+        "says when someone was last around, and what they were doing"
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().required("nick", Kind::Nick))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let nick = args.get_str("nick").unwrap_or("");
+        let (key, me) = match ctx.network() {
+            Some(net) => (
+                net.server_info().casemapping().fold(nick),
+                net.current_nick().map(|me| net.server_info().casemapping().equals(me, nick)),
+            ),
+            None => return,
+        };
+
+        if me == Some(true) {
+            return ctx.reply("i'm right here");
+        }
+
+        let sighting: Sighting = match self.ns.get(&key) {
+            Ok(Some(sighting)) => sighting,
+            Ok(None) => return ctx.reply(&format!("i haven't seen {}", nick)),
+            Err(e) => {
+                warn!("couldn't look up {}: {}", nick, e);
+                return ctx.reply_error("i can't remember right now, sorry");
+            },
+        };
+
+        let when = describe_ago(sighting.time, (self.clock)());
+        ctx.reply(&format!("{} was last seen {} {}", sighting.nick, when, describe(&sighting)));
+    }
+}
+
+/// Describes what someone was doing, like `in #miau-dev, saying "hi"`.
+fn describe(sighting: &Sighting) -> String {
+    let chan = sighting.chan.as_ref().map(|c| &c[..]).unwrap_or("somewhere");
+    let because = |reason: &Option<String>| match *reason {
+        Some(ref reason) if !reason.is_empty() => format!(" ({})", quote(reason)),
+        _ => String::new(),
+    };
+
+    match sighting.activity {
+        Activity::Message(ref text) if text.starts_with("\x01ACTION ") => {
+            let action = text["\x01ACTION ".len()..].trim_right_matches('\x01');
+            format!("in {}: * {} {}", chan, sighting.nick, quote(action))
+        },
+        Activity::Message(ref text) => format!("in {}, saying \"{}\"", chan, quote(text)),
+        Activity::Join => format!("joining {}", chan),
+        Activity::Part(ref reason) => format!("leaving {}{}", chan, because(reason)),
+        Activity::Quit(ref reason) => format!("quitting{}", because(reason)),
+        Activity::NickTo(ref nick) => format!("changing their nick to {}", nick),
+        Activity::NickFrom(ref nick) => format!("changing their nick from {}", nick),
+    }
+}
+
+/// Cuts a message short if it's too long to repeat in full.
+fn quote(text: &str) -> String {
+    match text.char_indices().nth(MAX_QUOTE_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+const TEST_CONFIG: &'static str = r##"
+    [irc]
+    nick = "miau"
+    channels = []
+"##;
+
+#[cfg(test)]
+fn test_registry(storage: &Storage) -> ::commands::CommandRegistry {
+    ::commands::registry_with(TEST_CONFIG, storage, |_, storage, clock| {
+        Seen::with_clock(storage, clock)
+    })
+}
+
+#[test]
+fn seen_records_activity() {
+    use commands::replies_from;
+
+    let storage = Storage::memory();
+    replies_from(&test_registry(&storage), TEST_CONFIG, &[
+        ":aji!a@h JOIN #miau-dev",
+        ":aji!a@h PRIVMSG #miau-dev :hello everypony",
+        ":rarity!r@h PART #miau-dev :off to the boutique",
+        ":pinkie!p@h QUIT",
+        ":rainbow!r@h NICK dash",
+    ]);
+
+    // a new registry, like after a restart
+    let out = replies_from(&test_registry(&storage), TEST_CONFIG, &[
+        ":spike!s@h PRIVMSG #miau-dev :!seen AJI",
+        ":spike!s@h PRIVMSG #miau-dev :!seen rarity",
+        ":spike!s@h PRIVMSG #miau-dev :!seen pinkie",
+        ":spike!s@h PRIVMSG #miau-dev :!seen rainbow",
+        ":spike!s@h PRIVMSG #miau-dev :!seen Dash",
+    ]);
+
+    assert_eq!(out, vec![
+        "PRIVMSG #miau-dev :spike: aji was last seen just now in #miau-dev, saying \"hello everypony\"",
+        "PRIVMSG #miau-dev :spike: rarity was last seen just now leaving #miau-dev (off to the boutique)",
+        "PRIVMSG #miau-dev :spike: pinkie was last seen just now quitting",
+        "PRIVMSG #miau-dev :spike: rainbow was last seen just now changing their nick to dash",
+        "PRIVMSG #miau-dev :spike: dash was last seen just now changing their nick from rainbow",
+    ]);
+}
+
+#[test]
+fn seen_keeps_secrets() {
+    use commands::replies_from;
+
+    let out = replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":aji!a@h PRIVMSG #miau-dev :hello everypony",
+        ":aji!a@h PRIVMSG [miau] :this is a secret",
+        ":[miau]!m@h JOIN #secret",
+        ":irc.test 324 [miau] #secret +s",
+        ":aji!a@h JOIN #secret",
+        ":aji!a@h PRIVMSG #secret :so is this",
+        ":aji!a@h PART #secret",
+        ":spike!s@h PRIVMSG #miau-dev :!seen aji",
+    ]);
+
+    assert_eq!(out.last().map(|l| &l[..]),
+        Some("PRIVMSG #miau-dev :spike: aji was last seen just now in #miau-dev, saying \"hello everypony\""));
+}
+
+#[test]
+fn seen_describes_sightings() {
+    use commands::replies_from;
+
+    let storage = Storage::memory();
+    storage.namespace("seen").put("{twilight}", &Sighting {
+        time: ::commands::test_clock() - 7500,
+        nick: "[Twilight]".to_string(),
+        chan: Some("#library".to_string()),
+        activity: Activity::Message("\x01ACTION reads\x01".to_string()),
+    }).unwrap();
+
+    let out = replies_from(&test_registry(&storage), TEST_CONFIG, &[
+        ":spike!s@h PRIVMSG #miau-dev :!seen {twilight}",
+        ":spike!s@h PRIVMSG #miau-dev :!seen applejack",
+        ":spike!s@h PRIVMSG #miau-dev :!seen [MIAU]",
+    ]);
+
+    assert_eq!(out, vec![
+        "PRIVMSG #miau-dev :spike: [Twilight] was last seen 2 hours, 5 minutes ago in #library: * [Twilight] reads",
+        "PRIVMSG #miau-dev :spike: i haven't seen applejack",
+        "PRIVMSG #miau-dev :spike: i'm right here",
+    ]);
+}
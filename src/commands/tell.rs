@@ -0,0 +1,469 @@
+//! Leaving messages for people who aren't around.
+//!
+//! Memos are delivered the next time the person they're for says something
+//! or joins in a channel we're in, either in a private message or in that
+//! channel, depending on the config. Memos that were left in private are
+//! always delivered privately.
+//!
+//! ```toml
+//! [tell]
+//! deliver = "channel"      # or "private", the default
+//! max_per_sender = 5       # memos someone can have waiting to be delivered
+//! max_per_recipient = 10   # memos that can be waiting for one person
+//!
+//! [tell.channels."#busy"]
+//! deliver = "private"
+//! ```
+
+use std::rc::Rc;
+
+use commands::Args;
+use commands::Command;
+use commands::Context;
+use commands::Kind;
+use commands::Plugin;
+use commands::Spec;
+use commands::describe_ago;
+use environment::Env;
+use irc::Command as IrcCommand;
+use irc::OwnedMessage;
+use irc::OwnedSource;
+use irc::casemap::CaseMapping;
+use network::Network;
+use network::Output;
+use scheduler::unix_time;
+use storage::Error;
+use storage::Namespace;
+use storage::Storage;
+use storage::Value;
+
+const DEFAULT_MAX_PER_SENDER: i64 = 5;
+const DEFAULT_MAX_PER_RECIPIENT: i64 = 10;
+
+/// Where the next memo's id is kept. Memos themselves are kept under
+/// `memo/<recipient> <id>`, so that each person's are together and in order.
+const NEXT_ID: &'static str = "next-id";
+const MEMO_PREFIX: &'static str = "memo/";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Delivery {
+    Private,
+    Channel,
+}
+
+impl Delivery {
+    fn from_value(value: Option<&::toml::Value>) -> Option<Delivery> {
+        match value.and_then(|v| v.as_str()) {
+            Some("private") => Some(Delivery::Private),
+            Some("channel") => Some(Delivery::Channel),
+            Some(other) => {
+                warn!("ignoring tell delivery {:?}, it should be private or channel", other);
+                None
+            },
+            None => None,
+        }
+    }
+}
+
+struct Memo {
+    time: u64,
+    from: String,
+    /// Who left the memo, casefolded, for counting how many they've left.
+    sender: String,
+    chan: Option<String>,
+    text: String,
+}
+
+impl Value for Memo {
+    fn encode(&self) -> Vec<u8> {
+        (self.time, self.from.clone(), self.sender.clone(), self.chan.clone(), self.text.clone())
+            .encode()
+    }
+
+    fn decode(raw: &[u8]) -> Option<Memo> {
+        Value::decode(raw).map(|(time, from, sender, chan, text)| Memo {
+            time: time,
+            from: from,
+            sender: sender,
+            chan: chan,
+            text: text,
+        })
+    }
+}
+
+impl Memo {
+    fn describe(&self, now: u64) -> String {
+        let place = self.chan.as_ref().map(|c| format!(" in {}", c)).unwrap_or_default();
+        format!("{} left you a message{} {}: {}",
+            self.from, place, describe_ago(self.time, now), self.text)
+    }
+}
+
+/// The memos, and the rules for leaving and delivering them.
+struct Memos {
+    ns: Namespace,
+    clock: fn() -> u64,
+    max_per_sender: usize,
+    max_per_recipient: usize,
+    deliver: Delivery,
+    channels: Vec<(String, Delivery)>,
+}
+
+impl Memos {
+    fn delivery(&self, chan: &str, casemap: CaseMapping) -> Delivery {
+        self.channels.iter()
+            .find(|&&(ref name, _)| casemap.equals(name, chan))
+            .map(|&(_, deliver)| deliver)
+            .unwrap_or(self.deliver)
+    }
+
+    /// The memos waiting for someone, by their casefolded nick, oldest first.
+    fn pending(&self, to: &str) -> Result<Vec<(String, Memo)>, Error> {
+        self.ns.scan(&format!("{}{} ", MEMO_PREFIX, to))
+    }
+
+    /// Saves a memo for someone, unless either of them is over their limit,
+    /// in which case the error says why.
+    fn leave(&self, to: &str, nick: &str, memo: &Memo) -> Result<(), Error> {
+        self.ns.transaction(|tx| {
+            let all: Vec<(String, Memo)> = try!(tx.scan(MEMO_PREFIX));
+            let prefix = format!("{}{} ", MEMO_PREFIX, to);
+
+            if all.iter().filter(|&&(_, ref m)| m.sender == memo.sender).count()
+                    >= self.max_per_sender {
+                return Err(Error::Aborted(format!("you already have {} memos waiting to be \
+                    delivered", self.max_per_sender)));
+            }
+            if all.iter().filter(|&&(ref key, _)| key.starts_with(&prefix)).count()
+                    >= self.max_per_recipient {
+                return Err(Error::Aborted(format!("{} already has {} memos waiting",
+                    nick, self.max_per_recipient)));
+            }
+
+            let id: u64 = try!(tx.get(NEXT_ID)).unwrap_or(1);
+            tx.put(NEXT_ID, &(id + 1));
+            tx.put(&format!("{}{:010}", prefix, id), memo);
+            Ok(())
+        })
+    }
+
+    /// Forgets the memos with the given keys.
+    fn clear(&self, keys: &[String]) -> Result<(), Error> {
+        self.ns.transaction(|tx| {
+            for key in keys {
+                tx.delete(key);
+            }
+            Ok(())
+        })
+    }
+
+    /// Hands someone who's just turned up in `chan` whatever memos are
+    /// waiting for them.
+    fn deliver(&self, net: &Network, out: &mut Output, nick: &str, chan: &str) {
+        let casemap = net.server_info().casemapping();
+        let memos = match self.pending(&casemap.fold(nick)) {
+            Ok(ref memos) if memos.is_empty() => return,
+            Ok(memos) => memos,
+            Err(e) => {
+                warn!("couldn't look up memos for {}: {}", nick, e);
+                return;
+            },
+        };
+
+        // forget them first, since delivering them twice is worse than not
+        // at all if something's wrong with storage
+        let keys: Vec<String> = memos.iter().map(|&(ref key, _)| key.clone()).collect();
+        if let Err(e) = self.clear(&keys) {
+            warn!("couldn't clear memos for {}: {}", nick, e);
+            return;
+        }
+
+        let now = (self.clock)();
+        let deliver = self.delivery(chan, casemap);
+        for (_, memo) in memos {
+            // memos left in private stay private
+            match (deliver, memo.chan.is_some()) {
+                (Delivery::Channel, true) =>
+                    out.PRIVMSG(chan, &format!("{}: {}", nick, memo.describe(now))),
+                _ => out.PRIVMSG(nick, &memo.describe(now)),
+            }
+        }
+    }
+}
+
+pub struct Tell {
+    memos: Rc<Memos>,
+}
+
+impl Tell {
+    pub fn new(env: &Env, storage: &Storage) -> Tell {
+        Tell::with_clock(env, storage, unix_time)
+    }
+
+    fn with_clock(env: &Env, storage: &Storage, clock: fn() -> u64) -> Tell {
+        let limit = |path, default: i64| env.conf_integer(path).unwrap_or(default).max(0) as usize;
+        let channels = env.conf_table("tell.channels").map(|t| {
+            t.iter().filter_map(|(chan, rules)| {
+                Delivery::from_value(rules.get("deliver")).map(|d| (chan.clone(), d))
+            }).collect()
+        });
+
+        Tell {
+            memos: Rc::new(Memos {
+                ns: storage.namespace("tell"),
+                clock: clock,
+                max_per_sender: limit("tell.max_per_sender", DEFAULT_MAX_PER_SENDER),
+                max_per_recipient: limit("tell.max_per_recipient", DEFAULT_MAX_PER_RECIPIENT),
+                deliver: Delivery::from_value(env.conf("tell.deliver")).unwrap_or(Delivery::Private),
+                channels: channels.unwrap_or_else(Vec::new),
+            }),
+        }
+    }
+}
+
+impl Plugin for Tell {
+    fn name(&self) -> &str {
+        "tell"
+    }
+
+    fn commands(&self) -> Vec<Box<Command>> {
+        vec![
+            Box::new(TellCommand(self.memos.clone())),
+            Box::new(MemosCommand(self.memos.clone())),
+        ]
+    }
+
+    fn observe(&self, net: &Network, out: &mut Output, m: &OwnedMessage) {
+        let nick = match m.src {
+            OwnedSource::User(ref nick, _, _) => &nick[..],
+            _ => return,
+        };
+        let info = net.server_info();
+
+        let chan = match m.command {
+            IrcCommand::Privmsg(ref target, _) => info.split_statusmsg(target).1,
+            IrcCommand::Join(ref chan, _) => chan,
+            _ => return,
+        };
+
+        let me = net.current_nick().map(|me| info.casemapping().equals(me, nick));
+        if info.is_channel(chan) && me == Some(false) {
+            self.memos.deliver(net, out, nick, chan);
+        }
+    }
+}
+
+struct TellCommand(Rc<Memos>);
+
+impl Command for TellCommand {
+    fn name(&self) -> &str {
+        "tell"
+    }
+
+    fn help(&self) -> &str {
+        "leaves a message for someone, for the next time they're around"
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().required("nick", Kind::Nick).required("message", Kind::Rest))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let nick = args.get_str("nick").unwrap_or("");
+        let (to, sender, me) = match (ctx.network(), ctx.sender()) {
+            (Some(net), Some(sender)) => {
+                let casemap = net.server_info().casemapping();
+                let me = net.current_nick().map(|me| casemap.equals(me, nick)).unwrap_or(false);
+                (casemap.fold(nick), casemap.fold(sender), me)
+            },
+            _ => return,
+        };
+
+        if me {
+            return ctx.reply("i'm right here");
+        } else if to == sender {
+            return ctx.reply("you could just tell yourself");
+        }
+
+        let memo = Memo {
+            time: (self.0.clock)(),
+            from: ctx.sender().unwrap_or("").to_string(),
+            sender: sender,
+            chan: ctx.channel_name().map(|c| c.to_string()),
+            text: args.get_str("message").unwrap_or("").to_string(),
+        };
+
+        match self.0.leave(&to, nick, &memo) {
+            Ok(()) => ctx.reply(&format!("ok, i'll tell {} when they're around", nick)),
+            Err(Error::Aborted(why)) => ctx.reply_error(&why),
+            Err(e) => {
+                warn!("couldn't leave a memo for {}: {}", nick, e);
+                ctx.reply_error("i can't take memos right now, sorry");
+            },
+        }
+    }
+}
+
+struct MemosCommand(Rc<Memos>);
+
+impl Command for MemosCommand {
+    fn name(&self) -> &str {
+        "memos"
+    }
+
+    fn help(&self) -> &str {
+        "lists the memos waiting for you, or clears them"
+    }
+
+    fn args(&self) -> Option<Spec> {
+        Some(Spec::new().optional("list|clear", Kind::Word))
+    }
+
+    fn run(&self, ctx: &mut Context, args: &Args) {
+        let to = match (ctx.network(), ctx.sender()) {
+            (Some(net), Some(sender)) => net.server_info().casemapping().fold(sender),
+            _ => return,
+        };
+
+        let memos = match self.0.pending(&to) {
+            Ok(memos) => memos,
+            Err(e) => {
+                warn!("couldn't look up memos for {}: {}", to, e);
+                return ctx.reply_error("i can't find your memos right now, sorry");
+            },
+        };
+
+        match args.get_str("list|clear").map(|a| a.to_ascii_lowercase()) {
+            None => self.list(ctx, &memos),
+            Some(ref action) if action == "list" => self.list(ctx, &memos),
+            Some(ref action) if action == "clear" => {
+                let keys: Vec<String> = memos.iter().map(|&(ref key, _)| key.clone()).collect();
+                match self.0.clear(&keys) {
+                    Ok(()) => ctx.reply(&format!("cleared {} memo{}",
+                        keys.len(), if keys.len() == 1 { "" } else { "s" })),
+                    Err(e) => {
+                        warn!("couldn't clear memos for {}: {}", to, e);
+                        ctx.reply_error("i can't clear your memos right now, sorry");
+                    },
+                }
+            },
+            Some(action) => ctx.reply_error(&format!("i can list or clear memos, not {}", action)),
+        }
+    }
+}
+
+impl MemosCommand {
+    fn list(&self, ctx: &mut Context, memos: &[(String, Memo)]) {
+        if memos.is_empty() {
+            return ctx.reply("you don't have any memos");
+        }
+        let now = (self.0.clock)();
+        for &(_, ref memo) in memos {
+            ctx.reply_private(&memo.describe(now));
+        }
+    }
+}
+
+#[cfg(test)]
+const TEST_CONFIG: &'static str = r##"
+    [irc]
+    nick = "miau"
+    channels = []
+
+    [tell]
+    max_per_sender = 2
+    max_per_recipient = 2
+
+    [tell.channels."#Chat"]
+    deliver = "channel"
+"##;
+
+#[cfg(test)]
+fn test_registry(storage: &Storage) -> ::commands::CommandRegistry {
+    ::commands::registry_with(TEST_CONFIG, storage, Tell::with_clock)
+}
+
+#[test]
+fn tell_delivers_memos() {
+    use commands::replies_from;
+
+    let storage = Storage::memory();
+    assert_eq!(replies_from(&test_registry(&storage), TEST_CONFIG, &[
+        ":aji!a@h PRIVMSG #miau-dev :!tell pinkie remember the party",
+        ":rarity!r@h PRIVMSG [miau] :tell pinkie cupcakes?",
+        ":aji!a@h PRIVMSG #miau-dev :!tell [Twilight] books are due",
+    ]), vec![
+        "PRIVMSG #miau-dev :aji: ok, i'll tell pinkie when they're around",
+        "NOTICE rarity :ok, i'll tell pinkie when they're around",
+        "PRIVMSG #miau-dev :aji: ok, i'll tell [Twilight] when they're around",
+    ]);
+
+    // after a restart. #chat gets memos in the channel, except for the ones
+    // left in private
+    assert_eq!(replies_from(&test_registry(&storage), TEST_CONFIG, &[
+        ":pinkie!p@h PRIVMSG #chat :hello",
+        ":pinkie!p@h PRIVMSG #chat :hello again",
+        ":{twilight}!t@h JOIN #miau-dev",
+    ]), vec![
+        "PRIVMSG #chat :pinkie: aji left you a message in #miau-dev just now: remember the party",
+        "PRIVMSG pinkie :rarity left you a message just now: cupcakes?",
+        "PRIVMSG {twilight} :aji left you a message in #miau-dev just now: books are due",
+    ]);
+}
+
+#[test]
+fn tell_limits_memos() {
+    use commands::replies_from;
+
+    assert_eq!(replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":aji!a@h PRIVMSG #miau-dev :!tell pinkie remember the party",
+        ":aji!a@h PRIVMSG #miau-dev :!tell pinkie bring balloons",
+        ":aji!a@h PRIVMSG #miau-dev :!tell rarity one too many",
+        ":dash!d@h PRIVMSG #miau-dev :!tell PINKIE you're awesome",
+        ":pinkie!p@h PRIVMSG #miau-dev :hi!",
+        ":dash!d@h PRIVMSG #miau-dev :!tell PINKIE you're awesome",
+        ":aji!a@h PRIVMSG #miau-dev :!tell rarity not too many now",
+    ]), vec![
+        "PRIVMSG #miau-dev :aji: ok, i'll tell pinkie when they're around",
+        "PRIVMSG #miau-dev :aji: ok, i'll tell pinkie when they're around",
+        "PRIVMSG #miau-dev :aji: you already have 2 memos waiting to be delivered",
+        "PRIVMSG #miau-dev :dash: PINKIE already has 2 memos waiting",
+        "PRIVMSG pinkie :aji left you a message in #miau-dev just now: remember the party",
+        "PRIVMSG pinkie :aji left you a message in #miau-dev just now: bring balloons",
+        "PRIVMSG #miau-dev :dash: ok, i'll tell PINKIE when they're around",
+        "PRIVMSG #miau-dev :aji: ok, i'll tell rarity when they're around",
+    ]);
+}
+
+#[test]
+fn tell_refuses_pointless_memos() {
+    use commands::replies_from;
+
+    assert_eq!(replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":aji!a@h PRIVMSG #miau-dev :!tell AJI hi",
+        ":aji!a@h PRIVMSG #miau-dev :!tell [miau] hi",
+    ]), vec![
+        "PRIVMSG #miau-dev :aji: you could just tell yourself",
+        "PRIVMSG #miau-dev :aji: i'm right here",
+    ]);
+}
+
+#[test]
+fn tell_lists_and_clears_memos() {
+    use commands::replies_from;
+
+    assert_eq!(replies_from(&test_registry(&Storage::memory()), TEST_CONFIG, &[
+        ":aji!a@h PRIVMSG #miau-dev :!tell rarity hi",
+        ":rarity!r@h PRIVMSG [miau] :memos",
+        ":rarity!r@h PRIVMSG [miau] :memos clear",
+        ":rarity!r@h PRIVMSG [miau] :memos burn",
+        ":rarity!r@h PRIVMSG #miau-dev :!memos",
+        ":rarity!r@h JOIN #miau-dev",
+    ]), vec![
+        "PRIVMSG #miau-dev :aji: ok, i'll tell rarity when they're around",
+        "NOTICE rarity :aji left you a message in #miau-dev just now: hi",
+        "NOTICE rarity :cleared 1 memo",
+        "NOTICE rarity :i can list or clear memos, not burn",
+        "PRIVMSG #miau-dev :rarity: you don't have any memos",
+    ]);
+}
@@ -25,6 +25,7 @@ use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::rc::Rc;
+use std::time::Duration;
 use toml;
 
 const CONFIG_ENV:  &'static str = "MIAU_CONFIG";
@@ -74,6 +75,22 @@ impl EnvInner {
         self.conf(path).and_then(|v| v.as_float())
     }
 
+    /// Fetches the given configuration value as a number, if it exists. Unlike
+    /// `conf_float`, this also accepts integers.
+    pub fn conf_number<'a>(&'a self, path: &'a str) -> Option<f64> {
+        self.conf(path).and_then(|v| v.as_float().or(v.as_integer().map(|i| i as f64)))
+    }
+
+    /// Fetches the given configuration value as a duration in seconds, or the
+    /// default value, if it doesn't exist, and prints a warning. Fractional
+    /// and negative values are accepted, with negative values clamped to 0.
+    pub fn conf_duration_or<'a>(&'a self, path: &'a str, or: Duration) -> Duration {
+        match self.conf_number(path) {
+            Some(secs) => secs_to_duration(secs),
+            None => { warn!("{} defaulting to {:?}", path, or); or },
+        }
+    }
+
     /// Fetches the given configuration value as a boolean, if it exists.
     pub fn conf_bool<'a>(&'a self, path: &'a str) -> Option<bool> {
         self.conf(path).and_then(|v| v.as_bool())
@@ -91,6 +108,12 @@ impl EnvInner {
     }
 }
 
+/// Converts a possibly fractional number of seconds into a `Duration`.
+pub fn secs_to_duration(secs: f64) -> Duration {
+    let secs = secs.max(0.0);
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
+
 /// Look up a value. Splits on periods, descends that way.
 pub fn lookup<'t>(x: &'t toml::Value, path: &str) -> Option<&'t toml::Value> {
     path.split('.').fold(Some(x), |x, p| x.and_then(|x| lookup_one(x, p)))
@@ -159,3 +182,12 @@ pub fn load() -> Result<Env, Error> {
 
     Ok(Rc::new(env))
 }
+
+/// Builds an environment from a string, for tests.
+#[cfg(test)]
+pub fn from_str(config: &str) -> Env {
+    Rc::new(EnvInner {
+        config: toml::from_str(config).expect("bad test config"),
+        overlay: toml::from_str("").expect("bad test config"),
+    })
+}
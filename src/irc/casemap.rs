@@ -0,0 +1,198 @@
+//! Case-insensitive comparison of nicknames and channel names.
+//!
+//! IRC servers compare names case-insensitively, but what counts as the same
+//! letter depends on the server's `CASEMAPPING`. Under RFC 1459 rules, the
+//! Scandinavian heritage of the protocol means `[]\~` are the uppercase forms
+//! of `{}|^`, so `[foo]` and `{FOO}` are the same nickname.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+/// The ways a server might fold case.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CaseMapping {
+    /// Only `A-Z` and `a-z` are equivalent.
+    Ascii,
+    /// Like `Ascii`, but `[]\~` are also equivalent to `{}|^`.
+    Rfc1459,
+    /// Like `Rfc1459`, but without `~` and `^`.
+    StrictRfc1459,
+    /// Unicode case folding. The RFC also asks for NFKC normalization, which
+    /// isn't done here, so names that only differ in their normal form are
+    /// still considered different.
+    Rfc7613,
+}
+
+impl Default for CaseMapping {
+    fn default() -> CaseMapping {
+        CaseMapping::Rfc1459
+    }
+}
+
+impl CaseMapping {
+    /// Looks up a case mapping by its `CASEMAPPING` name.
+    pub fn from_name(name: &str) -> Option<CaseMapping> {
+        match &name.to_ascii_lowercase()[..] {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            "rfc7613" => Some(CaseMapping::Rfc7613),
+            _ => None,
+        }
+    }
+
+    /// The `CASEMAPPING` name of this case mapping.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::StrictRfc1459 => "strict-rfc1459",
+            CaseMapping::Rfc7613 => "rfc7613",
+        }
+    }
+
+    /// Folds a name to lowercase.
+    pub fn fold(&self, s: &str) -> String {
+        match *self {
+            CaseMapping::Rfc7613 => s.to_lowercase(),
+            _ => s.chars().map(|c| self.fold_char(c)).collect(),
+        }
+    }
+
+    fn fold_char(&self, c: char) -> char {
+        match (*self, c) {
+            (_, 'A'...'Z') => c.to_ascii_lowercase(),
+            (CaseMapping::Rfc1459, '[') | (CaseMapping::StrictRfc1459, '[') => '{',
+            (CaseMapping::Rfc1459, ']') | (CaseMapping::StrictRfc1459, ']') => '}',
+            (CaseMapping::Rfc1459, '\\') | (CaseMapping::StrictRfc1459, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c,
+        }
+    }
+
+    /// Whether two names are the same under this case mapping.
+    pub fn equals(&self, a: &str, b: &str) -> bool {
+        self.fold(a) == self.fold(b)
+    }
+
+    /// Wraps a name in an `IrcKey` using this case mapping.
+    pub fn key(&self, name: &str) -> IrcKey {
+        IrcKey::new(name, *self)
+    }
+}
+
+/// A name that compares, hashes and sorts case-insensitively, for use as a
+/// map key. The original spelling is kept around for display.
+///
+/// Maps keyed by `IrcKey` can be searched with a `&str`, but only with one
+/// that's already been folded with the same case mapping.
+#[derive(Clone)]
+pub struct IrcKey {
+    name: String,
+    folded: String,
+}
+
+impl IrcKey {
+    pub fn new(name: &str, map: CaseMapping) -> IrcKey {
+        IrcKey { name: name.to_string(), folded: map.fold(name) }
+    }
+
+    /// The name as it was originally spelled.
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// The folded form of the name.
+    pub fn folded(&self) -> &str {
+        &self.folded
+    }
+}
+
+impl PartialEq for IrcKey {
+    fn eq(&self, other: &IrcKey) -> bool {
+        self.folded == other.folded
+    }
+}
+
+impl Eq for IrcKey { }
+
+impl PartialOrd for IrcKey {
+    fn partial_cmp(&self, other: &IrcKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IrcKey {
+    fn cmp(&self, other: &IrcKey) -> Ordering {
+        self.folded.cmp(&other.folded)
+    }
+}
+
+impl Hash for IrcKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state);
+    }
+}
+
+impl Borrow<str> for IrcKey {
+    fn borrow(&self) -> &str {
+        &self.folded
+    }
+}
+
+impl fmt::Display for IrcKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl fmt::Debug for IrcKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.name)
+    }
+}
+
+#[test]
+fn casemap_fold() {
+    let s = "Foo[]\\~Bar";
+    assert_eq!(CaseMapping::Ascii.fold(s), "foo[]\\~bar");
+    assert_eq!(CaseMapping::Rfc1459.fold(s), "foo{}|^bar");
+    assert_eq!(CaseMapping::StrictRfc1459.fold(s), "foo{}|~bar");
+    assert_eq!(CaseMapping::Rfc7613.fold("ÀÉÎ[]"), "àéî[]");
+    assert_eq!(CaseMapping::Rfc1459.fold("ÀÉ"), "ÀÉ");
+}
+
+#[test]
+fn casemap_equals() {
+    assert!(CaseMapping::Rfc1459.equals("[miau]", "{MIAU}"));
+    assert!(!CaseMapping::Ascii.equals("[miau]", "{MIAU}"));
+    assert!(CaseMapping::Ascii.equals("Miau", "mIAU"));
+    assert!(CaseMapping::Rfc7613.equals("Ünicode", "ünicode"));
+    assert!(!CaseMapping::Rfc1459.equals("miau", "miau_"));
+}
+
+#[test]
+fn casemap_from_name() {
+    assert_eq!(CaseMapping::from_name("RFC1459"), Some(CaseMapping::Rfc1459));
+    assert_eq!(CaseMapping::from_name("strict-rfc1459"), Some(CaseMapping::StrictRfc1459));
+    assert_eq!(CaseMapping::from_name("rfc7613").map(|m| m.name()), Some("rfc7613"));
+    assert_eq!(CaseMapping::from_name("bogus"), None);
+}
+
+#[test]
+fn casemap_keys() {
+    use std::collections::BTreeMap;
+
+    let map = CaseMapping::Rfc1459;
+    let mut m = BTreeMap::new();
+    m.insert(map.key("[Miau]"), 1);
+    m.insert(map.key("{miau}"), 2);
+
+    assert_eq!(m.len(), 1);
+    assert_eq!(m.get(&map.fold("[MIAU]")[..]), Some(&2));
+    assert_eq!(m.keys().next().unwrap().as_str(), "[Miau]");
+    assert_eq!(map.key("{x}").to_string(), "{x}");
+}
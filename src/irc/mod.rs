@@ -0,0 +1,1017 @@
+//! IRC handling functions
+
+use std::borrow::Cow;
+use std::fmt;
+use std::slice;
+use std::str::CharIndices;
+use std::iter::Peekable;
+
+pub mod casemap;
+pub mod text;
+
+/// Helper for the message parser
+struct Scanner<'a> {
+    s: &'a str,
+    c: Peekable<CharIndices<'a>>,
+}
+
+/// The parsed form of an IRC message.
+#[derive(PartialEq)]
+pub struct Message<'a> {
+    pub tags: Tags<'a>,
+    pub src: MessageSource<'a>,
+    pub verb: &'a str,
+    pub args: Vec<&'a str>,
+}
+
+/// The parsed source of an IRC message.
+#[derive(PartialEq)]
+pub enum MessageSource<'a> {
+    Missing,
+    User(&'a str, Option<&'a str>, Option<&'a str>),
+    Server(&'a str),
+}
+
+/// A message that owns all of its data, with the verb and parameters
+/// interpreted as a `Command`. Unlike `Message`, these can be stored and
+/// passed around freely.
+#[derive(Clone, PartialEq, Debug)]
+pub struct OwnedMessage {
+    pub tags: Tags<'static>,
+    pub src: OwnedSource,
+    pub command: Command,
+}
+
+/// The owned form of `MessageSource`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum OwnedSource {
+    Missing,
+    User(String, Option<String>, Option<String>),
+    Server(String),
+}
+
+/// The verb and parameters of a message. Commands we know something about
+/// get their own variants, and everything else is kept as `Raw`. A known
+/// command without its required parameters isn't a `Command` at all:
+/// `Command::parse` rejects it with `ParseError::MissingParam`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    /// `PRIVMSG <target> <text>`
+    Privmsg(String, String),
+    /// `NOTICE <target> <text>`
+    Notice(String, String),
+    /// `JOIN <channel> [<params>...]`. The extra parameters are keys when
+    /// sent by a client, or the account and realname with `extended-join`.
+    Join(String, Vec<String>),
+    /// `PART <channel> [<reason>]`
+    Part(String, Option<String>),
+    /// `KICK <channel> <nick> [<reason>]`
+    Kick(String, String, Option<String>),
+    /// `MODE <target> [<modes> <params>...]`
+    Mode(String, Vec<String>),
+    /// `NICK <nick>`
+    Nick(String),
+    /// `QUIT [<reason>]`
+    Quit(Option<String>),
+    /// `PING <token>`
+    Ping(String),
+    /// `PONG <server> [<token>]`
+    Pong(String, Option<String>),
+    /// A three-digit numeric reply, with all of its parameters.
+    Numeric(u16, Vec<String>),
+    /// A command we don't have a variant for.
+    Raw(String, Vec<String>),
+}
+
+/// The IRCv3 tags attached to a message, in the order they were received.
+/// Values are stored unescaped, and a tag sent without a value has the empty
+/// string as its value, since the spec considers the two equivalent.
+#[derive(PartialEq, Clone, Default)]
+pub struct Tags<'a> {
+    tags: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+/// The reasons a line from the server might not be usable as a message.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParseError {
+    /// The line is empty, or contains only whitespace.
+    EmptyLine,
+    /// The line has no verb, e.g. it's only tags and a source.
+    MissingVerb,
+    /// The line has more than the 15 parameters allowed by RFC 1459.
+    TooManyParams,
+    /// The source is empty or has an empty nickname.
+    InvalidSource,
+    /// The tag section is empty or contains a tag with an empty key.
+    InvalidTag,
+    /// The parameter at this index is required but wasn't given.
+    MissingParam(usize),
+}
+
+/// The most parameters a message is allowed to have.
+const MAX_PARAMS: usize = 15;
+
+/// The reasons a `Message` might not have a valid wire representation.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EncodeError {
+    /// Some part of the message contains a CR, LF, or NUL byte. These are only
+    /// allowed in tag values, where they get escaped.
+    IllegalChar,
+    /// The verb is empty or contains a space.
+    InvalidVerb,
+    /// A tag key is empty or contains characters that can't be escaped.
+    InvalidTag,
+    /// The source is empty or contains a space.
+    InvalidSource,
+    /// The parameter at this index can only be sent as the trailing parameter,
+    /// but it isn't the last one.
+    InvalidParam(usize),
+}
+
+impl<'a> Scanner<'a> {
+    fn new(s: &str) -> Scanner {
+        Scanner { s: s, c: s.char_indices().peekable() }
+    }
+
+    fn byte_pos(&mut self) -> usize {
+        self.c.peek().map(|x| x.0).unwrap_or(self.s.len())
+    }
+
+    fn peek(&mut self) -> char {
+        self.c.peek().map(|x| x.1).unwrap_or('\n')
+    }
+
+    fn empty(&mut self) -> bool {
+        self.byte_pos() >= self.s.len()
+    }
+
+    fn skip(&mut self) {
+        let _ = self.c.next();
+    }
+
+    fn skip_while<F>(&mut self, f: F) where F: Fn(char) -> bool {
+        while !self.empty() && f(self.peek()) { self.skip(); }
+    }
+
+    fn skip_spaces(&mut self) {
+        self.skip_while(|c| c.is_whitespace());
+    }
+
+    fn chomp(&mut self) -> &'a str {
+        self.skip_spaces();
+        let start = self.byte_pos();
+        self.skip_while(|c| !c.is_whitespace());
+        let end = self.byte_pos();
+        self.skip_spaces();
+
+        &self.s[start..end]
+    }
+
+    fn chomp_remaining(&mut self) -> &'a str {
+        let start = self.byte_pos();
+        while !self.empty() { self.skip(); }
+        &self.s[start..]
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Creates a message with no tags or source, as would be sent by a client.
+    pub fn new(verb: &'a str, args: Vec<&'a str>) -> Message<'a> {
+        Message {
+            tags: Tags::new(),
+            src: MessageSource::Missing,
+            verb: verb,
+            args: args,
+        }
+    }
+
+    /// Parses the byte slice into a `Message`
+    pub fn parse(spec: &'a str) -> Result<Message<'a>, ParseError> {
+        let mut scan = Scanner::new(spec);
+
+        scan.skip_spaces();
+
+        if scan.empty() {
+            return Err(ParseError::EmptyLine);
+        }
+
+        let tags = if scan.peek() == '@' {
+            scan.skip();
+            if scan.peek().is_whitespace() {
+                return Err(ParseError::InvalidTag);
+            }
+            try!(Tags::parse(scan.chomp()))
+        } else {
+            Tags::new()
+        };
+
+        let src = if scan.peek() == ':' {
+            scan.skip();
+            if scan.peek().is_whitespace() {
+                return Err(ParseError::InvalidSource);
+            }
+            let src = MessageSource::parse(scan.chomp());
+            match src {
+                MessageSource::User("", _, _) | MessageSource::Missing =>
+                    return Err(ParseError::InvalidSource),
+                _ => src,
+            }
+        } else {
+            MessageSource::Missing
+        };
+
+        let verb = scan.chomp();
+
+        if verb.is_empty() {
+            return Err(ParseError::MissingVerb);
+        }
+
+        let mut args = Vec::new();
+        while !scan.empty() {
+            if args.len() == MAX_PARAMS {
+                return Err(ParseError::TooManyParams);
+            }
+
+            args.push(if scan.peek() == ':' {
+                scan.skip();
+                scan.chomp_remaining()
+            } else {
+                scan.chomp()
+            });
+        }
+
+        Ok(Message {
+            tags: tags,
+            src:  src,
+            verb: verb,
+            args: args
+        })
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Checks that the message can be sent as-is and converts it to a line,
+    /// without the terminating CRLF.
+    pub fn encode(&self) -> Result<String, EncodeError> {
+        try!(self.validate());
+        Ok(self.to_string())
+    }
+
+    fn validate(&self) -> Result<(), EncodeError> {
+        for &(ref k, ref v) in self.tags.iter() {
+            if k.is_empty() || k.contains(|c| "=; ".contains(c)) || has_illegal_char(k) {
+                return Err(EncodeError::InvalidTag);
+            }
+            if v.contains('\0') {
+                return Err(EncodeError::IllegalChar);
+            }
+        }
+
+        match self.src {
+            MessageSource::Missing => { },
+            ref src => {
+                let src = src.to_string();
+                if has_illegal_char(&src) {
+                    return Err(EncodeError::IllegalChar);
+                }
+                if src.is_empty() || src.contains(' ') {
+                    return Err(EncodeError::InvalidSource);
+                }
+            },
+        }
+
+        if has_illegal_char(self.verb) {
+            return Err(EncodeError::IllegalChar);
+        }
+        if self.verb.is_empty() || self.verb.contains(' ') {
+            return Err(EncodeError::InvalidVerb);
+        }
+
+        for (i, arg) in self.args.iter().enumerate() {
+            if has_illegal_char(arg) {
+                return Err(EncodeError::IllegalChar);
+            }
+            if i + 1 != self.args.len() && needs_trailing(arg) {
+                return Err(EncodeError::InvalidParam(i));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the message in its wire format, without the terminating CRLF. This
+/// does no validation, so prefer `encode` for anything that will actually be
+/// sent to the server.
+impl<'a> fmt::Display for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.tags.is_empty() {
+            try!(write!(f, "@"));
+            for (i, &(ref k, ref v)) in self.tags.iter().enumerate() {
+                if i != 0 {
+                    try!(write!(f, ";"));
+                }
+                try!(write!(f, "{}", k));
+                if !v.is_empty() {
+                    try!(write!(f, "="));
+                    try!(write_escaped_tag_value(f, v));
+                }
+            }
+            try!(write!(f, " "));
+        }
+
+        if self.src != MessageSource::Missing {
+            try!(write!(f, ":{} ", self.src));
+        }
+
+        try!(write!(f, "{}", self.verb));
+
+        for (i, arg) in self.args.iter().enumerate() {
+            if i + 1 == self.args.len() && needs_trailing(arg) {
+                try!(write!(f, " :{}", arg));
+            } else {
+                try!(write!(f, " {}", arg));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::IllegalChar => write!(f, "message contains CR, LF, or NUL"),
+            EncodeError::InvalidVerb => write!(f, "invalid verb"),
+            EncodeError::InvalidTag => write!(f, "invalid tag key"),
+            EncodeError::InvalidSource => write!(f, "invalid source"),
+            EncodeError::InvalidParam(i) => write!(f, "parameter {} can't be sent", i),
+        }
+    }
+}
+
+/// Whether the parameter can only be sent as a trailing parameter.
+fn needs_trailing(arg: &str) -> bool {
+    arg.is_empty() || arg.starts_with(':') || arg.contains(' ')
+}
+
+fn has_illegal_char(s: &str) -> bool {
+    s.contains(|c| c == '\r' || c == '\n' || c == '\0')
+}
+
+impl OwnedMessage {
+    /// Parses a line straight into an `OwnedMessage`.
+    pub fn parse(spec: &str) -> Result<OwnedMessage, ParseError> {
+        Message::parse(spec).and_then(|m| OwnedMessage::from_message(&m))
+    }
+
+    /// Copies a message into an `OwnedMessage`. This fails if the message is
+    /// a command we know but is missing required parameters.
+    pub fn from_message(m: &Message) -> Result<OwnedMessage, ParseError> {
+        Ok(OwnedMessage {
+            tags: m.tags.clone().into_owned(),
+            src: m.src.to_owned(),
+            command: try!(Command::parse(m.verb, &m.args[..])),
+        })
+    }
+}
+
+/// Checked access to message parameters, so that a short message from the
+/// server results in an error rather than a panic.
+pub trait Params {
+    /// Fetches the parameter at the given index, or fails with
+    /// `ParseError::MissingParam` if there aren't enough.
+    fn param(&self, i: usize) -> Result<&str, ParseError>;
+}
+
+impl Params for [String] {
+    fn param(&self, i: usize) -> Result<&str, ParseError> {
+        self.get(i).map(|a| &a[..]).ok_or(ParseError::MissingParam(i))
+    }
+}
+
+impl<'a> Params for [&'a str] {
+    fn param(&self, i: usize) -> Result<&str, ParseError> {
+        self.get(i).map(|a| *a).ok_or(ParseError::MissingParam(i))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::EmptyLine => write!(f, "empty line"),
+            ParseError::MissingVerb => write!(f, "missing verb"),
+            ParseError::TooManyParams => write!(f, "more than {} parameters", MAX_PARAMS),
+            ParseError::InvalidSource => write!(f, "invalid source"),
+            ParseError::InvalidTag => write!(f, "invalid tag"),
+            ParseError::MissingParam(i) => write!(f, "missing parameter {}", i),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Message("));
+        if !self.tags.is_empty() {
+            try!(write!(f, "{:?}, ", self.tags));
+        }
+        try!(write!(f, "{:?}, {:?}", self.src, self.verb));
+        for s in self.args.iter() {
+            try!(write!(f, ", {:?}", s));
+        }
+        try!(write!(f, ")"));
+        Ok(())
+    }
+}
+
+impl<'a> Tags<'a> {
+    /// Creates an empty set of tags.
+    pub fn new() -> Tags<'a> {
+        Tags { tags: Vec::new() }
+    }
+
+    /// Parses the tag section of a message, without the leading `@`. If a
+    /// key appears more than once, the last value wins.
+    pub fn parse(spec: &'a str) -> Result<Tags<'a>, ParseError> {
+        let mut tags = Tags::new();
+
+        for tag in spec.split(';').filter(|t| !t.is_empty()) {
+            let (key, value) = match tag.find('=') {
+                Some(i) => (&tag[..i], unescape_tag_value(&tag[i+1..])),
+                None => (tag, Cow::Borrowed("")),
+            };
+
+            if key.is_empty() || key == "+" {
+                return Err(ParseError::InvalidTag);
+            }
+
+            tags.insert(key, value);
+        }
+
+        if tags.is_empty() {
+            return Err(ParseError::InvalidTag);
+        }
+
+        Ok(tags)
+    }
+
+    /// Fetches the unescaped value of the given tag, if it's present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|t| t.0 == key).map(|t| &t.1[..])
+    }
+
+    /// Returns whether the given tag is present, with or without a value.
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Sets the value of a tag, replacing any existing value.
+    pub fn insert<K, V>(&mut self, key: K, value: V)
+    where K: Into<Cow<'a, str>>, V: Into<Cow<'a, str>> {
+        let key = key.into();
+        let value = value.into();
+
+        match self.tags.iter().position(|t| t.0 == key) {
+            Some(i) => self.tags[i].1 = value,
+            None => self.tags.push((key, value)),
+        }
+    }
+
+    /// Iterates over the tags as `(key, value)` pairs.
+    pub fn iter(&self) -> slice::Iter<(Cow<'a, str>, Cow<'a, str>)> {
+        self.tags.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+}
+
+impl<'a> Tags<'a> {
+    /// Converts any borrowed keys and values into owned strings.
+    pub fn into_owned(self) -> Tags<'static> {
+        Tags {
+            tags: self.tags.into_iter()
+                .map(|(k, v)| (Cow::Owned(k.into_owned()), Cow::Owned(v.into_owned())))
+                .collect()
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Tags<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.tags.iter().map(|t| (&t.0, &t.1))).finish()
+    }
+}
+
+/// Returns whether the tag is a client-only tag, i.e. one that was attached by
+/// another client (with `TAGMSG` or similar) rather than by the server.
+pub fn is_client_only_tag(key: &str) -> bool {
+    key.starts_with('+')
+}
+
+fn write_escaped_tag_value(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    for c in value.chars() {
+        try!(match c {
+            ';' => write!(f, "\\:"),
+            ' ' => write!(f, "\\s"),
+            '\\' => write!(f, "\\\\"),
+            '\r' => write!(f, "\\r"),
+            '\n' => write!(f, "\\n"),
+            c => write!(f, "{}", c),
+        });
+    }
+    Ok(())
+}
+
+/// Undoes the escaping applied to tag values. Unknown escapes stand for the
+/// escaped character itself, and a lone trailing backslash is dropped.
+fn unescape_tag_value(value: &str) -> Cow<str> {
+    if !value.contains('\\') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => { },
+        }
+    }
+
+    Cow::Owned(out)
+}
+
+impl<'a> MessageSource<'a> {
+    pub fn parse(spec: &'a str) -> MessageSource<'a> {
+        use self::MessageSource::*;
+
+        // If there's a bug in this code, you're probably better off rewriting
+        // the whole thing.
+
+        // we shouldn't ever be parsing an empty source string, but just in
+        // case, handle it as Missing.
+        if spec.len() == 0 { return Missing }
+
+        let delimit = |c: &char| { *c == '.' || *c == '!' || *c == '@' };
+
+        // match on the first delimiter
+        match spec.chars().filter(delimit).nth(0) {
+            Some('!') | Some('@') => {
+                let ex = spec.find('!');
+                let at = spec.find('@');
+
+                // this is horrible and I'm sorry but hopefully I get it right
+                // the first time and nobody has to look at it ever again.
+                match ex {
+                    Some(exi) => match at {
+                        Some(ati) => {
+                            if exi < ati {
+                                User(
+                                    &spec[..exi],
+                                    Some(&spec[exi+1..ati]),
+                                    Some(&spec[ati+1..])
+                                )
+                            } else {
+                                User(
+                                    &spec[..ati],
+                                    Some(&spec[exi+1..]),
+                                    Some(&spec[ati+1..exi])
+                                )
+                            }
+                        },
+                        None => User(&spec[..exi], Some(&spec[exi+1..]), None)
+                    },
+                    None => match at {
+                        Some(ati) => User(&spec[..ati], None, Some(&spec[ati+1..])),
+                        None      => User(&spec[..],    None, None),
+                    },
+                }
+            },
+            Some('.') => Server(spec),
+            Some(_) => { error!("couldn't parse source!"); Missing }
+            None => User(spec, None, None)
+        }
+    }
+
+    pub fn short_name(&self) -> &'a str {
+        match *self {
+            MessageSource::Missing => "(nil)",
+            MessageSource::User(ref n, _, _) => n,
+            MessageSource::Server(ref s) => s,
+        }
+    }
+}
+
+impl<'a> MessageSource<'a> {
+    /// Copies the source into an `OwnedSource`.
+    pub fn to_owned(&self) -> OwnedSource {
+        match *self {
+            MessageSource::Missing => OwnedSource::Missing,
+            MessageSource::User(n, u, h) => OwnedSource::User(
+                n.to_string(),
+                u.map(|u| u.to_string()),
+                h.map(|h| h.to_string()),
+            ),
+            MessageSource::Server(s) => OwnedSource::Server(s.to_string()),
+        }
+    }
+}
+
+/// Writes the source as it would appear in a message, without the leading `:`.
+/// A missing source is written as the empty string.
+impl<'a> fmt::Display for MessageSource<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MessageSource::*;
+
+        match *self {
+            Missing => Ok(()),
+            User(n, u, h) => {
+                try!(write!(f, "{}", n));
+                if let Some(u) = u {
+                    try!(write!(f, "!{}", u));
+                }
+                if let Some(h) = h {
+                    try!(write!(f, "@{}", h));
+                }
+                Ok(())
+            },
+            Server(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for MessageSource<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MessageSource::*;
+
+        match *self {
+            Missing => write!(f, "Missing"),
+            User(ref n, ref u, ref h) =>
+                write!(f, "User({}!{}@{})", n, u.unwrap_or("?"), h.unwrap_or("?")),
+            Server(ref s) => write!(f, "Server({})", s),
+        }
+    }
+}
+
+impl OwnedSource {
+    /// The nickname, if the message came from a user.
+    pub fn nick(&self) -> Option<&str> {
+        match *self {
+            OwnedSource::User(ref n, _, _) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn short_name(&self) -> &str {
+        match *self {
+            OwnedSource::Missing => "(nil)",
+            OwnedSource::User(ref n, _, _) => n,
+            OwnedSource::Server(ref s) => s,
+        }
+    }
+}
+
+impl Command {
+    /// Interprets the verb and parameters of a message. Verbs are matched
+    /// case-insensitively, and known commands that are missing required
+    /// parameters are rejected.
+    pub fn parse(verb: &str, args: &[&str]) -> Result<Command, ParseError> {
+        use self::Command::*;
+
+        let arg = |i: usize| args.param(i).map(|a| a.to_string());
+        let opt = |i: usize| args.get(i).map(|a| a.to_string());
+        let rest = |i: usize| args.iter().skip(i).map(|a| a.to_string()).collect();
+
+        let verb = verb.to_ascii_uppercase();
+
+        Ok(match &verb[..] {
+            "PRIVMSG" => Privmsg(try!(arg(0)), try!(arg(1))),
+            "NOTICE" => Notice(try!(arg(0)), try!(arg(1))),
+            "JOIN" => Join(try!(arg(0)), rest(1)),
+            "PART" => Part(try!(arg(0)), opt(1)),
+            "KICK" => Kick(try!(arg(0)), try!(arg(1)), opt(2)),
+            "MODE" => Mode(try!(arg(0)), rest(1)),
+            "NICK" => Nick(try!(arg(0))),
+            "QUIT" => Quit(opt(0)),
+            "PING" => Ping(try!(arg(0))),
+            "PONG" => Pong(try!(arg(0)), opt(1)),
+            _ => match verb.parse::<u16>() {
+                Ok(num) if verb.len() == 3 => Numeric(num, rest(0)),
+                _ => Raw(verb, rest(0)),
+            },
+        })
+    }
+}
+
+#[test]
+fn message_source_parse_server() {
+    use self::MessageSource::*;
+
+    assert_eq!(MessageSource::parse("my.host"),
+        Server("my.host"));
+}
+
+#[test]
+fn message_source_parse_user() {
+    use self::MessageSource::*;
+
+    assert_eq!(MessageSource::parse("miau"),
+        User("miau", None, None));
+    assert_eq!(MessageSource::parse("miau!~u"),
+        User("miau", Some("~u"), None));
+    assert_eq!(MessageSource::parse("miau@h.ost"),
+        User("miau", None, Some("h.ost")));
+    assert_eq!(MessageSource::parse("miau!~u@h.ost"),
+        User("miau", Some("~u"), Some("h.ost")));
+
+    // servers should never do this, but test it anyway just in case.
+    assert_eq!(MessageSource::parse("miau@h.ost!~u"),
+        User("miau", Some("~u"), Some("h.ost")));
+}
+
+#[test]
+fn message_parse_non_ascii_source() {
+    assert_eq!(MessageSource::parse("é!ü@h.ost"),
+        MessageSource::User("é", Some("ü"), Some("h.ost")));
+
+    let m = OwnedMessage::parse(":é!u@h PRIVMSG #c :hi").unwrap();
+    assert_eq!(m.src, OwnedSource::User("é".to_string(), Some("u".to_string()),
+        Some("h".to_string())));
+}
+
+#[test]
+fn message_parse_no_source() {
+    assert_eq!(Message {
+        tags: Tags::new(),
+        src: MessageSource::Missing,
+        verb: "PING",
+        args: vec!["123"],
+    }, Message::parse("PING 123").unwrap());
+}
+
+#[test]
+fn message_parse_trailing() {
+    assert_eq!(Message {
+        tags: Tags::new(),
+        src: MessageSource::Missing,
+        verb: "PING",
+        args: vec!["this has spaces"],
+    }, Message::parse("PING :this has spaces").unwrap());
+}
+
+#[test]
+fn message_parse_with_spaces() {
+    assert_eq!(Message {
+        tags: Tags::new(),
+        src: MessageSource::Missing,
+        verb: "PING",
+        args: vec!["this", "has", "spaces"],
+    }, Message::parse("PING this has spaces").unwrap());
+}
+
+#[test]
+fn message_parse_with_many_extra_spaces() {
+    // not technically to-spec
+    assert_eq!(Message {
+        tags: Tags::new(),
+        src: MessageSource::Server("h.ost"),
+        verb: "PING",
+        args: vec!["this", "has", "very many spaces  "],
+    }, Message::parse("  :h.ost  PING     this   has   :very many spaces  ").unwrap());
+}
+
+#[test]
+fn message_parse_with_many_extra_spaces_and_no_trailing() {
+    // not technically to-spec
+    assert_eq!(Message {
+        tags: Tags::new(),
+        src: MessageSource::Server("h.ost"),
+        verb: "PING",
+        args: vec!["this", "has", "very", "many", "spaces"],
+    }, Message::parse("  :h.ost  PING     this   has   very many spaces  ").unwrap());
+}
+
+#[test]
+fn message_parse_with_source() {
+    assert_eq!(Message {
+        tags: Tags::new(),
+        src: MessageSource::Server("h.ost"),
+        verb: "PING",
+        args: vec!["this", "has spaces"],
+    }, Message::parse(":h.ost PING this :has spaces").unwrap());
+}
+
+#[test]
+fn message_parse_tags() {
+    let mut tags = Tags::new();
+    tags.insert("time", "2017-05-01T12:00:00.000Z");
+    tags.insert("account", "aji");
+    tags.insert("draft/bot", "");
+
+    assert_eq!(Message {
+        tags: tags,
+        src: MessageSource::User("aji", Some("~aji"), Some("h.ost")),
+        verb: "PRIVMSG",
+        args: vec!["#miau-dev", "hello"],
+    }, Message::parse("@time=2017-05-01T12:00:00.000Z;account=aji;draft/bot \
+                       :aji!~aji@h.ost PRIVMSG #miau-dev :hello").unwrap());
+}
+
+#[test]
+fn message_parse_tags_no_source() {
+    let m = Message::parse("@msgid=abc PING 123").unwrap();
+    assert_eq!(m.tags.get("msgid"), Some("abc"));
+    assert_eq!(m.src, MessageSource::Missing);
+    assert_eq!(m.verb, "PING");
+    assert_eq!(m.args, vec!["123"]);
+}
+
+#[test]
+fn message_parse_tag_escapes() {
+    let m = Message::parse(r"@a=one\:two\sthree\\four\r\n;b=\x\ PING").unwrap();
+    assert_eq!(m.tags.get("a"), Some("one;two three\\four\r\n"));
+    assert_eq!(m.tags.get("b"), Some("x"));
+}
+
+#[test]
+fn message_parse_tag_missing_values() {
+    let m = Message::parse("@a;b=;c=d PING").unwrap();
+    assert_eq!(m.tags.len(), 3);
+    assert_eq!(m.tags.get("a"), Some(""));
+    assert_eq!(m.tags.get("b"), Some(""));
+    assert_eq!(m.tags.get("c"), Some("d"));
+    assert_eq!(m.tags.get("e"), None);
+}
+
+#[test]
+fn message_parse_tag_duplicates() {
+    let m = Message::parse("@a=1;b=2;a=3 PING").unwrap();
+    assert_eq!(m.tags.len(), 2);
+    assert_eq!(m.tags.get("a"), Some("3"));
+}
+
+#[test]
+fn message_parse_client_only_tags() {
+    let m = Message::parse("@+example.com/foo=bar;time=x :n!u@h TAGMSG #c").unwrap();
+    assert_eq!(m.tags.get("+example.com/foo"), Some("bar"));
+
+    let client: Vec<_> = m.tags.iter().filter(|t| is_client_only_tag(&t.0)).collect();
+    assert_eq!(client.len(), 1);
+    assert!(!is_client_only_tag("time"));
+}
+
+#[test]
+fn message_encode_simple() {
+    assert_eq!(Message::new("NICK", vec!["miau"]).encode(),
+        Ok("NICK miau".to_string()));
+    assert_eq!(Message::new("PRIVMSG", vec!["#miau-dev", "hello there"]).encode(),
+        Ok("PRIVMSG #miau-dev :hello there".to_string()));
+    assert_eq!(Message::new("PRIVMSG", vec!["#miau-dev", ":)"]).encode(),
+        Ok("PRIVMSG #miau-dev ::)".to_string()));
+    assert_eq!(Message::new("TOPIC", vec!["#miau-dev", ""]).encode(),
+        Ok("TOPIC #miau-dev :".to_string()));
+    assert_eq!(Message::new("QUIT", vec![]).encode(),
+        Ok("QUIT".to_string()));
+}
+
+#[test]
+fn message_encode_source_and_tags() {
+    let mut m = Message::new("PRIVMSG", vec!["#c", "hi"]);
+    m.src = MessageSource::User("n", Some("u"), Some("h"));
+    m.tags.insert("+draft/reply", "a;b c\\d");
+    m.tags.insert("flag", "");
+
+    assert_eq!(m.encode(),
+        Ok(r"@+draft/reply=a\:b\sc\\d;flag :n!u@h PRIVMSG #c hi".to_string()));
+}
+
+#[test]
+fn message_encode_rejects_bad_input() {
+    assert_eq!(Message::new("PRIVMSG", vec!["#c", "hi\r\nQUIT"]).encode(),
+        Err(EncodeError::IllegalChar));
+    assert_eq!(Message::new("PRIVMSG", vec!["#c", "nul\0"]).encode(),
+        Err(EncodeError::IllegalChar));
+    assert_eq!(Message::new("", vec!["#c"]).encode(),
+        Err(EncodeError::InvalidVerb));
+    assert_eq!(Message::new("PRIV MSG", vec!["#c"]).encode(),
+        Err(EncodeError::InvalidVerb));
+    assert_eq!(Message::new("PRIVMSG", vec!["#a b", "hi"]).encode(),
+        Err(EncodeError::InvalidParam(0)));
+    assert_eq!(Message::new("KICK", vec!["#c", ":x", "bye"]).encode(),
+        Err(EncodeError::InvalidParam(1)));
+
+    let mut m = Message::new("PING", vec!["x"]);
+    m.tags.insert("a=b", "c");
+    assert_eq!(m.encode(), Err(EncodeError::InvalidTag));
+
+    // line breaks in tag values get escaped instead
+    let mut m = Message::new("PING", vec!["x"]);
+    m.tags.insert("a", "b\r\nc");
+    assert_eq!(m.encode(), Ok(r"@a=b\r\nc PING x".to_string()));
+}
+
+#[test]
+fn message_encode_round_trip() {
+    let lines = [
+        "PING 123",
+        "PING :this has spaces",
+        ":h.ost 001 miau :Welcome to the network",
+        ":miau!~miau@h.ost JOIN #miau-dev",
+        ":aji!~aji@h.ost PRIVMSG #miau-dev ::D :D",
+        ":h.ost 353 miau = #miau-dev :@aji +miau other",
+        r"@time=2017-05-01T12:00:00.000Z;+x=a\:b\sc;y :aji PRIVMSG miau hi",
+    ];
+
+    for line in lines.iter() {
+        let m = Message::parse(line).unwrap();
+        let encoded = m.encode().unwrap();
+        assert_eq!(&encoded, line);
+        assert_eq!(Message::parse(&encoded).unwrap(), m);
+    }
+}
+
+#[test]
+fn message_to_owned() {
+    let m = Message::parse("@account=aji :aji!~aji@h.ost PRIVMSG #miau-dev :hi there").unwrap();
+    let mut tags = Tags::new();
+    tags.insert("account", "aji");
+
+    assert_eq!(OwnedMessage::from_message(&m).unwrap(), OwnedMessage {
+        tags: tags,
+        src: OwnedSource::User("aji".to_string(), Some("~aji".to_string()), Some("h.ost".to_string())),
+        command: Command::Privmsg("#miau-dev".to_string(), "hi there".to_string()),
+    });
+}
+
+#[test]
+fn command_parse() {
+    fn cmd(line: &str) -> Command {
+        OwnedMessage::parse(line).unwrap().command
+    }
+
+    let s = |x: &str| x.to_string();
+
+    assert_eq!(cmd("privmsg #c :hi"), Command::Privmsg(s("#c"), s("hi")));
+    assert_eq!(cmd("NOTICE aji :hi"), Command::Notice(s("aji"), s("hi")));
+    assert_eq!(cmd("JOIN #c"), Command::Join(s("#c"), vec![]));
+    assert_eq!(cmd("JOIN #c aji :Alex"), Command::Join(s("#c"), vec![s("aji"), s("Alex")]));
+    assert_eq!(cmd("PART #c"), Command::Part(s("#c"), None));
+    assert_eq!(cmd("PART #c :bye"), Command::Part(s("#c"), Some(s("bye"))));
+    assert_eq!(cmd("KICK #c miau :no"), Command::Kick(s("#c"), s("miau"), Some(s("no"))));
+    assert_eq!(cmd("MODE #c +ov a b"), Command::Mode(s("#c"), vec![s("+ov"), s("a"), s("b")]));
+    assert_eq!(cmd("NICK miau_"), Command::Nick(s("miau_")));
+    assert_eq!(cmd("QUIT"), Command::Quit(None));
+    assert_eq!(cmd("PING :123"), Command::Ping(s("123")));
+    assert_eq!(cmd("PONG h.ost :123"), Command::Pong(s("h.ost"), Some(s("123"))));
+    assert_eq!(cmd("001 miau :Welcome"), Command::Numeric(1, vec![s("miau"), s("Welcome")]));
+    assert_eq!(cmd("CAP * LS :sasl"), Command::Raw(s("CAP"), vec![s("*"), s("LS"), s("sasl")]));
+    assert_eq!(cmd("1234 x"), Command::Raw(s("1234"), vec![s("x")]));
+}
+
+#[test]
+fn command_parse_missing_params() {
+    assert_eq!(OwnedMessage::parse("PING"), Err(ParseError::MissingParam(0)));
+    assert_eq!(OwnedMessage::parse("KICK #c"), Err(ParseError::MissingParam(1)));
+    assert_eq!(OwnedMessage::parse(":n!u@h PRIVMSG #c"), Err(ParseError::MissingParam(1)));
+}
+
+#[test]
+fn message_parse_errors() {
+    assert_eq!(Message::parse(""), Err(ParseError::EmptyLine));
+    assert_eq!(Message::parse("   "), Err(ParseError::EmptyLine));
+    assert_eq!(Message::parse(":h.ost"), Err(ParseError::MissingVerb));
+    assert_eq!(Message::parse("@a=b :h.ost "), Err(ParseError::MissingVerb));
+    assert_eq!(Message::parse(": PING x"), Err(ParseError::InvalidSource));
+    assert_eq!(Message::parse(":!u@h PING x"), Err(ParseError::InvalidSource));
+    assert_eq!(Message::parse("@ PING x"), Err(ParseError::InvalidTag));
+    assert_eq!(Message::parse("@a;=b PING x"), Err(ParseError::InvalidTag));
+    assert_eq!(Message::parse("X 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16"),
+        Err(ParseError::TooManyParams));
+    assert!(Message::parse("X 1 2 3 4 5 6 7 8 9 10 11 12 13 14 :15 16").is_ok());
+}
+
+#[test]
+fn message_params() {
+    let m = Message::parse("PING").unwrap();
+    assert_eq!(m.args.param(0), Err(ParseError::MissingParam(0)));
+
+    let m = Message::parse("PING a").unwrap();
+    assert_eq!(m.args.param(0), Ok("a"));
+    assert_eq!(vec!["a".to_string()].param(1), Err(ParseError::MissingParam(1)));
+}
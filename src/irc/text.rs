@@ -0,0 +1,208 @@
+//! Helpers for fitting text into IRC lines.
+//!
+//! The server limits lines to 512 bytes including the CRLF, and that limit
+//! applies to the line as it's relayed to other clients, i.e. with our
+//! `nick!user@host` prepended. Anything past the limit is silently cut off,
+//! so long messages have to be split up before they're sent.
+
+/// The longest a line can be, not counting the CRLF.
+pub const MAX_LINE_LEN: usize = 510;
+
+/// A guess at the longest username the server will show for us, including
+/// the `~` added when identd isn't running.
+pub const MAX_USER_LEN: usize = 11;
+
+/// A guess at the longest hostname the server will show for us.
+pub const MAX_HOST_LEN: usize = 63;
+
+/// A guess at the longest nickname, used when we don't know ours yet.
+pub const MAX_NICK_LEN: usize = 30;
+
+/// The length of our source, if we know it, or otherwise a guess.
+pub const DEFAULT_SOURCE_LEN: usize = MAX_NICK_LEN + 1 + MAX_USER_LEN + 1 + MAX_HOST_LEN;
+
+/// Works out the length of `nick!user@host`, assuming the worst for any
+/// parts we don't know.
+pub fn source_len(nick: &str, user: Option<&str>, host: Option<&str>) -> usize {
+    nick.len() + 1 +
+        user.map(|u| u.len()).unwrap_or(MAX_USER_LEN) + 1 +
+        host.map(|h| h.len()).unwrap_or(MAX_HOST_LEN)
+}
+
+/// Works out how many bytes of text fit in a message like
+/// `:<source> <verb> <target> :<text>`.
+pub fn payload_len(source_len: usize, verb: &str, target: &str) -> usize {
+    let overhead = 1 + source_len + 1 + verb.len() + 1 + target.len() + 2;
+    MAX_LINE_LEN.saturating_sub(overhead)
+}
+
+/// Splits text into lines of at most `max` bytes. The text is first split
+/// on line breaks, and long lines are then broken at spaces where possible.
+/// Lines are never split in the middle of a UTF-8 sequence or a mIRC color
+/// code, and empty lines are dropped.
+pub fn split_lines(text: &str, max: usize) -> Vec<&str> {
+    let mut lines = Vec::new();
+
+    for line in text.split('\n') {
+        split_line(line.trim_right_matches('\r'), max, &mut lines);
+    }
+
+    lines
+}
+
+fn split_line<'a>(mut line: &'a str, max: usize, lines: &mut Vec<&'a str>) {
+    loop {
+        if line.len() <= max {
+            if !line.trim().is_empty() {
+                lines.push(line);
+            }
+            return;
+        }
+
+        let (head, tail) = line.split_at(find_cut(line, max));
+        if !head.trim().is_empty() {
+            lines.push(head.trim_right_matches(' '));
+        }
+        line = tail.trim_left_matches(' ');
+    }
+}
+
+/// Finds where to cut a line that's longer than `max`. This is the last space
+/// that fits, or failing that, the last unit boundary that fits. Formatting
+/// codes stay attached to the text that follows them.
+fn find_cut(line: &str, max: usize) -> usize {
+    let mut pos = 0;
+    let mut last_fit = 0;
+    let mut last_space = 0;
+
+    while pos < line.len() {
+        let formatting = is_formatting(&line[pos..]);
+        let next = pos + unit_len(&line[pos..]);
+        if next > max {
+            break;
+        }
+
+        pos = next;
+        if !formatting {
+            last_fit = pos;
+        }
+        if line[pos..].starts_with(' ') {
+            last_space = pos;
+        }
+    }
+
+    if last_space > 0 {
+        return last_space;
+    } else if last_fit > 0 {
+        return last_fit;
+    }
+
+    // not even one character fits. send it anyway, along with any formatting
+    // before it, rather than looping forever
+    let mut pos = 0;
+    while pos < line.len() {
+        let formatting = is_formatting(&line[pos..]);
+        pos += unit_len(&line[pos..]);
+        if !formatting {
+            break;
+        }
+    }
+    pos
+}
+
+/// Whether the string starts with a formatting code.
+fn is_formatting(s: &str) -> bool {
+    match s.as_bytes().first() {
+        Some(&0x02) | Some(&0x03) | Some(&0x04) | Some(&0x0f) | Some(&0x11) |
+        Some(&0x16) | Some(&0x1d) | Some(&0x1e) | Some(&0x1f) => true,
+        _ => false,
+    }
+}
+
+/// The length of the indivisible unit at the start of the string, which is
+/// either a color code with its arguments, or a single character.
+fn unit_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+
+    let digits = |start: usize, max: usize, hex: bool| {
+        bytes[start..].iter()
+            .take(max)
+            .take_while(|b| if hex { b.is_ascii_hexdigit() } else { b.is_ascii_digit() })
+            .count()
+    };
+
+    let color = |hex: bool| {
+        let width = if hex { 6 } else { 2 };
+        let mut len = 1 + digits(1, width, hex);
+        if len > 1 && bytes.get(len) == Some(&b',') && digits(len + 1, 1, hex) == 1 {
+            len += 1 + digits(len + 1, width, hex);
+        }
+        len
+    };
+
+    match bytes.first() {
+        Some(&0x03) => color(false),
+        Some(&0x04) => color(true),
+        _ => s.chars().next().map(|c| c.len_utf8()).unwrap_or(0),
+    }
+}
+
+#[test]
+fn text_payload_len() {
+    let src = source_len("miau", Some("~miau"), Some("h.ost"));
+    assert_eq!(src, "miau!~miau@h.ost".len());
+
+    let line = ":miau!~miau@h.ost PRIVMSG #miau-dev :";
+    assert_eq!(payload_len(src, "PRIVMSG", "#miau-dev"), MAX_LINE_LEN - line.len());
+
+    assert_eq!(source_len("miau", None, None), 4 + 1 + MAX_USER_LEN + 1 + MAX_HOST_LEN);
+}
+
+#[test]
+fn text_split_short() {
+    assert_eq!(split_lines("hello there", 20), vec!["hello there"]);
+    assert_eq!(split_lines("", 20), Vec::<&str>::new());
+}
+
+#[test]
+fn text_split_newlines() {
+    assert_eq!(split_lines("one\r\ntwo\n\nthree\n", 20), vec!["one", "two", "three"]);
+}
+
+#[test]
+fn text_split_words() {
+    assert_eq!(split_lines("the quick brown fox jumps", 10),
+        vec!["the quick", "brown fox", "jumps"]);
+    assert_eq!(split_lines("aaaa bbbb", 4), vec!["aaaa", "bbbb"]);
+}
+
+#[test]
+fn text_split_long_word() {
+    assert_eq!(split_lines("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+}
+
+#[test]
+fn text_split_utf8() {
+    // each of these is 3 bytes
+    assert_eq!(split_lines("日本語テキスト", 8), vec!["日本", "語テ", "キス", "ト"]);
+    assert_eq!(split_lines("é", 1), vec!["é"]);
+}
+
+#[test]
+fn text_split_color_codes() {
+    let text = "ab\x0304,12cd";
+    assert_eq!(split_lines(text, 8), vec!["ab", "\x0304,12cd"]);
+    assert_eq!(split_lines(text, 4), vec!["ab", "\x0304,12c", "d"]);
+
+    let text = "ab\x04ff00ffcd";
+    assert_eq!(split_lines(text, 5), vec!["ab", "\x04ff00ffc", "d"]);
+
+    let text = "one \x02two\x02 three";
+    assert_eq!(split_lines(text, 9), vec!["one \x02two\x02", "three"]);
+    assert_eq!(split_lines(text, 8), vec!["one", "\x02two\x02", "three"]);
+
+    // a bare comma after a color isn't part of the code
+    assert_eq!(unit_len("\x0304,x"), 3);
+    assert_eq!(unit_len("\x03,12"), 1);
+    assert_eq!(unit_len("\x02bold"), 1);
+}
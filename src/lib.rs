@@ -9,6 +9,11 @@ extern crate bytes;
 extern crate futures;
 extern crate tokio_core;
 extern crate tokio_io;
+extern crate rand;
+extern crate native_tls;
+extern crate tokio_tls;
+extern crate base64;
+extern crate rusqlite;
 
 pub mod bot;
 pub mod commands;
@@ -16,3 +21,9 @@ pub mod environment;
 pub mod irc;
 pub mod logging;
 pub mod network;
+pub mod storage;
+
+mod backoff;
+mod ratelimit;
+mod scheduler;
+mod watchdog;
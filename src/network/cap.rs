@@ -0,0 +1,261 @@
+//! IRCv3 capability negotiation.
+//!
+//! During registration we send `CAP LS 302`, which makes the server hold off
+//! on welcoming us until we send `CAP END`. Once the list of capabilities
+//! arrives, we request the ones we want (`irc.caps` in the config) and end
+//! negotiation when the server has answered. Servers that support
+//! `cap-notify` can also add and remove capabilities while we're connected
+//! with `CAP NEW` and `CAP DEL`.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use environment::Env;
+use irc::Message;
+use irc::ParseError;
+use irc::Params;
+use network::Output;
+
+/// The capabilities requested when `irc.caps` isn't set. These only add
+/// information to messages we already handle, so they're safe to ask for.
+const DEFAULT_CAPS: &'static [&'static str] = &[
+    "account-notify",
+    "account-tag",
+    "cap-notify",
+    "chghost",
+    "extended-join",
+    "message-tags",
+    "multi-prefix",
+    "server-time",
+];
+
+pub struct Caps {
+    wanted: Vec<String>,
+    available: BTreeMap<String, String>,
+    enabled: BTreeSet<String>,
+    requested: BTreeSet<String>,
+    listing: bool,
+}
+
+impl Caps {
+    /// Creates a `Caps` that will request the capabilities listed in
+    /// `irc.caps`.
+    pub fn from_env(env: &Env) -> Caps {
+        let wanted = match env.conf_array("irc.caps") {
+            Some(caps) => caps.iter()
+                .filter_map(|c| c.as_str())
+                .map(|c| c.to_string())
+                .collect(),
+            None => DEFAULT_CAPS.iter().map(|c| c.to_string()).collect(),
+        };
+
+        Caps::new(wanted)
+    }
+
+    fn new(wanted: Vec<String>) -> Caps {
+        Caps {
+            wanted: wanted,
+            available: BTreeMap::new(),
+            enabled: BTreeSet::new(),
+            requested: BTreeSet::new(),
+            listing: false,
+        }
+    }
+
+    /// Adds a capability to the list we request.
+    pub fn want(&mut self, cap: &str) {
+        if !self.wanted.iter().any(|c| c == cap) {
+            self.wanted.push(cap.to_string());
+        }
+    }
+
+    /// Asks the server which capabilities it supports. This has to happen
+    /// before `NICK` and `USER` for the server to wait for `CAP END`.
+    pub fn start<T: Output>(&mut self, out: &mut T) {
+        self.listing = true;
+        out.send_message(&Message::new("CAP", vec!["LS", "302"]));
+    }
+
+    /// Gives up on negotiation, for when the server welcomes us without ever
+    /// answering `CAP LS`.
+    pub fn abandon(&mut self) {
+        self.listing = false;
+        self.requested.clear();
+    }
+
+    /// Whether negotiation is over, i.e. the full list of capabilities has
+    /// arrived and every request has been acknowledged or rejected.
+    pub fn settled(&self) -> bool {
+        !self.listing && self.requested.is_empty()
+    }
+
+    /// Whether the capability has been acknowledged by the server.
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.enabled.contains(cap)
+    }
+
+    /// The value the server advertised for a capability, such as the list of
+    /// mechanisms for `sasl`. Capabilities without a value give an empty
+    /// string.
+    pub fn value(&self, cap: &str) -> Option<&str> {
+        self.available.get(cap).map(|v| &v[..])
+    }
+
+    /// Iterates over the enabled capabilities.
+    pub fn enabled(&self) -> ::std::collections::btree_set::Iter<String> {
+        self.enabled.iter()
+    }
+
+    /// Handles the arguments of a `CAP` message from the server.
+    pub fn handle<T: Output>(&mut self, out: &mut T, args: &[String]) -> Result<(), ParseError> {
+        let subcommand = try!(args.param(1)).to_ascii_uppercase();
+        // the list is always last, and a `*` before it means there's more
+        let list = try!(args.param(args.len().max(3) - 1));
+        let more = args.len() > 3 && args[2] == "*";
+
+        match &subcommand[..] {
+            "LS" => {
+                for (cap, value) in parse_list(list) {
+                    self.available.insert(cap.to_string(), value.to_string());
+                }
+                if !more && self.listing {
+                    self.listing = false;
+                    self.request_wanted(out);
+                }
+            },
+
+            "NEW" => {
+                for (cap, value) in parse_list(list) {
+                    info!("server offers new capability {}", cap);
+                    self.available.insert(cap.to_string(), value.to_string());
+                }
+                self.request_wanted(out);
+            },
+
+            "DEL" => {
+                for (cap, _) in parse_list(list) {
+                    info!("server withdrew capability {}", cap);
+                    self.available.remove(cap);
+                    self.enabled.remove(cap);
+                }
+            },
+
+            "ACK" => {
+                for (cap, _) in parse_list(list) {
+                    if cap.starts_with('-') {
+                        self.enabled.remove(&cap[1..]);
+                        self.requested.remove(&cap[1..]);
+                    } else {
+                        debug!("enabled capability {}", cap);
+                        self.enabled.insert(cap.to_string());
+                        self.requested.remove(cap);
+                    }
+                }
+            },
+
+            "NAK" => {
+                warn!("server refused capabilities: {}", list);
+                for (cap, _) in parse_list(list) {
+                    self.requested.remove(cap.trim_left_matches('-'));
+                }
+            },
+
+            _ => debug!("ignoring CAP {}", subcommand),
+        }
+
+        Ok(())
+    }
+
+    /// Requests every wanted capability that's available but not enabled or
+    /// already requested.
+    fn request_wanted<T: Output>(&mut self, out: &mut T) {
+        let caps: Vec<String> = self.wanted.iter()
+            .filter(|c| self.available.contains_key(&c[..]))
+            .filter(|c| !self.enabled.contains(&c[..]))
+            .filter(|c| !self.requested.contains(&c[..]))
+            .cloned()
+            .collect();
+
+        if caps.is_empty() {
+            return;
+        }
+
+        let list = caps.join(" ");
+        out.send_message(&Message::new("CAP", vec!["REQ", &list]));
+        self.requested.extend(caps);
+    }
+}
+
+/// Splits a capability list like `sasl=PLAIN,EXTERNAL server-time` into names
+/// and values.
+fn parse_list<'a>(list: &'a str) -> Vec<(&'a str, &'a str)> {
+    list.split(' ')
+        .filter(|c| !c.is_empty())
+        .map(|c| match c.find('=') {
+            Some(i) => (&c[..i], &c[i+1..]),
+            None => (c, ""),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn cap_args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn caps_negotiate() {
+    let mut out = Vec::new();
+    let mut caps = Caps::new(vec!["server-time".into(), "sasl".into(), "echo-message".into()]);
+
+    caps.start(&mut out);
+    assert_eq!(out, vec!["CAP LS 302"]);
+    assert!(!caps.settled());
+
+    caps.handle(&mut out, &cap_args(&["*", "LS", "*", "sasl=PLAIN,EXTERNAL multi-prefix"])).unwrap();
+    assert_eq!(out.len(), 1);
+    caps.handle(&mut out, &cap_args(&["*", "LS", "server-time"])).unwrap();
+    assert_eq!(out[1], "CAP REQ :server-time sasl");
+    assert!(!caps.settled());
+
+    caps.handle(&mut out, &cap_args(&["*", "ACK", "server-time sasl"])).unwrap();
+    assert!(caps.settled());
+    assert!(caps.is_enabled("sasl"));
+    assert!(!caps.is_enabled("multi-prefix"));
+    assert_eq!(caps.value("sasl"), Some("PLAIN,EXTERNAL"));
+}
+
+#[test]
+fn caps_nak_settles() {
+    let mut out = Vec::new();
+    let mut caps = Caps::new(vec!["server-time".into()]);
+
+    caps.start(&mut out);
+    caps.handle(&mut out, &cap_args(&["*", "LS", "server-time"])).unwrap();
+    caps.handle(&mut out, &cap_args(&["*", "NAK", "server-time"])).unwrap();
+    assert!(caps.settled());
+    assert!(!caps.is_enabled("server-time"));
+}
+
+#[test]
+fn caps_new_and_del() {
+    let mut out = Vec::new();
+    let mut caps = Caps::new(vec!["away-notify".into()]);
+
+    caps.handle(&mut out, &cap_args(&["miau", "NEW", "away-notify batch"])).unwrap();
+    assert_eq!(out, vec!["CAP REQ away-notify"]);
+    caps.handle(&mut out, &cap_args(&["miau", "ACK", "away-notify"])).unwrap();
+    assert!(caps.is_enabled("away-notify"));
+
+    caps.handle(&mut out, &cap_args(&["miau", "DEL", "away-notify"])).unwrap();
+    assert!(!caps.is_enabled("away-notify"));
+    assert_eq!(caps.value("away-notify"), None);
+}
+
+#[test]
+fn caps_bad_message() {
+    let mut out = Vec::new();
+    let mut caps = Caps::new(Vec::new());
+    assert!(caps.handle(&mut out, &cap_args(&["*"])).is_err());
+    assert!(caps.handle(&mut out, &cap_args(&["*", "LS"])).is_err());
+}
@@ -0,0 +1,640 @@
+//! Tracking of the channels we're in and who's in them.
+//!
+//! Everything here is built from what the server tells us: our own JOIN
+//! creates a channel, the NAMES reply that follows fills in its members, and
+//! JOIN, PART, KICK, QUIT, NICK and MODE keep it up to date from then on.
+//! On servers with WHOX, we also ask who's in each channel we join, to learn
+//! the accounts of people who were there before us.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use irc::Command;
+use irc::casemap::CaseMapping;
+use irc::casemap::IrcKey;
+use irc::Message;
+use irc::OwnedMessage;
+use irc::OwnedSource;
+use network::ModeKind;
+use network::Output;
+use network::ServerInfo;
+
+/// Identifies the replies to our own WHOX queries.
+const WHOX_TOKEN: &'static str = "17";
+
+pub struct Channels {
+    casemap: CaseMapping,
+    chans: BTreeMap<IrcKey, Channel>,
+    users: BTreeMap<IrcKey, User>,
+}
+
+pub struct Channel {
+    name: String,
+    casemap: CaseMapping,
+    members: BTreeMap<IrcKey, Member>,
+    topic: Option<Topic>,
+    modes: BTreeMap<char, Option<String>>,
+    receiving_names: bool,
+}
+
+pub struct Member {
+    nick: String,
+    modes: String,
+    op: bool,
+}
+
+pub struct User {
+    nick: String,
+    user: Option<String>,
+    host: Option<String>,
+    account: Option<String>,
+}
+
+pub struct Topic {
+    text: String,
+    set_by: Option<String>,
+    set_at: Option<SystemTime>,
+}
+
+impl Channels {
+    pub fn new() -> Channels {
+        Channels {
+            casemap: CaseMapping::default(),
+            chans: BTreeMap::new(),
+            users: BTreeMap::new(),
+        }
+    }
+
+    /// Switches to a different case mapping, which means everything has to
+    /// be filed under new keys.
+    fn set_casemap(&mut self, casemap: CaseMapping) {
+        if casemap == self.casemap {
+            return;
+        }
+
+        fn rekey<V>(map: &mut BTreeMap<IrcKey, V>, casemap: CaseMapping) {
+            let old = ::std::mem::replace(map, BTreeMap::new());
+            for (k, v) in old {
+                map.insert(casemap.key(k.as_str()), v);
+            }
+        }
+
+        self.casemap = casemap;
+        rekey(&mut self.chans, casemap);
+        rekey(&mut self.users, casemap);
+        for c in self.chans.values_mut() {
+            c.casemap = casemap;
+            rekey(&mut c.members, casemap);
+        }
+    }
+
+    /// Looks up a channel we're in.
+    pub fn get(&self, name: &str) -> Option<&Channel> {
+        self.chans.get(&self.casemap.fold(name)[..])
+    }
+
+    /// Iterates over the channels we're in.
+    pub fn iter(&self) -> btree_map::Values<IrcKey, Channel> {
+        self.chans.values()
+    }
+
+    /// Looks up a user who shares a channel with us.
+    pub fn user(&self, nick: &str) -> Option<&User> {
+        self.users.get(&self.casemap.fold(nick)[..])
+    }
+
+    /// Updates channel state from a message. `me` is our current nickname.
+    pub fn handle<T: Output>(&mut self, out: &mut T, me: &str, info: &ServerInfo,
+                             m: &OwnedMessage) {
+        self.set_casemap(info.casemapping());
+
+        let nick = match m.src.nick() {
+            Some(nick) => nick,
+            None => "",
+        };
+        let from_me = self.casemap.equals(nick, me);
+
+        match m.command {
+            Command::Join(ref chan, ref extra) => {
+                if from_me {
+                    debug!("joined {}", chan);
+                    self.chans.insert(self.casemap.key(chan), Channel::new(chan, self.casemap));
+                    // ask for the channel modes, which come back as 324
+                    out.send_message(&Message::new("MODE", vec![chan]));
+                    if info.get("WHOX").is_some() {
+                        let fields = format!("%tuhna,{}", WHOX_TOKEN);
+                        out.send_message(&Message::new("WHO", vec![chan, &fields]));
+                    }
+                }
+                self.see_user(&m.src);
+                // with extended-join, the first extra parameter is the account
+                if let Some(account) = extra.get(0) {
+                    self.set_account(nick, account);
+                }
+                if let Some(c) = self.chans.get_mut(&self.casemap.fold(chan)[..]) {
+                    c.add_member(nick, "", info);
+                }
+            },
+
+            Command::Part(ref chan, _) => self.remove_member(me, chan, nick),
+
+            Command::Kick(ref chan, ref victim, _) => self.remove_member(me, chan, victim),
+
+            Command::Quit(_) => {
+                for c in self.chans.values_mut() {
+                    c.members.remove(&self.casemap.fold(nick)[..]);
+                }
+                self.users.remove(&self.casemap.fold(nick)[..]);
+            },
+
+            Command::Nick(ref new_nick) => self.rename(nick, new_nick),
+
+            Command::Mode(ref target, ref args) => {
+                if let Some(c) = self.chans.get_mut(&self.casemap.fold(target)[..]) {
+                    c.apply_modes(args, info);
+                }
+            },
+
+            Command::Raw(ref verb, ref args) => match &verb[..] {
+                "TOPIC" if args.len() >= 2 => {
+                    if let Some(c) = self.chans.get_mut(&self.casemap.fold(&args[0])[..]) {
+                        c.topic = Some(Topic {
+                            text: args[1].clone(),
+                            set_by: Some(nick.to_string()),
+                            set_at: Some(SystemTime::now()),
+                        });
+                    }
+                },
+                "ACCOUNT" if !args.is_empty() => self.set_account(nick, &args[0]),
+                "CHGHOST" if args.len() >= 2 => {
+                    if let Some(u) = self.users.get_mut(&self.casemap.fold(nick)[..]) {
+                        u.user = Some(args[0].clone());
+                        u.host = Some(args[1].clone());
+                    }
+                },
+                _ => { },
+            },
+
+            Command::Numeric(num, ref args) => self.handle_numeric(num, args, info),
+
+            _ => { },
+        }
+    }
+
+    fn handle_numeric(&mut self, num: u16, args: &[String], info: &ServerInfo) {
+        if num == 354 { // RPL_WHOSPCRPL
+            return self.handle_whox(args);
+        }
+
+        // all of these have our nick and then the channel first, except for
+        // NAMES, which has a symbol for the channel type in between
+        let name = match args.get(if num == 353 { 2 } else { 1 }) {
+            Some(name) => self.casemap.fold(name),
+            None => return,
+        };
+        let chan = match self.chans.get_mut(&name[..]) {
+            Some(chan) => chan,
+            None => return,
+        };
+
+        match num {
+            324 => { // RPL_CHANNELMODEIS
+                chan.modes.clear();
+                chan.apply_modes(&args[2..], info);
+            },
+
+            331 => chan.topic = None, // RPL_NOTOPIC
+
+            332 => { // RPL_TOPIC
+                if let Some(text) = args.get(2) {
+                    chan.topic = Some(Topic { text: text.clone(), set_by: None, set_at: None });
+                }
+            },
+
+            333 => { // RPL_TOPICWHOTIME
+                if let Some(ref mut topic) = chan.topic {
+                    topic.set_by = args.get(2).cloned();
+                    topic.set_at = args.get(3)
+                        .and_then(|t| t.parse().ok())
+                        .map(|t| UNIX_EPOCH + Duration::from_secs(t));
+                }
+            },
+
+            353 => { // RPL_NAMREPLY
+                if !chan.receiving_names {
+                    // a fresh NAMES reply replaces what we knew
+                    chan.members.clear();
+                    chan.receiving_names = true;
+                }
+                let names = args.last().map(|n| &n[..]).unwrap_or("");
+                for name in names.split(' ').filter(|n| !n.is_empty()) {
+                    let (modes, rest) = split_prefix(name, info);
+                    // with userhost-in-names, names are full sources
+                    let nick = rest.split('!').next().unwrap_or(rest);
+                    chan.add_member(nick, &modes, info);
+                    if !self.users.contains_key(&self.casemap.fold(nick)[..]) {
+                        self.users.insert(self.casemap.key(nick), User::new(nick));
+                    }
+                }
+            },
+
+            366 => chan.receiving_names = false, // RPL_ENDOFNAMES
+
+            _ => { },
+        }
+    }
+
+    /// Fills in a user from the reply to our WHOX query, which has our nick,
+    /// the token, and then the user, host, nick and account.
+    fn handle_whox(&mut self, args: &[String]) {
+        if args.len() < 6 || args[1] != WHOX_TOKEN {
+            return;
+        }
+        if let Some(u) = self.users.get_mut(&self.casemap.fold(&args[4])[..]) {
+            u.user = Some(args[2].clone());
+            u.host = Some(args[3].clone());
+        }
+        // the account is 0 for users who aren't logged in
+        let account = if args[5] == "0" { "*" } else { &args[5] };
+        self.set_account(&args[4], account);
+    }
+
+    /// Records what we know about the source of a message.
+    fn see_user(&mut self, src: &OwnedSource) {
+        if let OwnedSource::User(ref nick, ref user, ref host) = *src {
+            let u = self.users.entry(self.casemap.key(nick)).or_insert_with(|| User::new(nick));
+            if user.is_some() {
+                u.user = user.clone();
+            }
+            if host.is_some() {
+                u.host = host.clone();
+            }
+        }
+    }
+
+    fn set_account(&mut self, nick: &str, account: &str) {
+        if let Some(u) = self.users.get_mut(&self.casemap.fold(nick)[..]) {
+            u.account = if account == "*" { None } else { Some(account.to_string()) };
+        }
+    }
+
+    fn remove_member(&mut self, me: &str, chan: &str, nick: &str) {
+        if self.casemap.equals(nick, me) {
+            debug!("left {}", chan);
+            self.chans.remove(&self.casemap.fold(chan)[..]);
+        } else if let Some(c) = self.chans.get_mut(&self.casemap.fold(chan)[..]) {
+            c.members.remove(&self.casemap.fold(nick)[..]);
+        }
+        self.forget_strangers();
+    }
+
+    fn rename(&mut self, old: &str, new: &str) {
+        for c in self.chans.values_mut() {
+            if let Some(mut member) = c.members.remove(&self.casemap.fold(old)[..]) {
+                member.nick = new.to_string();
+                c.members.insert(self.casemap.key(new), member);
+            }
+        }
+        if let Some(mut u) = self.users.remove(&self.casemap.fold(old)[..]) {
+            u.nick = new.to_string();
+            self.users.insert(self.casemap.key(new), u);
+        }
+    }
+
+    /// Drops users who no longer share any channels with us.
+    fn forget_strangers(&mut self) {
+        let chans = &self.chans;
+        let strangers: Vec<IrcKey> = self.users.keys()
+            .filter(|k| !chans.values().any(|c| c.members.contains_key(k.folded())))
+            .cloned()
+            .collect();
+        for k in strangers {
+            self.users.remove(&k);
+        }
+    }
+}
+
+/// Splits the status prefixes off the front of a name from NAMES, giving the
+/// corresponding modes and the rest of the name.
+fn split_prefix<'a>(name: &'a str, info: &ServerInfo) -> (String, &'a str) {
+    let mut modes = String::new();
+    for (i, c) in name.char_indices() {
+        match info.mode_for_prefix(c) {
+            Some(mode) => modes.push(mode),
+            None => return (modes, &name[i..]),
+        }
+    }
+    (modes, "")
+}
+
+impl Channel {
+    fn new(name: &str, casemap: CaseMapping) -> Channel {
+        Channel {
+            name: name.to_string(),
+            casemap: casemap,
+            members: BTreeMap::new(),
+            topic: None,
+            modes: BTreeMap::new(),
+            receiving_names: false,
+        }
+    }
+
+    fn add_member(&mut self, nick: &str, modes: &str, info: &ServerInfo) {
+        let member = self.members.entry(self.casemap.key(nick)).or_insert_with(|| Member {
+            nick: nick.to_string(),
+            modes: String::new(),
+            op: false,
+        });
+        for mode in modes.chars() {
+            member.set_mode(mode, true, info);
+        }
+    }
+
+    /// Applies a mode change like `+ov-k nick1 nick2 key`.
+    fn apply_modes(&mut self, args: &[String], info: &ServerInfo) {
+        let mut params = args.iter().skip(1);
+        let mut adding = true;
+
+        for mode in args.get(0).map(|m| &m[..]).unwrap_or("").chars() {
+            match (mode, info.mode_kind(mode)) {
+                ('+', _) => adding = true,
+                ('-', _) => adding = false,
+
+                (_, ModeKind::Status) => {
+                    if let Some(nick) = params.next() {
+                        if let Some(member) = self.members.get_mut(&self.casemap.fold(nick)[..]) {
+                            member.set_mode(mode, adding, info);
+                        }
+                    }
+                },
+
+                // lists like bans aren't kept track of
+                (_, ModeKind::List) => { params.next(); },
+
+                (_, ModeKind::AlwaysParam) => {
+                    let param = params.next();
+                    self.set_mode(mode, adding, param);
+                },
+
+                (_, ModeKind::SetParam) if adding => {
+                    let param = params.next();
+                    self.set_mode(mode, adding, param);
+                },
+
+                _ => self.set_mode(mode, adding, None),
+            }
+        }
+    }
+
+    fn set_mode(&mut self, mode: char, adding: bool, param: Option<&String>) {
+        if adding {
+            self.modes.insert(mode, param.cloned());
+        } else {
+            self.modes.remove(&mode);
+        }
+    }
+
+    /// The channel's name, as the server gave it to us.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Looks up a member of the channel.
+    pub fn member(&self, nick: &str) -> Option<&Member> {
+        self.members.get(&self.casemap.fold(nick)[..])
+    }
+
+    /// Iterates over the members of the channel.
+    pub fn members(&self) -> btree_map::Values<IrcKey, Member> {
+        self.members.values()
+    }
+
+    pub fn topic(&self) -> Option<&Topic> {
+        self.topic.as_ref()
+    }
+
+    /// Whether the channel has the given mode set, e.g. `'m'` for moderated.
+    pub fn has_mode(&self, mode: char) -> bool {
+        self.modes.contains_key(&mode)
+    }
+
+    /// The parameter of a mode that has one, like the key for `'k'`.
+    pub fn mode_param(&self, mode: char) -> Option<&str> {
+        self.modes.get(&mode).and_then(|p| p.as_ref()).map(|p| &p[..])
+    }
+
+    /// The channel modes as a string like `+ntl 50`.
+    pub fn mode_string(&self) -> String {
+        let mut modes = String::from("+");
+        let mut params = String::new();
+        for (mode, param) in self.modes.iter() {
+            modes.push(*mode);
+            if let Some(ref param) = *param {
+                params.push(' ');
+                params.push_str(param);
+            }
+        }
+        modes + &params
+    }
+}
+
+impl Member {
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// The member's status modes, e.g. `"ov"`, highest first.
+    pub fn modes(&self) -> &str {
+        &self.modes
+    }
+
+    pub fn has_mode(&self, mode: char) -> bool {
+        self.modes.contains(mode)
+    }
+
+    /// Whether the member is a channel operator, or anything above one.
+    pub fn is_op(&self) -> bool {
+        self.op
+    }
+
+    /// Whether the member is voiced, or anything above it.
+    pub fn is_voiced(&self) -> bool {
+        !self.modes.is_empty()
+    }
+
+    fn set_mode(&mut self, mode: char, adding: bool, info: &ServerInfo) {
+        let mut modes: Vec<char> = self.modes.chars().filter(|m| *m != mode).collect();
+        if adding {
+            modes.push(mode);
+        }
+        modes.sort_by_key(|m| info.status_rank(*m));
+        self.op = modes.iter().any(|m| info.is_op_mode(*m));
+        self.modes = modes.into_iter().collect();
+    }
+}
+
+impl User {
+    fn new(nick: &str) -> User {
+        User { nick: nick.to_string(), user: None, host: None, account: None }
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_ref().map(|u| &u[..])
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_ref().map(|h| &h[..])
+    }
+
+    /// The services account the user is logged in to, if we know it.
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_ref().map(|a| &a[..])
+    }
+}
+
+impl Topic {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Who set the topic, which may be a full `nick!user@host`.
+    pub fn set_by(&self) -> Option<&str> {
+        self.set_by.as_ref().map(|s| &s[..])
+    }
+
+    pub fn set_at(&self) -> Option<SystemTime> {
+        self.set_at
+    }
+}
+
+#[cfg(test)]
+fn feed(chans: &mut Channels, line: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let info = ServerInfo::new();
+    chans.handle(&mut out, "miau", &info, &OwnedMessage::parse(line).unwrap());
+    out
+}
+
+#[cfg(test)]
+fn joined() -> Channels {
+    let mut chans = Channels::new();
+    feed(&mut chans, ":miau!~miau@host JOIN #Miau-Dev");
+    feed(&mut chans, ":irc.test 353 miau = #miau-dev :miau @aji +pony @+Twi");
+    feed(&mut chans, ":irc.test 366 miau #miau-dev :End of /NAMES list.");
+    chans
+}
+
+#[test]
+fn channels_join_and_names() {
+    let mut chans = Channels::new();
+    let out = feed(&mut chans, ":miau!~miau@host JOIN #Miau-Dev");
+    assert_eq!(out, vec!["MODE #Miau-Dev"]);
+
+    let chans = joined();
+    let c = chans.get("#miau-dev").unwrap();
+    assert_eq!(c.name(), "#Miau-Dev");
+    assert_eq!(c.members().count(), 4);
+    assert!(c.member("AJI").unwrap().is_op());
+    assert!(!c.member("pony").unwrap().is_op());
+    assert!(c.member("pony").unwrap().is_voiced());
+    assert_eq!(c.member("twi").unwrap().modes(), "ov");
+    assert_eq!(c.member("twi").unwrap().nick(), "Twi");
+    assert_eq!(chans.user("miau").unwrap().host(), Some("host"));
+}
+
+#[test]
+fn channels_membership_changes() {
+    let mut chans = joined();
+    feed(&mut chans, ":rarity!r@h JOIN #miau-dev");
+    feed(&mut chans, ":pony!p@h PART #miau-dev :bye");
+    feed(&mut chans, ":aji!a@h KICK #miau-dev Twi :no");
+    feed(&mut chans, ":rarity!r@h NICK :Rarity_");
+
+    let c = chans.get("#miau-dev").unwrap();
+    let mut nicks: Vec<&str> = c.members().map(|m| m.nick()).collect();
+    nicks.sort();
+    assert_eq!(nicks, vec!["Rarity_", "aji", "miau"]);
+    assert!(chans.user("pony").is_none());
+    assert_eq!(chans.user("rarity_").unwrap().user(), Some("r"));
+
+    feed(&mut chans, ":aji!a@h QUIT :gone");
+    assert!(chans.get("#miau-dev").unwrap().member("aji").is_none());
+
+    feed(&mut chans, ":miau!~miau@host PART #miau-dev");
+    assert!(chans.get("#miau-dev").is_none());
+    assert!(chans.user("rarity_").is_none());
+}
+
+#[test]
+fn channels_modes() {
+    let mut chans = joined();
+    feed(&mut chans, ":irc.test 324 miau #miau-dev +ntk secret");
+    feed(&mut chans, ":aji!a@h MODE #miau-dev +l-o+vb 10 Twi pony *!*@spam");
+    feed(&mut chans, ":aji!a@h MODE #miau-dev -k secret");
+
+    let c = chans.get("#miau-dev").unwrap();
+    assert_eq!(c.mode_string(), "+lnt 10");
+    assert_eq!(c.mode_param('l'), Some("10"));
+    assert!(!c.has_mode('b'));
+    assert_eq!(c.member("twi").unwrap().modes(), "v");
+    assert!(c.member("pony").unwrap().has_mode('v'));
+}
+
+#[test]
+fn channels_topic() {
+    let mut chans = joined();
+    feed(&mut chans, ":irc.test 332 miau #miau-dev :meow meow");
+    feed(&mut chans, ":irc.test 333 miau #miau-dev aji!a@h 1500000000");
+    {
+        let topic = chans.get("#miau-dev").unwrap().topic().unwrap();
+        assert_eq!(topic.text(), "meow meow");
+        assert_eq!(topic.set_by(), Some("aji!a@h"));
+        assert_eq!(topic.set_at(), Some(UNIX_EPOCH + Duration::from_secs(1500000000)));
+    }
+
+    feed(&mut chans, ":aji!a@h TOPIC #miau-dev :new topic");
+    let topic = chans.get("#miau-dev").unwrap().topic().unwrap();
+    assert_eq!(topic.text(), "new topic");
+    assert_eq!(topic.set_by(), Some("aji"));
+}
+
+#[test]
+fn channels_whox() {
+    let mut info = ServerInfo::new();
+    info.handle(&["miau".to_string(), "WHOX".to_string(), "are supported".to_string()]);
+    let mut chans = Channels::new();
+    let mut out = Vec::new();
+    let mut feed = |line: &str| {
+        chans.handle(&mut out, "miau", &info, &OwnedMessage::parse(line).unwrap());
+    };
+
+    feed(":miau!~miau@host JOIN #miau-dev");
+    feed(":irc.test 353 miau = #miau-dev :miau @rarity applejack");
+    feed(":irc.test 354 miau 17 ~r rarity.boutique rarity Rarity");
+    feed(":irc.test 354 miau 17 aj farm applejack 0");
+    feed(":irc.test 354 miau 99 x y applejack someone");
+
+    assert_eq!(out, vec!["MODE #miau-dev", "WHO #miau-dev %tuhna,17"]);
+    let rarity = chans.user("rarity").unwrap();
+    assert_eq!(rarity.account(), Some("Rarity"));
+    assert_eq!(rarity.host(), Some("rarity.boutique"));
+    assert_eq!(chans.user("applejack").unwrap().account(), None);
+}
+
+#[test]
+fn channels_accounts() {
+    let mut chans = joined();
+    feed(&mut chans, ":rarity!r@h JOIN #miau-dev rarity :Rarity");
+    assert_eq!(chans.user("rarity").unwrap().account(), Some("rarity"));
+    feed(&mut chans, ":rarity!r@h ACCOUNT *");
+    assert_eq!(chans.user("rarity").unwrap().account(), None);
+    feed(&mut chans, ":rarity!r@h CHGHOST rar new.host");
+    assert_eq!(chans.user("rarity").unwrap().host(), Some("new.host"));
+}
@@ -0,0 +1,349 @@
+//! The server's `RPL_ISUPPORT` (005) tokens.
+//!
+//! After welcoming us, the server sends a few 005 replies describing how it
+//! behaves: which characters start a channel name, which status modes it has
+//! and how they're shown, how it compares nicknames, and so on. Until those
+//! arrive (or on servers that don't send them) we assume what RFC 1459 says,
+//! plus the modes most servers have.
+
+use std::collections::BTreeMap;
+
+use irc::casemap::CaseMapping;
+
+/// How a channel mode uses parameters, following the groups of `CHANMODES`
+/// plus the status modes from `PREFIX`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ModeKind {
+    /// A list like bans. Always takes a parameter.
+    List,
+    /// A setting that always takes a parameter, like the key.
+    AlwaysParam,
+    /// A setting that takes a parameter only when set, like the limit.
+    SetParam,
+    /// A flag that never takes a parameter.
+    NoParam,
+    /// A status mode like op or voice, which takes a nickname.
+    Status,
+}
+
+pub struct ServerInfo {
+    tokens: BTreeMap<String, String>,
+    chantypes: String,
+    prefix: Vec<(char, char)>,
+    chanmodes: [String; 4],
+    statusmsg: String,
+    casemapping: CaseMapping,
+    nicklen: Option<usize>,
+    modes: Option<usize>,
+    targmax: BTreeMap<String, Option<usize>>,
+}
+
+impl ServerInfo {
+    pub fn new() -> ServerInfo {
+        let mut info = ServerInfo {
+            tokens: BTreeMap::new(),
+            chantypes: String::new(),
+            prefix: Vec::new(),
+            chanmodes: Default::default(),
+            statusmsg: String::new(),
+            casemapping: CaseMapping::default(),
+            nicklen: None,
+            modes: None,
+            targmax: BTreeMap::new(),
+        };
+        for token in &["CHANTYPES", "PREFIX", "CHANMODES", "STATUSMSG", "CASEMAPPING",
+                       "NICKLEN", "MODES", "TARGMAX"] {
+            info.apply(token, None);
+        }
+        info
+    }
+
+    /// Handles the arguments of an `RPL_ISUPPORT` reply. The first is our
+    /// nickname and the last is a human readable message, and everything in
+    /// between is a token like `KEY`, `KEY=value` or `-KEY`.
+    pub fn handle(&mut self, args: &[String]) {
+        if args.len() < 3 {
+            return;
+        }
+
+        for token in &args[1..args.len() - 1] {
+            if token.starts_with('-') {
+                self.tokens.remove(&token[1..]);
+                self.apply(&token[1..], None);
+                continue;
+            }
+
+            let (key, value) = match token.find('=') {
+                Some(i) => (&token[..i], unescape(&token[i+1..])),
+                None => (&token[..], String::new()),
+            };
+
+            self.apply(key, Some(&value));
+            self.tokens.insert(key.to_string(), value);
+        }
+    }
+
+    /// Updates the parsed form of a token, or resets it to the default if
+    /// the server withdrew it.
+    fn apply(&mut self, key: &str, value: Option<&str>) {
+        match key {
+            "CHANTYPES" => {
+                self.chantypes = value.unwrap_or("#&").to_string();
+            },
+
+            "PREFIX" => {
+                self.prefix = parse_prefix(value.unwrap_or("(ov)@+")).unwrap_or_else(|| {
+                    warn!("ignoring invalid PREFIX {:?}", value);
+                    self.prefix.clone()
+                });
+            },
+
+            "CHANMODES" => {
+                let value = value.unwrap_or("beI,k,l,imnpst");
+                let mut groups = value.split(',').map(|g| g.to_string());
+                for group in self.chanmodes.iter_mut() {
+                    *group = groups.next().unwrap_or_default();
+                }
+            },
+
+            "STATUSMSG" => {
+                self.statusmsg = value.unwrap_or("").to_string();
+            },
+
+            "CASEMAPPING" => {
+                let name = value.unwrap_or("rfc1459");
+                self.casemapping = CaseMapping::from_name(name).unwrap_or_else(|| {
+                    warn!("unknown CASEMAPPING {}, assuming rfc1459", name);
+                    CaseMapping::Rfc1459
+                });
+            },
+
+            "NICKLEN" => self.nicklen = value.and_then(|v| v.parse().ok()),
+
+            "MODES" => {
+                self.modes = match value {
+                    // no value means no limit
+                    Some("") => None,
+                    Some(v) => v.parse().ok(),
+                    None => Some(3),
+                };
+            },
+
+            "TARGMAX" => {
+                self.targmax.clear();
+                for item in value.unwrap_or("").split(',').filter(|i| !i.is_empty()) {
+                    let mut parts = item.splitn(2, ':');
+                    let cmd = parts.next().unwrap_or("").to_ascii_uppercase();
+                    let max = parts.next().and_then(|m| m.parse().ok());
+                    self.targmax.insert(cmd, max);
+                }
+            },
+
+            _ => { },
+        }
+    }
+
+    /// The raw value of a token, or `None` if the server didn't send it.
+    /// Tokens without a value give an empty string.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.tokens.get(key).map(|v| &v[..])
+    }
+
+    /// Whether the name is a channel, judging by its first character.
+    pub fn is_channel(&self, name: &str) -> bool {
+        name.chars().next().map(|c| self.chantypes.contains(c)).unwrap_or(false)
+    }
+
+    /// Splits any `STATUSMSG` prefixes off a message target, so that a message
+    /// to `@#chan` can be recognized as being in `#chan`.
+    pub fn split_statusmsg<'a>(&self, target: &'a str) -> (&'a str, &'a str) {
+        let start = target.find(|c| !self.statusmsg.contains(c)).unwrap_or(target.len());
+        target.split_at(start)
+    }
+
+    /// The status modes and their prefixes, highest first.
+    pub fn prefix(&self) -> &[(char, char)] {
+        &self.prefix
+    }
+
+    /// The status mode shown with the given prefix, e.g. `'o'` for `'@'`.
+    pub fn mode_for_prefix(&self, prefix: char) -> Option<char> {
+        self.prefix.iter().find(|p| p.1 == prefix).map(|p| p.0)
+    }
+
+    /// Where a status mode ranks, with 0 being the highest.
+    pub fn status_rank(&self, mode: char) -> Option<usize> {
+        self.prefix.iter().position(|p| p.0 == mode)
+    }
+
+    /// Whether the status mode makes someone an operator, meaning it's `o`
+    /// or ranks above it.
+    pub fn is_op_mode(&self, mode: char) -> bool {
+        match (self.status_rank(mode), self.status_rank('o')) {
+            (Some(rank), Some(op)) => rank <= op,
+            (Some(_), None) => mode == 'o',
+            (None, _) => false,
+        }
+    }
+
+    /// How a channel mode takes parameters. Unknown modes are assumed not to
+    /// take any.
+    pub fn mode_kind(&self, mode: char) -> ModeKind {
+        if self.status_rank(mode).is_some() {
+            return ModeKind::Status;
+        }
+
+        let kinds = [ModeKind::List, ModeKind::AlwaysParam, ModeKind::SetParam, ModeKind::NoParam];
+        self.chanmodes.iter()
+            .position(|group| group.contains(mode))
+            .map(|i| kinds[i])
+            .unwrap_or(ModeKind::NoParam)
+    }
+
+    /// How the server compares nicknames and channel names.
+    pub fn casemapping(&self) -> CaseMapping {
+        self.casemapping
+    }
+
+    /// The longest nickname the server allows, if it said.
+    pub fn nicklen(&self) -> Option<usize> {
+        self.nicklen
+    }
+
+    /// The most modes with parameters that can be sent in one `MODE`, or
+    /// `None` if there's no limit.
+    pub fn max_modes(&self) -> Option<usize> {
+        self.modes
+    }
+
+    /// The most targets the command accepts at once, or `None` if there's no
+    /// limit. Commands the server didn't mention get one target, to be safe.
+    pub fn max_targets(&self, cmd: &str) -> Option<usize> {
+        match self.targmax.get(&cmd.to_ascii_uppercase()) {
+            Some(max) => *max,
+            None => Some(1),
+        }
+    }
+
+    /// The network's name, like `Libera.Chat`.
+    pub fn network(&self) -> Option<&str> {
+        self.get("NETWORK")
+    }
+}
+
+/// Parses a `PREFIX` value like `(ov)@+`.
+fn parse_prefix(value: &str) -> Option<Vec<(char, char)>> {
+    if value.is_empty() {
+        return Some(Vec::new());
+    }
+    if !value.starts_with('(') {
+        return None;
+    }
+
+    let close = match value.find(')') {
+        Some(i) => i,
+        None => return None,
+    };
+    let modes = &value[1..close];
+    let prefixes = &value[close + 1..];
+
+    if modes.chars().count() != prefixes.chars().count() {
+        return None;
+    }
+
+    Some(modes.chars().zip(prefixes.chars()).collect())
+}
+
+/// Undoes the `\xHH` escapes allowed in token values.
+fn unescape(value: &str) -> String {
+    let mut out = Vec::with_capacity(value.len());
+    let bytes = value.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = i + 4 <= bytes.len() &&
+            bytes[i] == b'\\' && bytes[i + 1] == b'x' &&
+            bytes[i + 2].is_ascii_hexdigit() && bytes[i + 3].is_ascii_hexdigit();
+        if escaped {
+            if let Ok(b) = u8::from_str_radix(&value[i + 2..i + 4], 16) {
+                out.push(b);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+fn isupport(tokens: &[&str]) -> ServerInfo {
+    let mut args = vec!["miau".to_string()];
+    args.extend(tokens.iter().map(|t| t.to_string()));
+    args.push("are supported by this server".to_string());
+
+    let mut info = ServerInfo::new();
+    info.handle(&args);
+    info
+}
+
+#[test]
+fn isupport_defaults() {
+    let info = ServerInfo::new();
+    assert!(info.is_channel("#miau-dev"));
+    assert!(info.is_channel("&local"));
+    assert!(!info.is_channel("aji"));
+    assert!(!info.is_channel(""));
+    assert_eq!(info.prefix(), &[('o', '@'), ('v', '+')]);
+    assert_eq!(info.casemapping(), CaseMapping::Rfc1459);
+    assert_eq!(info.max_modes(), Some(3));
+    assert_eq!(info.mode_kind('b'), ModeKind::List);
+    assert_eq!(info.mode_kind('l'), ModeKind::SetParam);
+    assert_eq!(info.network(), None);
+}
+
+#[test]
+fn isupport_tokens() {
+    let info = isupport(&[
+        "CHANTYPES=#", "PREFIX=(qaohv)~&@%+", "CHANMODES=beIq,k,flj,CFLMPQcgimnprstz",
+        "CASEMAPPING=ascii", "NICKLEN=16", "MODES=4", "NETWORK=Example\\x20Net",
+        "STATUSMSG=@+", "TARGMAX=PRIVMSG:4,NOTICE:4,JOIN:", "EXCEPTS",
+    ]);
+
+    assert!(!info.is_channel("&local"));
+    assert_eq!(info.mode_for_prefix('~'), Some('q'));
+    assert_eq!(info.mode_kind('o'), ModeKind::Status);
+    assert_eq!(info.mode_kind('k'), ModeKind::AlwaysParam);
+    assert_eq!(info.mode_kind('j'), ModeKind::SetParam);
+    assert_eq!(info.mode_kind('m'), ModeKind::NoParam);
+    assert!(info.is_op_mode('a'));
+    assert!(info.is_op_mode('o'));
+    assert!(!info.is_op_mode('h'));
+    assert_eq!(info.casemapping(), CaseMapping::Ascii);
+    assert_eq!(info.nicklen(), Some(16));
+    assert_eq!(info.max_modes(), Some(4));
+    assert_eq!(info.network(), Some("Example Net"));
+    assert_eq!(info.split_statusmsg("@#chan"), ("@", "#chan"));
+    assert_eq!(info.split_statusmsg("#chan"), ("", "#chan"));
+    assert_eq!(info.max_targets("privmsg"), Some(4));
+    assert_eq!(info.max_targets("JOIN"), None);
+    assert_eq!(info.max_targets("KICK"), Some(1));
+    assert_eq!(info.get("EXCEPTS"), Some(""));
+}
+
+#[test]
+fn isupport_negation() {
+    let mut info = isupport(&["CHANTYPES=#", "NETWORK=Test"]);
+    info.handle(&["miau".to_string(), "-CHANTYPES".to_string(), "-NETWORK".to_string(),
+                  "are supported".to_string()]);
+    assert!(info.is_channel("&local"));
+    assert_eq!(info.network(), None);
+}
+
+#[test]
+fn isupport_bad_prefix() {
+    let info = isupport(&["PREFIX=(ov)@"]);
+    assert_eq!(info.prefix(), &[('o', '@'), ('v', '+')]);
+}
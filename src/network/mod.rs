@@ -0,0 +1,717 @@
+//! IRC network state management and protocol handling.
+//!
+//! Most socket-level nastiness is in bot.rs
+
+use std::io;
+use std::time::Duration;
+use std::time::Instant;
+
+use environment::Env;
+use irc::Command;
+use irc::Message;
+use irc::OwnedMessage;
+use irc::OwnedSource;
+use irc::Params;
+use irc::text;
+
+pub use self::cap::Caps;
+pub use self::channels::Channel;
+pub use self::channels::Channels;
+pub use self::channels::Member;
+pub use self::channels::Topic;
+pub use self::channels::User;
+pub use self::isupport::ModeKind;
+pub use self::isupport::ServerInfo;
+
+use self::nick::Nicks;
+use self::sasl::Progress;
+use self::sasl::Sasl;
+
+mod cap;
+mod channels;
+mod isupport;
+mod nick;
+mod sasl;
+
+pub struct Network {
+    env: Env,
+    state: State,
+    nicks: Nicks,
+    caps: Caps,
+    sasl: Option<Sasl>,
+    server: ServerInfo,
+    lag: Option<Duration>,
+    quitting: bool,
+}
+
+enum State {
+    Registering(Registration),
+    Active(Active),
+}
+
+struct Registration {
+    last_requested_nick: String,
+    cap_ended: bool,
+}
+
+struct Active {
+    nick: String,
+    user: Option<String>,
+    host: Option<String>,
+    channels: Channels,
+}
+
+impl Network {
+    pub fn register<T: Output>(env: Env, out: &mut T) -> Network {
+        let nicks = Nicks::from_env(&env);
+        let nick = nicks.desired().to_string();
+
+        let mut caps = Caps::from_env(&env);
+        let sasl = Sasl::from_env(&env);
+        if sasl.is_some() {
+            caps.want("sasl");
+        }
+
+        caps.start(out);
+        out.NICK(&nick);
+        out.USER("miau", env!("CARGO_PKG_HOMEPAGE"));
+
+        let reg = Registration { last_requested_nick: nick, cap_ended: false };
+        Network {
+            env: env,
+            state: State::Registering(reg),
+            nicks: nicks,
+            caps: caps,
+            sasl: sasl,
+            server: ServerInfo::new(),
+            lag: None,
+            quitting: false,
+        }
+    }
+
+    pub fn current_nick(&self) -> Option<&str> {
+        match &self.state {
+            &State::Active(ref act) => Some(&act.nick[..]),
+            _ => None,
+        }
+    }
+
+    /// What the server has told us about itself with `RPL_ISUPPORT`.
+    pub fn server_info(&self) -> &ServerInfo {
+        &self.server
+    }
+
+    /// The channels we're in, once we're connected.
+    pub fn channels(&self) -> Option<&Channels> {
+        match &self.state {
+            &State::Active(ref act) => Some(&act.channels),
+            _ => None,
+        }
+    }
+
+    /// Looks up a channel we're in.
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels().and_then(|c| c.get(name))
+    }
+
+    /// The round trip time of the most recent ping to the server, if any
+    /// have been answered yet.
+    pub fn lag(&self) -> Option<Duration> {
+        self.lag
+    }
+
+    pub fn set_lag(&mut self, lag: Duration) {
+        self.lag = Some(lag);
+    }
+
+    /// The capabilities negotiated with the server.
+    pub fn caps(&self) -> &Caps {
+        &self.caps
+    }
+
+    /// Whether the server has acknowledged the capability.
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.caps.is_enabled(cap)
+    }
+
+    /// Whether the message is one of our own, sent back to us because of
+    /// `echo-message`.
+    pub fn is_echo(&self, m: &OwnedMessage) -> bool {
+        let me = match self.current_nick() {
+            Some(me) if self.has_cap("echo-message") => me,
+            _ => return false,
+        };
+        match m.command {
+            Command::Privmsg(..) | Command::Notice(..) =>
+                m.src.nick().map(|n| self.server.casemapping().equals(n, me)).unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// Changes our nick, and makes it the one we try to get back if we lose
+    /// it, until the next time we connect.
+    pub fn change_nick<T: Output + ?Sized>(&mut self, out: &mut T, nick: &str) {
+        self.nicks.set_desired(out, nick);
+        out.NICK(nick);
+    }
+
+    /// Leaves the server. The connection closing after this is expected, and
+    /// shouldn't be followed by a reconnect.
+    pub fn quit<T: Output + ?Sized>(&mut self, out: &mut T, reason: Option<&str>) {
+        info!("quitting: {}", reason.unwrap_or("no reason given"));
+        self.quitting = true;
+        out.QUIT(reason);
+    }
+
+    /// Whether we've sent a `QUIT`.
+    pub fn is_quitting(&self) -> bool {
+        self.quitting
+    }
+
+    /// Handles a message from the server. An error means the connection
+    /// should be dropped.
+    pub fn handle_message<T: Output>(&mut self, out: &mut T, m: &OwnedMessage) -> io::Result<()> {
+        if let Command::Ping(ref token) = m.command {
+            out.PONG(token);
+            return Ok(());
+        }
+
+        if let Command::Raw(ref verb, ref args) = m.command {
+            if verb == "CAP" {
+                self.handle_cap(out, args);
+                return self.try_end_cap(out);
+            }
+        }
+
+        if let Command::Numeric(5, ref args) = m.command { // RPL_ISUPPORT
+            self.server.handle(args);
+        }
+
+        if let State::Registering(ref mut reg) = self.state {
+            if let Command::Numeric(num @ 432...437, _) = m.command {
+                if num != 434 && num != 435 {
+                    return reg.handle_nick_error(out, &mut self.nicks, num);
+                }
+            }
+        }
+
+        if let State::Active(ref act) = self.state {
+            self.nicks.handle(out, &act.nick, &self.server, m, Instant::now());
+        }
+
+        if let State::Registering(_) = self.state {
+            let handled = match self.sasl {
+                Some(ref mut sasl) => sasl.handle(out, m),
+                None => false,
+            };
+            if handled {
+                return self.try_end_cap(out);
+            }
+        }
+
+        let next_state = match self.state {
+            State::Registering(ref mut reg) => reg.handle(out, m),
+            State::Active(ref mut act) => act.handle(out, &self.server, m),
+        };
+
+        if let Some(state) = next_state {
+            if let Some(ref sasl) = self.sasl {
+                // the server welcomed us without ever getting to SASL
+                if sasl.required() && !sasl.succeeded() {
+                    error!("registered without SASL authentication");
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "registered without SASL authentication"
+                    ));
+                }
+            }
+
+            self.state = state;
+            if let State::Active(_) = self.state {
+                // a server that doesn't know CAP will have ignored it
+                self.caps.abandon();
+                self.on_become_active(out);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_cap<T: Output>(&mut self, out: &mut T, args: &[String]) {
+        if let Err(e) = self.caps.handle(out, args) {
+            warn!("ignoring CAP: {}", e);
+        }
+    }
+
+    /// Ends capability negotiation during registration, once the server has
+    /// answered our requests and SASL is out of the way.
+    fn try_end_cap<T: Output>(&mut self, out: &mut T) -> io::Result<()> {
+        let reg = match self.state {
+            State::Registering(ref mut reg) => reg,
+            _ => return Ok(()),
+        };
+
+        if reg.cap_ended || !self.caps.settled() {
+            return Ok(());
+        }
+
+        if let Some(ref mut sasl) = self.sasl {
+            match sasl.poll(out, &self.caps) {
+                Progress::Continue => return Ok(()),
+                Progress::Finished => { },
+                Progress::Failed(ref why) if sasl.required() => {
+                    error!("SASL authentication failed: {}", why);
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("SASL authentication failed: {}", why)
+                    ));
+                },
+                Progress::Failed(ref why) => {
+                    warn!("SASL authentication failed, continuing anyway: {}", why);
+                },
+            }
+        }
+
+        out.send_message(&Message::new("CAP", vec!["END"]));
+        reg.cap_ended = true;
+        Ok(())
+    }
+
+    fn for_each_autojoin_chan<F: FnMut(&str)>(&self, mut f: F) {
+        if let Some(chans) = self.env.conf_array("irc.channels") {
+            for c in chans.iter().filter_map(|c| c.as_str()) {
+                f(c);
+            }
+        } else {
+            f("#miau-dev");
+        }
+    }
+
+    fn on_become_active<T: Output>(&mut self, out: &mut T) {
+        if let State::Active(ref act) = self.state {
+            self.nicks.start(out, &act.nick, &self.server, Instant::now());
+        }
+        self.for_each_autojoin_chan(|c| out.JOIN(c));
+    }
+
+    /// When `tick` next needs to be called, if at all.
+    pub fn next_tick(&self) -> Option<Instant> {
+        self.nicks.next_tick()
+    }
+
+    /// Does anything that was scheduled for `now` or earlier.
+    pub fn tick<T: Output>(&mut self, out: &mut T, now: Instant) {
+        self.nicks.tick(out, now);
+    }
+}
+
+#[allow(non_snake_case)]
+pub trait Output {
+    /// Queues a line to be sent, subject to flood control.
+    fn send(&mut self, line: String);
+
+    /// Sends a line ahead of anything queued with `send`. This is meant for
+    /// protocol traffic that shouldn't have to wait behind a long reply.
+    fn send_urgent(&mut self, line: String) {
+        self.send(line);
+    }
+
+    /// Encodes and sends the message. Messages that can't be represented on
+    /// the wire are logged and dropped rather than sent malformed.
+    fn send_message(&mut self, m: &Message) {
+        match m.encode() {
+            Ok(line) if is_urgent(m.verb) => self.send_urgent(line),
+            Ok(line) => self.send(line),
+            Err(e) => error!("refusing to send {:?}: {}", m, e),
+        }
+    }
+
+    fn NICK(&mut self, nick: &str) {
+        self.send_message(&Message::new("NICK", vec![nick]));
+    }
+
+    fn USER(&mut self, ident: &str, gecos: &str) {
+        self.send_message(&Message::new("USER", vec![ident, "*", "*", gecos]));
+    }
+
+    fn JOIN(&mut self, chan: &str) {
+        self.send_message(&Message::new("JOIN", vec![chan]));
+    }
+
+    fn PART(&mut self, chan: &str, reason: Option<&str>) {
+        let mut args = vec![chan];
+        args.extend(reason);
+        self.send_message(&Message::new("PART", args));
+    }
+
+    fn QUIT(&mut self, reason: Option<&str>) {
+        self.send_message(&Message::new("QUIT", reason.into_iter().collect()));
+    }
+
+    /// Sends a `MODE` change, like `MODE #chan +o aji` for
+    /// `MODE("#chan", &["+o", "aji"])`.
+    fn MODE(&mut self, target: &str, changes: &[&str]) {
+        let mut args = vec![target];
+        args.extend_from_slice(changes);
+        self.send_message(&Message::new("MODE", args));
+    }
+
+    fn KICK(&mut self, chan: &str, nick: &str, reason: Option<&str>) {
+        let mut args = vec![chan, nick];
+        args.extend(reason);
+        self.send_message(&Message::new("KICK", args));
+    }
+
+    fn TOPIC(&mut self, chan: &str, topic: &str) {
+        self.send_message(&Message::new("TOPIC", vec![chan, topic]));
+    }
+
+    fn PONG(&mut self, token: &str) {
+        self.send_message(&Message::new("PONG", vec![token]));
+    }
+
+    fn NOTICE(&mut self, target: &str, text: &str) {
+        self.send_text("NOTICE", target, text);
+    }
+
+    fn PRIVMSG(&mut self, target: &str, text: &str) {
+        self.send_text("PRIVMSG", target, text);
+    }
+
+    /// Sends text with `PRIVMSG` or `NOTICE`, split into as many lines as it
+    /// takes for none of them to be cut off by the server. If there are more
+    /// lines than `max_lines`, the rest are replaced with a count.
+    fn send_text(&mut self, verb: &str, target: &str, text: &str) {
+        let max_len = text::payload_len(self.source_len(), verb, target);
+        let lines = text::split_lines(text, max_len);
+        let max_lines = self.max_lines().unwrap_or(lines.len()).max(1);
+
+        for line in lines.iter().take(max_lines) {
+            self.send_message(&Message::new(verb, vec![target, line]));
+        }
+
+        if lines.len() > max_lines {
+            let more = format!("({} more lines)", lines.len() - max_lines);
+            self.send_message(&Message::new("NOTICE", vec![target, &more]));
+        }
+    }
+
+    /// The length of our `nick!user@host` as other clients see it, which is
+    /// needed to work out how much text fits in a line.
+    fn source_len(&self) -> usize {
+        text::DEFAULT_SOURCE_LEN
+    }
+
+    /// Updates what we know about our own `nick!user@host`.
+    fn set_source(&mut self, _nick: &str, _user: Option<&str>, _host: Option<&str>) {
+    }
+
+    /// The most lines a single call to `send_text` should produce.
+    fn max_lines(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Whether messages with this verb should skip the queue. Keepalives and
+/// registration traffic go first, since the server won't wait for them.
+fn is_urgent(verb: &str) -> bool {
+    match verb {
+        "PING" | "PONG" | "PASS" | "CAP" | "AUTHENTICATE" | "NICK" | "USER" | "QUIT" => true,
+        _ => false,
+    }
+}
+
+impl Registration {
+    /// Handles the server rejecting the nick we asked for, with
+    /// `ERR_ERRONEUSNICKNAME`, `ERR_NICKNAMEINUSE`, `ERR_NICKCOLLISION` or
+    /// `ERR_UNAVAILRESOURCE`.
+    fn handle_nick_error<T: Output>(&mut self, out: &mut T, nicks: &mut Nicks, num: u16)
+    -> io::Result<()> {
+        let erroneous = num == 432;
+        match nicks.next_candidate(&self.last_requested_nick, erroneous) {
+            Some(nick) => {
+                warn!("can't use {} ({}), trying {}", self.last_requested_nick, num, nick);
+                out.NICK(&nick);
+                self.last_requested_nick = nick;
+                Ok(())
+            },
+            None => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "couldn't find a nick the server would accept"
+            )),
+        }
+    }
+
+    fn handle<T: Output>(&mut self, out: &mut T, m: &OwnedMessage) -> Option<State> {
+        match m.command {
+            Command::Numeric(1, ref args) => { // RPL_WELCOME
+                let my_nick = match args.param(0) {
+                    Ok(nick) => nick.to_string(),
+                    Err(e) => {
+                        warn!("ignoring RPL_WELCOME: {}", e);
+                        return None;
+                    }
+                };
+                debug!("my nick is {}", my_nick);
+                out.set_source(&my_nick, None, None);
+                return Some(State::Active(Active {
+                    nick: my_nick,
+                    user: None,
+                    host: None,
+                    channels: Channels::new(),
+                }));
+            },
+
+            _ => { }
+        }
+
+        None
+    }
+}
+
+impl Active {
+    fn handle<T: Output>(&mut self, out: &mut T, info: &ServerInfo, m: &OwnedMessage)
+    -> Option<State> {
+        self.channels.handle(out, &self.nick, info, m);
+
+        let casemap = info.casemapping();
+        let from_me = m.src.nick().map(|n| casemap.equals(n, &self.nick)).unwrap_or(false);
+
+        match m.command {
+            Command::Join(..) if from_me => {
+                // our own JOIN is the first place we see our full source
+                if let OwnedSource::User(_, ref user, ref host) = m.src {
+                    self.user = user.clone();
+                    self.host = host.clone();
+                    self.update_source(out);
+                }
+            },
+
+            Command::Nick(ref nick) if from_me => {
+                debug!("my nick is now {}", nick);
+                self.nick = nick.clone();
+                self.update_source(out);
+            },
+
+            Command::Raw(ref verb, ref args) if from_me && verb == "CHGHOST" => {
+                if let (Ok(user), Ok(host)) = (args.param(0), args.param(1)) {
+                    self.user = Some(user.to_string());
+                    self.host = Some(host.to_string());
+                    self.update_source(out);
+                }
+            },
+
+            Command::Numeric(396, ref args) => { // RPL_VISIBLEHOST
+                if let Ok(host) = args.param(1) {
+                    self.host = Some(host.to_string());
+                    self.update_source(out);
+                }
+            },
+
+            _ => { }
+        }
+
+        None
+    }
+
+    fn update_source<T: Output>(&self, out: &mut T) {
+        let user = self.user.as_ref().map(|u| &u[..]);
+        let host = self.host.as_ref().map(|h| &h[..]);
+        out.set_source(&self.nick, user, host);
+    }
+}
+
+#[cfg(test)]
+impl Output for Vec<String> {
+    fn send(&mut self, line: String) {
+        self.push(line);
+    }
+}
+
+#[test]
+fn output_helpers_build_messages() {
+    let mut out = Vec::new();
+    out.NICK("miau");
+    out.USER("miau", "https://github.com/aji/miau");
+    out.PRIVMSG("#miau-dev", "hello there");
+    out.NOTICE("aji", ":3");
+    out.PONG("irc.example.com");
+
+    assert_eq!(out, vec![
+        "NICK miau",
+        "USER miau * * https://github.com/aji/miau",
+        "PRIVMSG #miau-dev :hello there",
+        "NOTICE aji ::3",
+        "PONG irc.example.com",
+    ]);
+}
+
+#[test]
+fn output_splits_long_text() {
+    let mut out = Vec::new();
+    let text = vec!["meow"; 200].join(" ");
+    out.PRIVMSG("#miau-dev", &text);
+
+    assert!(out.len() > 1);
+    for line in out.iter() {
+        let relayed = text::DEFAULT_SOURCE_LEN + 2 + line.len();
+        assert!(relayed <= text::MAX_LINE_LEN);
+        assert!(line.ends_with("meow"));
+    }
+
+    let total: usize = out.iter().map(|l| l.matches("meow").count()).sum();
+    assert_eq!(total, 200);
+}
+
+#[cfg(test)]
+struct CappedOutput(Vec<String>);
+
+#[cfg(test)]
+impl Output for CappedOutput {
+    fn send(&mut self, line: String) {
+        self.0.push(line);
+    }
+
+    fn max_lines(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+#[test]
+fn output_caps_reply_lines() {
+    let mut out = CappedOutput(Vec::new());
+    out.PRIVMSG("#miau-dev", "one\ntwo\nthree\nfour");
+
+    assert_eq!(out.0, vec![
+        "PRIVMSG #miau-dev one",
+        "PRIVMSG #miau-dev two",
+        "NOTICE #miau-dev :(2 more lines)",
+    ]);
+}
+
+#[test]
+fn output_drops_invalid_messages() {
+    let mut out = Vec::new();
+    out.PRIVMSG("#miau-dev", "hi\0QUIT :pwned");
+    out.send_message(&Message::new("KICK", vec!["#miau-dev", "", "bye"]));
+    assert!(out.is_empty());
+}
+
+#[cfg(test)]
+fn feed<T: Output>(net: &mut Network, out: &mut T, line: &str) {
+    net.handle_message(out, &OwnedMessage::parse(line).unwrap()).unwrap();
+}
+
+#[test]
+fn network_registers_with_caps() {
+    let env = ::environment::from_str(r##"
+        [irc]
+        nick = "miau"
+        caps = ["server-time", "echo-message"]
+        channels = ["#miau-dev"]
+    "##);
+
+    let mut out = Vec::new();
+    let mut net = Network::register(env, &mut out);
+    assert_eq!(out[0], "CAP LS 302");
+    assert_eq!(out[1], "NICK miau");
+    out.clear();
+
+    feed(&mut net, &mut out, ":irc.test CAP * LS :server-time sasl");
+    feed(&mut net, &mut out, ":irc.test CAP * ACK :server-time");
+    assert_eq!(out, vec!["CAP REQ server-time", "CAP END"]);
+    assert!(net.has_cap("server-time"));
+    assert!(!net.has_cap("echo-message"));
+    out.clear();
+
+    feed(&mut net, &mut out, ":irc.test 001 miau :Welcome");
+    assert_eq!(out, vec!["JOIN #miau-dev"]);
+    assert_eq!(net.current_nick(), Some("miau"));
+
+    feed(&mut net, &mut out, ":irc.test CAP miau NEW :echo-message");
+    assert_eq!(out[1], "CAP REQ echo-message");
+    feed(&mut net, &mut out, ":irc.test CAP miau ACK :echo-message");
+    assert!(net.has_cap("echo-message"));
+    feed(&mut net, &mut out, ":irc.test CAP miau DEL :echo-message");
+    assert!(!net.has_cap("echo-message"));
+}
+
+#[test]
+fn network_sasl_required() {
+    let env = ::environment::from_str(r##"
+        [irc.sasl]
+        username = "miau"
+        password = "hunter2"
+        required = true
+    "##);
+
+    let mut out = Vec::new();
+    let mut net = Network::register(env, &mut out);
+    out.clear();
+
+    feed(&mut net, &mut out, ":irc.test CAP * LS :sasl=PLAIN");
+    feed(&mut net, &mut out, ":irc.test CAP * ACK :sasl");
+    assert_eq!(out, vec!["CAP REQ sasl", "AUTHENTICATE PLAIN"]);
+
+    let failed = OwnedMessage::parse(":irc.test 904 miau :SASL authentication failed").unwrap();
+    assert!(net.handle_message(&mut out, &failed).is_err());
+    assert!(!out.iter().any(|l| l == "CAP END"));
+
+    // a server that ignores CAP doesn't get to skip SASL
+    let env = ::environment::from_str("[irc.sasl]\nusername = \"miau\"\nrequired = true");
+    let mut net = Network::register(env, &mut out);
+    out.clear();
+
+    let welcome = OwnedMessage::parse(":irc.test 001 miau :Welcome").unwrap();
+    assert!(net.handle_message(&mut out, &welcome).is_err());
+    assert_eq!(net.current_nick(), None);
+    assert!(out.is_empty());
+}
+
+#[test]
+fn network_uses_isupport() {
+    let env = ::environment::from_str("[irc]\nchannels = []");
+    let mut out = Vec::new();
+    let mut net = Network::register(env, &mut out);
+
+    feed(&mut net, &mut out, ":irc.test 001 miau :Welcome");
+    feed(&mut net, &mut out, ":irc.test 005 miau PREFIX=(qaohv)~&@%+ CHANTYPES=# :are supported");
+    feed(&mut net, &mut out, ":miau!m@h JOIN #miau-dev");
+    feed(&mut net, &mut out, ":irc.test 353 miau = #miau-dev :miau ~aji %pony");
+
+    assert!(!net.server_info().is_channel("&local"));
+    let chan = net.channel("#miau-dev").unwrap();
+    assert!(chan.member("aji").unwrap().is_op());
+    assert!(!chan.member("pony").unwrap().is_op());
+    assert_eq!(chan.member("pony").unwrap().modes(), "h");
+}
+
+#[test]
+fn network_nick_in_use() {
+    let env = ::environment::from_str(r##"
+        [irc]
+        nick = "miau"
+        alt_nicks = ["meow"]
+        channels = []
+    "##);
+
+    let mut out = Vec::new();
+    let mut net = Network::register(env, &mut out);
+    out.clear();
+
+    feed(&mut net, &mut out, ":irc.test 433 * miau :Nickname is already in use");
+    feed(&mut net, &mut out, ":irc.test 433 * meow :Nickname is already in use");
+    assert_eq!(out, vec!["NICK meow", "NICK meow_"]);
+    out.clear();
+
+    feed(&mut net, &mut out, ":irc.test 001 meow_ :Welcome");
+    let now = net.next_tick().unwrap();
+    net.tick(&mut out, now);
+    assert_eq!(out, vec!["ISON miau"]);
+
+    feed(&mut net, &mut out, ":irc.test 303 meow_ :");
+    feed(&mut net, &mut out, ":meow_!m@h NICK :miau");
+    assert_eq!(out[1], "NICK miau");
+    assert_eq!(net.current_nick(), Some("miau"));
+    assert_eq!(net.next_tick(), None);
+}
@@ -0,0 +1,351 @@
+//! Choosing a nickname, and getting ours back when we can't have it.
+//!
+//! If `irc.nick` is taken when we connect, we work through `irc.alt_nicks`
+//! and then make something up. Once connected, we watch for the nick we
+//! wanted to become free, using `MONITOR` if the server has it or polling
+//! with `ISON` every `irc.regain.interval` seconds if not, and switch back
+//! when it does. If `irc.regain.method` is set, we also ask NickServ to
+//! `GHOST` or `REGAIN` it for us.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use rand;
+
+use environment::Env;
+use irc::Command;
+use irc::Message;
+use irc::OwnedMessage;
+use network::Output;
+use network::ServerInfo;
+
+/// How many nicks to try during registration before giving up.
+const MAX_ATTEMPTS: usize = 20;
+
+pub struct Nicks {
+    desired: String,
+    alternates: Vec<String>,
+    attempts: usize,
+    regain: Option<Regain>,
+    monitoring: bool,
+    next_check: Option<Instant>,
+}
+
+struct Regain {
+    interval: Duration,
+    nickserv: Option<NickServ>,
+}
+
+struct NickServ {
+    service: String,
+    command: &'static str,
+    password: String,
+}
+
+impl Nicks {
+    pub fn from_env(env: &Env) -> Nicks {
+        let desired = env.conf_str("irc.nick").unwrap_or("miau").to_string();
+        let alternates = env.conf_array("irc.alt_nicks")
+            .map(|a| a.iter().filter_map(|n| n.as_str()).map(|n| n.to_string()).collect())
+            .unwrap_or_else(Vec::new);
+
+        let regain = if env.conf_bool("irc.regain.enabled").unwrap_or(true) {
+            Some(Regain {
+                interval: env.conf_duration_or("irc.regain.interval", Duration::from_secs(60))
+                    .max(Duration::from_secs(1)),
+                nickserv: NickServ::from_env(env),
+            })
+        } else {
+            None
+        };
+
+        Nicks {
+            desired: desired,
+            alternates: alternates,
+            attempts: 0,
+            regain: regain,
+            monitoring: false,
+            next_check: None,
+        }
+    }
+
+    /// The nick we want.
+    pub fn desired(&self) -> &str {
+        &self.desired
+    }
+
+    /// Picks the nick to try after the server rejected one during
+    /// registration. `erroneous` is whether the server said the nick isn't
+    /// allowed at all, as opposed to being in use.
+    pub fn next_candidate(&mut self, rejected: &str, erroneous: bool) -> Option<String> {
+        self.attempts += 1;
+        if self.attempts >= MAX_ATTEMPTS {
+            return None;
+        }
+
+        if let Some(alt) = self.alternates.get(self.attempts - 1) {
+            return Some(alt.clone());
+        }
+
+        if erroneous {
+            // probably too long. try something short
+            let stem: String = self.desired.chars().take(5).collect();
+            return Some(format!("{}{}", stem, rand::random::<u16>() % 10000));
+        }
+
+        Some(format!("{}_", rejected))
+    }
+
+    /// Called once we're registered. If we didn't get the nick we wanted,
+    /// starts trying to get it back.
+    pub fn start<T: Output>(&mut self, out: &mut T, current: &str, info: &ServerInfo,
+                            now: Instant) {
+        if info.casemapping().equals(current, &self.desired) {
+            return;
+        }
+
+        let regain = match self.regain {
+            Some(ref regain) => regain,
+            None => return,
+        };
+
+        info!("we wanted to be {}, trying to get it back", self.desired);
+
+        if let Some(ref ns) = regain.nickserv {
+            let text = format!("{} {} {}", ns.command, self.desired, ns.password);
+            out.PRIVMSG(&ns.service, &text);
+        }
+
+        if info.get("MONITOR").is_some() {
+            out.send_message(&Message::new("MONITOR", vec!["+", &self.desired]));
+            self.monitoring = true;
+        } else {
+            self.next_check = Some(now);
+        }
+    }
+
+    /// Makes `nick` the one we want, giving up on getting the old one back.
+    pub fn set_desired<T: Output + ?Sized>(&mut self, out: &mut T, nick: &str) {
+        self.stop(out);
+        self.desired = nick.to_string();
+    }
+
+    /// Stops watching for the nick, now that it's ours.
+    fn stop<T: Output + ?Sized>(&mut self, out: &mut T) {
+        if self.monitoring {
+            out.send_message(&Message::new("MONITOR", vec!["-", &self.desired]));
+            self.monitoring = false;
+        }
+        self.next_check = None;
+    }
+
+    fn take_nick<T: Output>(&mut self, out: &mut T) {
+        debug!("{} looks free, taking it", self.desired);
+        out.NICK(&self.desired);
+    }
+
+    /// Handles a message while we're connected. `current` is our nick before
+    /// the message.
+    pub fn handle<T: Output>(&mut self, out: &mut T, current: &str, info: &ServerInfo,
+                             m: &OwnedMessage, now: Instant) {
+        let casemap = info.casemapping();
+        let desired = self.desired.clone();
+        let is_desired = |nick: &str| casemap.equals(nick, &desired);
+        let from_me = m.src.nick().map(|n| casemap.equals(n, current)).unwrap_or(false);
+
+        match m.command {
+            Command::Nick(ref nick) if from_me => {
+                if is_desired(nick) {
+                    info!("got our nick back");
+                    self.stop(out);
+                } else if is_desired(current) {
+                    // something changed our nick. try to change it back
+                    self.start(out, nick, info, now);
+                }
+            },
+
+            Command::Numeric(303, ref args) if self.next_check.is_some() => { // RPL_ISON
+                let online = args.get(1).map(|a| &a[..]).unwrap_or("");
+                if !online.split(' ').any(|n| is_desired(n)) {
+                    self.take_nick(out);
+                }
+            },
+
+            Command::Numeric(731, ref args) if self.monitoring => { // RPL_MONOFFLINE
+                let offline = args.get(1).map(|a| &a[..]).unwrap_or("");
+                if offline.split(',').any(|n| is_desired(n)) {
+                    self.take_nick(out);
+                }
+            },
+
+            Command::Numeric(432, ref args) => { // ERR_ERRONEUSNICKNAME
+                if args.get(1).map(|n| is_desired(n)) == Some(true) {
+                    warn!("server says {} isn't a valid nick, giving up on it", self.desired);
+                    self.stop(out);
+                    self.regain = None;
+                }
+            },
+
+            Command::Numeric(433, ref args) | // ERR_NICKNAMEINUSE
+            Command::Numeric(437, ref args) => { // ERR_UNAVAILRESOURCE
+                // someone beat us to it, or the nick is being held for a while
+                // after a ghost. with MONITOR we might not hear about it being
+                // free again, so try again later
+                if args.get(1).map(|n| is_desired(n)) == Some(true) && self.monitoring {
+                    let interval = self.regain.as_ref().map(|r| r.interval);
+                    self.next_check = interval.map(|i| now + i);
+                }
+            },
+
+            _ => { },
+        }
+    }
+
+    /// When `tick` next needs to be called, if at all.
+    pub fn next_tick(&self) -> Option<Instant> {
+        self.next_check
+    }
+
+    /// Checks on the nick if it's time to.
+    pub fn tick<T: Output>(&mut self, out: &mut T, now: Instant) {
+        match self.next_check {
+            Some(at) if at <= now => { },
+            _ => return,
+        }
+
+        if self.monitoring {
+            // waiting out a 437. just try again
+            self.next_check = None;
+            self.take_nick(out);
+        } else {
+            out.send_message(&Message::new("ISON", vec![&self.desired]));
+            let interval = self.regain.as_ref().map(|r| r.interval);
+            self.next_check = interval.map(|i| now + i);
+        }
+    }
+}
+
+impl NickServ {
+    fn from_env(env: &Env) -> Option<NickServ> {
+        let command = match env.conf_str("irc.regain.method") {
+            Some(m) if m.eq_ignore_ascii_case("ghost") => "GHOST",
+            Some(m) if m.eq_ignore_ascii_case("regain") => "REGAIN",
+            Some(m) => {
+                warn!("unknown irc.regain.method {}, not using NickServ", m);
+                return None;
+            },
+            None => return None,
+        };
+
+        let password = env.conf_str("irc.regain.password")
+            .or_else(|| env.conf_str("irc.sasl.password"));
+        let password = match password {
+            Some(p) => p.to_string(),
+            None => {
+                warn!("irc.regain.method is set, but there's no password to use");
+                return None;
+            },
+        };
+
+        Some(NickServ {
+            service: env.conf_str("irc.regain.service").unwrap_or("NickServ").to_string(),
+            command: command,
+            password: password,
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_nicks(config: &str) -> Nicks {
+    Nicks::from_env(&::environment::from_str(config))
+}
+
+#[cfg(test)]
+fn test_info(tokens: &str) -> ServerInfo {
+    let mut info = ServerInfo::new();
+    let mut args = vec!["miau".to_string()];
+    args.extend(tokens.split(' ').map(|t| t.to_string()));
+    args.push("are supported".to_string());
+    info.handle(&args);
+    info
+}
+
+#[cfg(test)]
+fn msg(line: &str) -> OwnedMessage {
+    OwnedMessage::parse(line).unwrap()
+}
+
+#[test]
+fn nick_candidates() {
+    let mut nicks = test_nicks("[irc]\nnick = \"miau\"\nalt_nicks = [\"meow\", \"nyan\"]");
+    assert_eq!(nicks.next_candidate("miau", false), Some("meow".to_string()));
+    assert_eq!(nicks.next_candidate("meow", false), Some("nyan".to_string()));
+    assert_eq!(nicks.next_candidate("nyan", false), Some("nyan_".to_string()));
+
+    let random = nicks.next_candidate("nyan_", true).unwrap();
+    assert!(random.starts_with("miau") && random.len() > 4);
+
+    for _ in 0..MAX_ATTEMPTS {
+        nicks.next_candidate("x", false);
+    }
+    assert_eq!(nicks.next_candidate("x", false), None);
+}
+
+#[test]
+fn nick_regain_with_ison() {
+    let mut nicks = test_nicks("[irc.regain]\ninterval = 30");
+    let info = test_info("CASEMAPPING=rfc1459");
+    let now = Instant::now();
+    let mut out = Vec::new();
+
+    nicks.start(&mut out, "MIAU", &info, now);
+    assert!(out.is_empty());
+
+    nicks.start(&mut out, "miau_", &info, now);
+    assert_eq!(nicks.next_tick(), Some(now));
+    nicks.tick(&mut out, now);
+    assert_eq!(out, vec!["ISON miau"]);
+    assert_eq!(nicks.next_tick(), Some(now + Duration::from_secs(30)));
+
+    nicks.handle(&mut out, "miau_", &info, &msg(":irc.test 303 miau_ :Miau"), now);
+    assert_eq!(out.len(), 1);
+    nicks.handle(&mut out, "miau_", &info, &msg(":irc.test 303 miau_ :"), now);
+    assert_eq!(out[1], "NICK miau");
+
+    nicks.handle(&mut out, "miau_", &info, &msg(":miau_!m@h NICK miau"), now);
+    assert_eq!(nicks.next_tick(), None);
+}
+
+#[test]
+fn nick_regain_with_monitor_and_nickserv() {
+    let mut nicks = test_nicks(
+        "[irc.regain]\nmethod = \"ghost\"\npassword = \"hunter2\"");
+    let info = test_info("MONITOR=100");
+    let now = Instant::now();
+    let mut out = Vec::new();
+
+    nicks.start(&mut out, "miau_", &info, now);
+    assert_eq!(out, vec!["PRIVMSG NickServ :GHOST miau hunter2", "MONITOR + miau"]);
+    assert_eq!(nicks.next_tick(), None);
+    out.clear();
+
+    nicks.handle(&mut out, "miau_", &info, &msg(":irc.test 731 miau_ :miau"), now);
+    assert_eq!(out, vec!["NICK miau"]);
+
+    nicks.handle(&mut out, "miau_", &info, &msg(":irc.test 437 miau_ miau :Nick is delayed"), now);
+    assert_eq!(nicks.next_tick(), Some(now + Duration::from_secs(60)));
+    nicks.tick(&mut out, now + Duration::from_secs(60));
+    assert_eq!(out[1], "NICK miau");
+
+    nicks.handle(&mut out, "miau_", &info, &msg(":miau_!m@h NICK miau"), now);
+    assert_eq!(out[2], "MONITOR - miau");
+}
+
+#[test]
+fn nick_regain_disabled() {
+    let mut nicks = test_nicks("[irc.regain]\nenabled = false");
+    let mut out = Vec::new();
+    nicks.start(&mut out, "miau_", &ServerInfo::new(), Instant::now());
+    assert!(out.is_empty());
+    assert_eq!(nicks.next_tick(), None);
+}
@@ -0,0 +1,284 @@
+//! SASL authentication during registration.
+//!
+//! Once the server has acknowledged the `sasl` capability, we authenticate
+//! with the mechanism from `irc.sasl.mechanism` before ending capability
+//! negotiation, so that we're logged in (and cloaked) before the server
+//! welcomes us. `PLAIN` sends `irc.sasl.username` and `irc.sasl.password`,
+//! and `EXTERNAL` relies on the TLS client certificate.
+
+use base64;
+
+use environment::Env;
+use irc::Command;
+use irc::Message;
+use irc::OwnedMessage;
+use network::Caps;
+use network::Output;
+
+/// The most base64 that fits in one `AUTHENTICATE` message.
+const CHUNK_LEN: usize = 400;
+
+pub struct Sasl {
+    mechanism: Mechanism,
+    required: bool,
+    state: State,
+}
+
+enum Mechanism {
+    Plain { username: String, password: String },
+    External,
+}
+
+enum State {
+    Idle,
+    Authenticating,
+    Succeeded,
+    Failed(String),
+}
+
+/// Where authentication has got to.
+pub enum Progress {
+    /// We're still waiting on the server.
+    Continue,
+    /// We're logged in.
+    Finished,
+    /// Authentication failed for the given reason.
+    Failed(String),
+}
+
+impl Sasl {
+    /// Creates a `Sasl` from the `irc.sasl` section, if SASL is configured.
+    pub fn from_env(env: &Env) -> Option<Sasl> {
+        let username = env.conf_str("irc.sasl.username");
+        let mech = match (env.conf_str("irc.sasl.mechanism"), username) {
+            (Some(mech), _) => mech,
+            (None, Some(_)) => "PLAIN",
+            (None, None) => return None,
+        };
+
+        let mechanism = match &mech.to_ascii_uppercase()[..] {
+            "EXTERNAL" => Mechanism::External,
+            "PLAIN" => Mechanism::Plain {
+                username: username.unwrap_or("").to_string(),
+                password: env.conf_str("irc.sasl.password").unwrap_or("").to_string(),
+            },
+            _ => {
+                error!("unsupported SASL mechanism {}, not authenticating", mech);
+                return None;
+            },
+        };
+
+        Some(Sasl {
+            mechanism: mechanism,
+            required: env.conf_bool("irc.sasl.required").unwrap_or(false),
+            state: State::Idle,
+        })
+    }
+
+    /// Whether the connection should be dropped if authentication fails.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Whether we've logged in.
+    pub fn succeeded(&self) -> bool {
+        match self.state {
+            State::Succeeded => true,
+            _ => false,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.mechanism {
+            Mechanism::Plain { .. } => "PLAIN",
+            Mechanism::External => "EXTERNAL",
+        }
+    }
+
+    /// Starts authenticating if we haven't yet, and reports how it's going.
+    /// Should be called once capability negotiation has settled.
+    pub fn poll<T: Output>(&mut self, out: &mut T, caps: &Caps) -> Progress {
+        if let State::Idle = self.state {
+            self.state = self.start(out, caps);
+        }
+
+        match self.state {
+            State::Idle | State::Authenticating => Progress::Continue,
+            State::Succeeded => Progress::Finished,
+            State::Failed(ref why) => Progress::Failed(why.clone()),
+        }
+    }
+
+    fn start<T: Output>(&mut self, out: &mut T, caps: &Caps) -> State {
+        if !caps.is_enabled("sasl") {
+            return State::Failed("server doesn't support SASL".to_string());
+        }
+
+        // with CAP LS 302, the server tells us which mechanisms it supports
+        let name = self.name();
+        if let Some(mechs) = caps.value("sasl") {
+            if !mechs.is_empty() && !mechs.split(',').any(|m| m.eq_ignore_ascii_case(name)) {
+                return State::Failed(format!("server only supports {}", mechs));
+            }
+        }
+
+        info!("authenticating with SASL {}", name);
+        out.send_message(&Message::new("AUTHENTICATE", vec![name]));
+        State::Authenticating
+    }
+
+    /// Handles a message that might be part of the SASL exchange. Returns
+    /// whether it was.
+    pub fn handle<T: Output>(&mut self, out: &mut T, m: &OwnedMessage) -> bool {
+        match m.command {
+            Command::Raw(ref verb, ref args) if verb == "AUTHENTICATE" => {
+                if let State::Authenticating = self.state {
+                    if args.get(0).map(|a| &a[..]) == Some("+") {
+                        self.send_response(out);
+                    }
+                }
+            },
+
+            Command::Numeric(900, ref args) => { // RPL_LOGGEDIN
+                let account = args.get(2).map(|a| &a[..]).unwrap_or("?");
+                info!("logged in as {}", account);
+            },
+
+            Command::Numeric(903, _) | // RPL_SASLSUCCESS
+            Command::Numeric(907, _) => { // ERR_SASLALREADY
+                info!("SASL authentication succeeded");
+                self.state = State::Succeeded;
+            },
+
+            Command::Numeric(908, ref args) => { // RPL_SASLMECHS
+                let mechs = args.get(1).map(|a| &a[..]).unwrap_or("?");
+                warn!("server supports SASL mechanisms {}", mechs);
+            },
+
+            Command::Numeric(num @ 902, ref args) | // ERR_NICKLOCKED
+            Command::Numeric(num @ 904, ref args) | // ERR_SASLFAIL
+            Command::Numeric(num @ 905, ref args) | // ERR_SASLTOOLONG
+            Command::Numeric(num @ 906, ref args) => { // ERR_SASLABORTED
+                let why = args.last().map(|a| &a[..]).unwrap_or("no reason given");
+                self.state = State::Failed(format!("{} ({})", why, num));
+            },
+
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn send_response<T: Output>(&self, out: &mut T) {
+        let payload = match self.mechanism {
+            Mechanism::Plain { ref username, ref password } => {
+                let creds = format!("{}\0{}\0{}", username, username, password);
+                base64::encode(creds.as_bytes())
+            },
+            Mechanism::External => String::new(),
+        };
+
+        for chunk in chunks(&payload) {
+            out.send_message(&Message::new("AUTHENTICATE", vec![chunk]));
+        }
+    }
+}
+
+/// Splits a base64 payload into `AUTHENTICATE` arguments. A payload that
+/// ends on a chunk boundary (including an empty one) is followed by `+`.
+fn chunks(payload: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = payload;
+
+    // base64 is ASCII, so any byte offset is a character boundary
+    while rest.len() >= CHUNK_LEN {
+        let (chunk, tail) = rest.split_at(CHUNK_LEN);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    chunks.push(if rest.is_empty() { "+" } else { rest });
+    chunks
+}
+
+#[cfg(test)]
+fn test_sasl(mechanism: Mechanism) -> Sasl {
+    Sasl { mechanism: mechanism, required: true, state: State::Idle }
+}
+
+#[cfg(test)]
+fn test_caps(ls: &str) -> Caps {
+    let mut out = Vec::new();
+    let mut caps = Caps::from_env(&::environment::from_str("[irc]\ncaps = [\"sasl\"]"));
+    caps.start(&mut out);
+    let args = vec!["*".to_string(), "LS".to_string(), ls.to_string()];
+    caps.handle(&mut out, &args).unwrap();
+    caps.handle(&mut out, &["*".to_string(), "ACK".to_string(), "sasl".to_string()]).unwrap();
+    caps
+}
+
+#[cfg(test)]
+fn msg(line: &str) -> OwnedMessage {
+    OwnedMessage::parse(line).unwrap()
+}
+
+#[test]
+fn sasl_plain() {
+    let mut out = Vec::new();
+    let caps = test_caps("sasl=PLAIN,EXTERNAL");
+    let mut sasl = test_sasl(Mechanism::Plain {
+        username: "miau".to_string(),
+        password: "hunter2".to_string(),
+    });
+
+    match sasl.poll(&mut out, &caps) { Progress::Continue => {}, _ => panic!() }
+    assert_eq!(out, vec!["AUTHENTICATE PLAIN"]);
+
+    assert!(sasl.handle(&mut out, &msg("AUTHENTICATE +")));
+    assert_eq!(out[1], format!("AUTHENTICATE {}", base64::encode(b"miau\0miau\0hunter2")));
+
+    assert!(sasl.handle(&mut out, &msg(":irc.test 900 miau miau!miau@x miau :Logged in")));
+    assert!(sasl.handle(&mut out, &msg(":irc.test 903 miau :SASL successful")));
+    match sasl.poll(&mut out, &caps) { Progress::Finished => {}, _ => panic!() }
+
+    assert!(!sasl.handle(&mut out, &msg(":irc.test 001 miau :Welcome")));
+}
+
+#[test]
+fn sasl_external_failure() {
+    let mut out = Vec::new();
+    let caps = test_caps("sasl");
+    let mut sasl = test_sasl(Mechanism::External);
+
+    sasl.poll(&mut out, &caps);
+    sasl.handle(&mut out, &msg("AUTHENTICATE +"));
+    assert_eq!(out, vec!["AUTHENTICATE EXTERNAL", "AUTHENTICATE +"]);
+
+    sasl.handle(&mut out, &msg(":irc.test 904 miau :SASL authentication failed"));
+    match sasl.poll(&mut out, &caps) {
+        Progress::Failed(why) => assert_eq!(why, "SASL authentication failed (904)"),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn sasl_unsupported_mechanism() {
+    let mut out = Vec::new();
+    let caps = test_caps("sasl=EXTERNAL");
+    let mut sasl = test_sasl(Mechanism::Plain { username: "a".into(), password: "b".into() });
+
+    match sasl.poll(&mut out, &caps) { Progress::Failed(_) => {}, _ => panic!() }
+    assert!(out.is_empty());
+}
+
+#[test]
+fn sasl_chunks() {
+    assert_eq!(chunks(""), vec!["+"]);
+    assert_eq!(chunks("abc"), vec!["abc"]);
+
+    let exact = "a".repeat(CHUNK_LEN);
+    assert_eq!(chunks(&exact), vec![&exact[..], "+"]);
+
+    let long = format!("{}{}", "a".repeat(CHUNK_LEN), "b");
+    assert_eq!(chunks(&long), vec![&long[..CHUNK_LEN], "b"]);
+}
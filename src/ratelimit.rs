@@ -0,0 +1,129 @@
+//! Token bucket rate limiting for outgoing lines.
+//!
+//! Servers disconnect clients that send too much too quickly ("Excess
+//! Flood"), so normal traffic has to take a token from the bucket before it
+//! can be sent. The bucket holds up to `irc.flood.burst` tokens and refills at
+//! `irc.flood.rate` tokens per second.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use environment::Env;
+use environment::secs_to_duration;
+
+pub struct TokenBucket {
+    burst: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(burst: u32, rate: f64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            burst: burst.max(1) as f64,
+            rate: rate,
+            tokens: burst.max(1) as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Creates a full bucket configured from the `irc.flood` section. Setting
+    /// `irc.flood.enabled` to false gives a bucket that never runs out.
+    pub fn from_env(env: &Env, now: Instant) -> TokenBucket {
+        if !env.conf_bool("irc.flood.enabled").unwrap_or(true) {
+            return TokenBucket::new(1, ::std::f64::INFINITY, now);
+        }
+
+        let burst = env.conf_integer("irc.flood.burst").unwrap_or(5);
+        let rate = env.conf_number("irc.flood.rate").unwrap_or(0.5);
+
+        TokenBucket::new(burst.max(1) as u32, rate, now)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.last_refill {
+            let elapsed = now - self.last_refill;
+            let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+            self.tokens = (self.tokens + secs * self.rate).min(self.burst);
+            self.last_refill = now;
+        }
+    }
+
+    /// Whether a token could be taken right now.
+    pub fn has_token(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// Takes a token if there's one available.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        if self.has_token(now) {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes a token if there's one available, and otherwise lets the line
+    /// through anyway. Used for traffic that can't wait, which still counts
+    /// against the server's limits.
+    pub fn take_anyway(&mut self, now: Instant) {
+        self.refill(now);
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+
+    /// The earliest point at which a token will be available.
+    pub fn next_token_at(&mut self, now: Instant) -> Instant {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            now
+        } else if self.rate <= 0.0 {
+            // never going to refill, but don't spin either
+            now + Duration::from_secs(3600)
+        } else {
+            now + secs_to_duration((1.0 - self.tokens) / self.rate)
+        }
+    }
+}
+
+#[test]
+fn token_bucket_burst_then_refill() {
+    let start = Instant::now();
+    let mut b = TokenBucket::new(3, 0.5, start);
+
+    assert!(b.try_take(start));
+    assert!(b.try_take(start));
+    assert!(b.try_take(start));
+    assert!(!b.try_take(start));
+    assert_eq!(b.next_token_at(start), start + Duration::from_secs(2));
+
+    assert!(!b.try_take(start + Duration::from_secs(1)));
+    assert!(b.try_take(start + Duration::from_secs(2)));
+    assert!(!b.try_take(start + Duration::from_secs(2)));
+}
+
+#[test]
+fn token_bucket_caps_at_burst() {
+    let start = Instant::now();
+    let mut b = TokenBucket::new(2, 1.0, start);
+
+    let later = start + Duration::from_secs(100);
+    assert!(b.try_take(later));
+    assert!(b.try_take(later));
+    assert!(!b.try_take(later));
+}
+
+#[test]
+fn token_bucket_take_anyway() {
+    let start = Instant::now();
+    let mut b = TokenBucket::new(1, 1.0, start);
+
+    b.take_anyway(start);
+    b.take_anyway(start);
+    assert!(!b.has_token(start));
+    assert_eq!(b.next_token_at(start), start + Duration::from_secs(1));
+}
@@ -0,0 +1,99 @@
+//! Waking the bot up when something is due.
+//!
+//! Plugins that want to do things at particular times, like reminders, say
+//! when they next need to with `Plugin::next_due`. The `Scheduler` keeps a
+//! timer on the reactor for the soonest of those, and the bot calls
+//! `Plugin::tick` when it goes off. Times are Unix times in seconds, so that
+//! they can be saved and still mean the same thing after a restart.
+
+use std::io;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use tokio_core::reactor::Handle;
+use tokio_core::reactor::Timeout;
+
+/// The current time, in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub struct Scheduler {
+    handle: Handle,
+    timer: Option<(u64, Timeout)>,
+}
+
+impl Scheduler {
+    pub fn new(handle: Handle) -> Scheduler {
+        Scheduler { handle: handle, timer: None }
+    }
+
+    /// Waits for `due` to come around, returning the time once it has. If
+    /// there's nothing due, this waits forever.
+    pub fn poll(&mut self, due: Option<u64>) -> Poll<u64, io::Error> {
+        loop {
+            let due = match due {
+                Some(due) => due,
+                None => {
+                    self.timer = None;
+                    return Ok(Async::NotReady);
+                },
+            };
+
+            let now = unix_time();
+            if due <= now {
+                self.timer = None;
+                return Ok(Async::Ready(now));
+            }
+
+            let stale = match self.timer {
+                Some((at, _)) => at != due,
+                None => true,
+            };
+            if stale {
+                let timer = try!(Timeout::new(Duration::from_secs(due - now), &self.handle));
+                self.timer = Some((due, timer));
+            }
+
+            match self.timer.as_mut().map(|t| t.1.poll()) {
+                // the clock might not quite agree with the timer, so check again
+                Some(Ok(Async::Ready(()))) => {
+                    self.timer = None;
+                    continue;
+                },
+                Some(Err(e)) => return Err(e),
+                _ => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+#[test]
+fn scheduler_polls() {
+    use futures::future;
+    use tokio_core::reactor::Core;
+
+    let mut core = Core::new().unwrap();
+    let mut scheduler = Scheduler::new(core.handle());
+
+    match scheduler.poll(None) {
+        Ok(Async::NotReady) => assert!(scheduler.timer.is_none()),
+        _ => panic!("nothing was due"),
+    }
+
+    let now = unix_time();
+    match scheduler.poll(Some(now - 10)) {
+        Ok(Async::Ready(at)) => assert!(at >= now),
+        _ => panic!("something was due"),
+    }
+    assert!(scheduler.timer.is_none());
+
+    let due = unix_time() + 1;
+    let at = core.run(future::poll_fn(|| scheduler.poll(Some(due)))).unwrap();
+    assert!(at >= due);
+    assert!(scheduler.timer.is_none());
+}
@@ -0,0 +1,56 @@
+//! Storage kept in memory, for tests.
+
+use std::collections::BTreeMap;
+
+use storage::Backend;
+use storage::Changes;
+use storage::Error;
+
+pub struct Memory {
+    values: BTreeMap<String, BTreeMap<String, Vec<u8>>>,
+    versions: BTreeMap<String, u32>,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory { values: BTreeMap::new(), versions: BTreeMap::new() }
+    }
+}
+
+impl Backend for Memory {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.values.get(namespace).and_then(|ns| ns.get(key)).cloned())
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let ns = match self.values.get(namespace) {
+            Some(ns) => ns,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(ns.range(prefix.to_string()..)
+            .take_while(|&(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn version(&self, namespace: &str) -> Result<u32, Error> {
+        Ok(self.versions.get(namespace).cloned().unwrap_or(0))
+    }
+
+    fn apply(&mut self, changes: &Changes) -> Result<(), Error> {
+        let ns = self.values.entry(changes.namespace.to_string()).or_insert_with(BTreeMap::new);
+        for (key, value) in changes.writes {
+            match *value {
+                Some(ref value) => { ns.insert(key.clone(), value.clone()); },
+                None => { ns.remove(key); },
+            }
+        }
+
+        if let Some(version) = changes.version {
+            self.versions.insert(changes.namespace.to_string(), version);
+        }
+
+        Ok(())
+    }
+}
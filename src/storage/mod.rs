@@ -0,0 +1,330 @@
+//! Keeping plugin state across restarts.
+//!
+//! Storage is split into namespaces, one per plugin, each of which maps string
+//! keys to values. Values are anything implementing [`Value`](trait.Value.html),
+//! which covers strings, integers and booleans, and lists, options and tuples
+//! of those:
+//!
+//! ```ignore
+//! let seen = storage.namespace("seen");
+//! try!(seen.put("aji", &(1500000000i64, "#miau-dev".to_string())));
+//! let when: Option<(i64, String)> = try!(seen.get("aji"));
+//! ```
+//!
+//! Several changes can be made at once with
+//! [`transaction`](struct.Namespace.html#method.transaction), in which case
+//! either all of them are saved or none are. Namespaces also keep a version
+//! number, so that plugins can change how they lay out their data with
+//! [`migrate`](struct.Namespace.html#method.migrate).
+//!
+//! The database is SQLite, kept at `storage.path`:
+//!
+//! ```toml
+//! [storage]
+//! path = "/opt/miau/data/miau.sqlite"
+//! ```
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use environment::Env;
+
+mod memory;
+mod sqlite;
+mod value;
+
+pub use self::memory::Memory;
+pub use self::sqlite::Sqlite;
+pub use self::value::Value;
+
+const DEFAULT_PATH: &'static str = "miau.sqlite";
+
+/// Something wrong with storage, either in the database itself or in what
+/// was found there.
+#[derive(Debug)]
+pub enum Error {
+    Sqlite(::rusqlite::Error),
+    /// A value couldn't be read back as the type it was asked for.
+    Decode { namespace: String, key: String },
+    /// The data was written by a newer version of the bot.
+    TooNew { namespace: String, version: u32, known: u32 },
+    /// A plugin gave up on a transaction.
+    Aborted(String),
+}
+
+impl From<::rusqlite::Error> for Error {
+    fn from(err: ::rusqlite::Error) -> Error { Error::Sqlite(err) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Sqlite(ref err) => write!(f, "sqlite: {}", err),
+            Error::Decode { ref namespace, ref key } =>
+                write!(f, "couldn't decode {}/{}", namespace, key),
+            Error::TooNew { ref namespace, version, known } =>
+                write!(f, "{} is at version {}, but only {} is known", namespace, version, known),
+            Error::Aborted(ref why) => write!(f, "aborted: {}", why),
+        }
+    }
+}
+
+/// Changes to a namespace, to be saved all at once. A `None` value deletes
+/// the key.
+pub struct Changes<'a> {
+    pub namespace: &'a str,
+    pub writes: &'a BTreeMap<String, Option<Vec<u8>>>,
+    pub version: Option<u32>,
+}
+
+/// Somewhere to keep namespaces of raw values.
+pub trait Backend {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Every key in the namespace starting with `prefix`, in order.
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, Error>;
+
+    fn version(&self, namespace: &str) -> Result<u32, Error>;
+
+    /// Saves all of the changes, or none of them if something goes wrong.
+    fn apply(&mut self, changes: &Changes) -> Result<(), Error>;
+}
+
+/// A handle to the bot's storage. Clones share the same backend.
+#[derive(Clone)]
+pub struct Storage {
+    backend: Rc<RefCell<Box<Backend>>>,
+}
+
+impl Storage {
+    pub fn new(backend: Box<Backend>) -> Storage {
+        Storage { backend: Rc::new(RefCell::new(backend)) }
+    }
+
+    /// Opens the database at `storage.path`, creating it if needed.
+    pub fn open(env: &Env) -> Result<Storage, Error> {
+        let path = env.conf_str("storage.path").unwrap_or_else(|| {
+            warn!("storage.path defaulting to {}", DEFAULT_PATH);
+            DEFAULT_PATH
+        });
+        info!("opening storage at {}", path);
+        Ok(Storage::new(Box::new(try!(Sqlite::open(path)))))
+    }
+
+    /// Storage that's forgotten when dropped, for tests.
+    pub fn memory() -> Storage {
+        Storage::new(Box::new(Memory::new()))
+    }
+
+    pub fn namespace(&self, name: &str) -> Namespace {
+        Namespace { storage: self.clone(), name: name.to_string() }
+    }
+}
+
+/// One plugin's corner of storage.
+#[derive(Clone)]
+pub struct Namespace {
+    storage: Storage,
+    name: String,
+}
+
+/// Steps for bringing a namespace up to date. The first step takes a
+/// namespace from version 0 to 1, the second from 1 to 2, and so on.
+pub type Migration = fn(&mut Transaction) -> Result<(), Error>;
+
+impl Namespace {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get<T: Value>(&self, key: &str) -> Result<Option<T>, Error> {
+        let raw = try!(self.storage.backend.borrow().get(&self.name, key));
+        raw.map(|raw| self.decode(key, &raw)).map_or(Ok(None), |v| v.map(Some))
+    }
+
+    pub fn put<T: Value>(&self, key: &str, value: &T) -> Result<(), Error> {
+        self.transaction(|tx| { tx.put(key, value); Ok(()) })
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        self.transaction(|tx| { tx.delete(key); Ok(()) })
+    }
+
+    /// Every key starting with `prefix` and its value, in order of key.
+    pub fn scan<T: Value>(&self, prefix: &str) -> Result<Vec<(String, T)>, Error> {
+        let raw = try!(self.storage.backend.borrow().scan(&self.name, prefix));
+        raw.into_iter().map(|(key, raw)| {
+            let value = try!(self.decode(&key, &raw));
+            Ok((key, value))
+        }).collect()
+    }
+
+    pub fn version(&self) -> Result<u32, Error> {
+        self.storage.backend.borrow().version(&self.name)
+    }
+
+    /// Runs `f`, then saves everything it changed at once. Nothing is saved
+    /// if it returns an error.
+    pub fn transaction<F, R>(&self, f: F) -> Result<R, Error>
+    where F: FnOnce(&mut Transaction) -> Result<R, Error> {
+        let mut tx = Transaction { ns: self, writes: BTreeMap::new(), version: None };
+        let result = try!(f(&mut tx));
+        try!(tx.commit());
+        Ok(result)
+    }
+
+    /// Runs whichever migrations haven't been run yet, each in its own
+    /// transaction.
+    pub fn migrate(&self, migrations: &[Migration]) -> Result<(), Error> {
+        let known = migrations.len() as u32;
+        let version = try!(self.version());
+        if version > known {
+            return Err(Error::TooNew {
+                namespace: self.name.clone(),
+                version: version,
+                known: known,
+            });
+        }
+
+        for (i, migration) in migrations.iter().enumerate().skip(version as usize) {
+            info!("migrating {} to version {}", self.name, i + 1);
+            try!(self.transaction(|tx| {
+                try!(migration(tx));
+                tx.version = Some(i as u32 + 1);
+                Ok(())
+            }));
+        }
+
+        Ok(())
+    }
+
+    fn decode<T: Value>(&self, key: &str, raw: &[u8]) -> Result<T, Error> {
+        T::decode(raw).ok_or_else(|| Error::Decode {
+            namespace: self.name.clone(),
+            key: key.to_string(),
+        })
+    }
+}
+
+/// Changes being made to a namespace. Reads see the transaction's own
+/// writes.
+pub struct Transaction<'a> {
+    ns: &'a Namespace,
+    writes: BTreeMap<String, Option<Vec<u8>>>,
+    version: Option<u32>,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn get<T: Value>(&self, key: &str) -> Result<Option<T>, Error> {
+        match self.writes.get(key) {
+            Some(&Some(ref raw)) => self.ns.decode(key, raw).map(Some),
+            Some(&None) => Ok(None),
+            None => self.ns.get(key),
+        }
+    }
+
+    pub fn put<T: Value>(&mut self, key: &str, value: &T) {
+        self.writes.insert(key.to_string(), Some(value.encode()));
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        self.writes.insert(key.to_string(), None);
+    }
+
+    pub fn scan<T: Value>(&self, prefix: &str) -> Result<Vec<(String, T)>, Error> {
+        let mut found: BTreeMap<String, Vec<u8>> = try!(
+            self.ns.storage.backend.borrow().scan(&self.ns.name, prefix)
+        ).into_iter().collect();
+
+        for (key, raw) in &self.writes {
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            match *raw {
+                Some(ref raw) => { found.insert(key.clone(), raw.clone()); },
+                None => { found.remove(key); },
+            }
+        }
+
+        found.into_iter().map(|(key, raw)| {
+            let value = try!(self.ns.decode(&key, &raw));
+            Ok((key, value))
+        }).collect()
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        if self.writes.is_empty() && self.version.is_none() {
+            return Ok(());
+        }
+        self.ns.storage.backend.borrow_mut().apply(&Changes {
+            namespace: &self.ns.name,
+            writes: &self.writes,
+            version: self.version,
+        })
+    }
+}
+
+#[cfg(test)]
+pub fn check_backend(storage: Storage) {
+    let ns = storage.namespace("test");
+    let other = storage.namespace("other");
+
+    assert_eq!(ns.get::<String>("a").unwrap(), None);
+    ns.put("a", &"apple".to_string()).unwrap();
+    ns.put("b", &42i64).unwrap();
+    ns.put("ab", &true).unwrap();
+    other.put("a", &"elsewhere".to_string()).unwrap();
+
+    assert_eq!(ns.get::<String>("a").unwrap(), Some("apple".to_string()));
+    assert_eq!(ns.get::<i64>("b").unwrap(), Some(42));
+    assert_eq!(other.get::<String>("a").unwrap(), Some("elsewhere".to_string()));
+    assert!(ns.get::<i64>("a").is_err());
+
+    let keys: Vec<String> = ns.scan::<String>("a").unwrap().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec!["a", "ab"]);
+
+    ns.delete("ab").unwrap();
+    assert_eq!(ns.get::<bool>("ab").unwrap(), None);
+
+    let failed: Result<(), Error> = ns.transaction(|tx| {
+        tx.put("a", &"avocado".to_string());
+        assert_eq!(tx.get::<String>("a").unwrap(), Some("avocado".to_string()));
+        Err(Error::Aborted("changed my mind".to_string()))
+    });
+    assert!(failed.is_err());
+    assert_eq!(ns.get::<String>("a").unwrap(), Some("apple".to_string()));
+
+    ns.transaction(|tx| {
+        tx.put("c", &3i64);
+        tx.delete("b");
+        assert_eq!(tx.scan::<String>("").unwrap().len(), 2);
+        Ok(())
+    }).unwrap();
+    assert_eq!(ns.get::<i64>("c").unwrap(), Some(3));
+    assert_eq!(ns.get::<i64>("b").unwrap(), None);
+
+    fn first(tx: &mut Transaction) -> Result<(), Error> {
+        tx.put("migrated", &1i64);
+        Ok(())
+    }
+    fn second(tx: &mut Transaction) -> Result<(), Error> {
+        let n: i64 = try!(tx.get("migrated")).unwrap_or(0);
+        tx.put("migrated", &(n + 1));
+        Ok(())
+    }
+    assert_eq!(ns.version().unwrap(), 0);
+    ns.migrate(&[first]).unwrap();
+    ns.migrate(&[first, second]).unwrap();
+    ns.migrate(&[first, second]).unwrap();
+    assert_eq!(ns.version().unwrap(), 2);
+    assert_eq!(other.version().unwrap(), 0);
+    assert_eq!(ns.get::<i64>("migrated").unwrap(), Some(2));
+    assert!(ns.migrate(&[first]).is_err());
+}
+
+#[test]
+fn storage_memory() {
+    check_backend(Storage::memory());
+}
@@ -0,0 +1,171 @@
+//! Storage in an SQLite database.
+
+use std::fs;
+use std::path::Path;
+
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::NO_PARAMS;
+
+use storage::Backend;
+use storage::Changes;
+use storage::Error;
+
+/// Changes to the database's own tables, kept in `PRAGMA user_version`. Only
+/// ever add to the end of this.
+const SCHEMA: &'static [&'static str] = &[
+    "CREATE TABLE kv (
+        namespace TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BLOB NOT NULL,
+        PRIMARY KEY (namespace, key)
+    );
+    CREATE TABLE versions (
+        namespace TEXT PRIMARY KEY,
+        version INTEGER NOT NULL
+    );",
+];
+
+pub struct Sqlite {
+    conn: Connection,
+}
+
+impl Sqlite {
+    /// Opens the database at `path`, creating it and any missing directories
+    /// if needed.
+    pub fn open(path: &str) -> Result<Sqlite, Error> {
+        if let Some(dir) = Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() {
+                if let Err(e) = fs::create_dir_all(dir) {
+                    warn!("couldn't create {}: {}", dir.display(), e);
+                }
+            }
+        }
+
+        let mut db = Sqlite { conn: try!(Connection::open(path)) };
+        try!(db.upgrade());
+        Ok(db)
+    }
+
+    fn upgrade(&mut self) -> Result<(), Error> {
+        let known = SCHEMA.len() as u32;
+        let version: u32 = try!(self.conn.query_row("PRAGMA user_version", NO_PARAMS,
+            |row| row.get(0)));
+        if version > known {
+            return Err(Error::TooNew {
+                namespace: "storage".to_string(),
+                version: version,
+                known: known,
+            });
+        }
+
+        for (i, sql) in SCHEMA.iter().enumerate().skip(version as usize) {
+            debug!("upgrading storage schema to version {}", i + 1);
+            let tx = try!(self.conn.transaction());
+            try!(tx.execute_batch(sql));
+            try!(tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1)));
+            try!(tx.commit());
+        }
+
+        Ok(())
+    }
+}
+
+impl Backend for Sqlite {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let value = try!(self.conn.query_row(
+            "SELECT value FROM kv WHERE namespace = ?1 AND key = ?2",
+            &[namespace, key],
+            |row| row.get(0)
+        ).optional());
+        Ok(value)
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        // LIKE would need the prefix escaping, and comparing is just as quick
+        let mut stmt = try!(self.conn.prepare(
+            "SELECT key, value FROM kv WHERE namespace = ?1 AND key >= ?2 ORDER BY key"
+        ));
+        let rows = try!(stmt.query_map(&[namespace, prefix], |row| {
+            Ok((try!(row.get::<_, String>(0)), try!(row.get::<_, Vec<u8>>(1))))
+        }));
+
+        let mut found = Vec::new();
+        for row in rows {
+            let (key, value) = try!(row);
+            if !key.starts_with(prefix) {
+                break;
+            }
+            found.push((key, value));
+        }
+        Ok(found)
+    }
+
+    fn version(&self, namespace: &str) -> Result<u32, Error> {
+        let version: Option<u32> = try!(self.conn.query_row(
+            "SELECT version FROM versions WHERE namespace = ?1",
+            &[namespace],
+            |row| row.get(0)
+        ).optional());
+        Ok(version.unwrap_or(0))
+    }
+
+    fn apply(&mut self, changes: &Changes) -> Result<(), Error> {
+        let tx = try!(self.conn.transaction());
+
+        for (key, value) in changes.writes {
+            match *value {
+                Some(ref value) => try!(tx.execute(
+                    "INSERT OR REPLACE INTO kv (namespace, key, value) VALUES (?1, ?2, ?3)",
+                    &[&changes.namespace as &::rusqlite::ToSql, key, value]
+                )),
+                None => try!(tx.execute(
+                    "DELETE FROM kv WHERE namespace = ?1 AND key = ?2",
+                    &[changes.namespace, key]
+                )),
+            };
+        }
+
+        if let Some(version) = changes.version {
+            try!(tx.execute(
+                "INSERT OR REPLACE INTO versions (namespace, version) VALUES (?1, ?2)",
+                &[&changes.namespace as &::rusqlite::ToSql, &version]
+            ));
+        }
+
+        try!(tx.commit());
+        Ok(())
+    }
+}
+
+#[test]
+fn storage_sqlite() {
+    use storage::check_backend;
+    use storage::Storage;
+
+    check_backend(Storage::new(Box::new(Sqlite::open(":memory:").unwrap())));
+}
+
+#[test]
+fn storage_sqlite_persists() {
+    use std::env;
+    use storage::Storage;
+
+    let dir = env::temp_dir().join(format!("miau-storage-test-{}", ::rand::random::<u32>()));
+    let path = dir.join("miau.sqlite");
+    let path = path.to_str().unwrap();
+
+    {
+        let storage = Storage::new(Box::new(Sqlite::open(path).unwrap()));
+        let ns = storage.namespace("test");
+        ns.put("key", &"value".to_string()).unwrap();
+        ns.migrate(&[|_| Ok(())]).unwrap();
+    }
+
+    let storage = Storage::new(Box::new(Sqlite::open(path).unwrap()));
+    let ns = storage.namespace("test");
+    assert_eq!(ns.get::<String>("key").unwrap(), Some("value".to_string()));
+    assert_eq!(ns.version().unwrap(), 1);
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,158 @@
+//! Turning values into bytes for storage, and back.
+//!
+//! Numbers and booleans are stored as text, so that the database can be read
+//! by hand. Lists and tuples are stored as their parts one after another, each
+//! preceded by its length, as in `5:apple3:fig`.
+
+use std::str;
+
+/// Something that can be stored.
+pub trait Value: Sized {
+    fn encode(&self) -> Vec<u8>;
+
+    /// Reads a value back from `encode`, or `None` if it doesn't look like
+    /// one.
+    fn decode(raw: &[u8]) -> Option<Self>;
+}
+
+impl Value for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(raw: &[u8]) -> Option<String> {
+        String::from_utf8(raw.to_vec()).ok()
+    }
+}
+
+macro_rules! value_from_str {
+    ($($t:ty),*) => {$(
+        impl Value for $t {
+            fn encode(&self) -> Vec<u8> {
+                self.to_string().into_bytes()
+            }
+
+            fn decode(raw: &[u8]) -> Option<$t> {
+                str::from_utf8(raw).ok().and_then(|s| s.parse().ok())
+            }
+        }
+    )*}
+}
+
+value_from_str!(i64, u64, i32, u32, bool);
+
+impl<T: Value> Value for Option<T> {
+    fn encode(&self) -> Vec<u8> {
+        match *self {
+            Some(ref value) => {
+                let mut raw = vec![b'+'];
+                raw.extend(value.encode());
+                raw
+            },
+            None => vec![b'-'],
+        }
+    }
+
+    fn decode(raw: &[u8]) -> Option<Option<T>> {
+        match raw.split_first() {
+            Some((&b'+', rest)) => T::decode(rest).map(Some),
+            Some((&b'-', rest)) if rest.is_empty() => Some(None),
+            _ => None,
+        }
+    }
+}
+
+fn write_part<T: Value>(raw: &mut Vec<u8>, value: &T) {
+    let part = value.encode();
+    raw.extend(part.len().to_string().into_bytes());
+    raw.push(b':');
+    raw.extend(part);
+}
+
+/// Reads a part written by `write_part` off the front of `raw`.
+fn read_part<T: Value>(raw: &mut &[u8]) -> Option<T> {
+    let colon = match raw.iter().position(|&b| b == b':') {
+        Some(colon) => colon,
+        None => return None,
+    };
+    let len: usize = match str::from_utf8(&raw[..colon]).ok().and_then(|s| s.parse().ok()) {
+        Some(len) => len,
+        None => return None,
+    };
+
+    let rest = &raw[colon + 1..];
+    if rest.len() < len {
+        return None;
+    }
+    *raw = &rest[len..];
+    T::decode(&rest[..len])
+}
+
+impl<T: Value> Value for Vec<T> {
+    fn encode(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for value in self {
+            write_part(&mut raw, value);
+        }
+        raw
+    }
+
+    fn decode(mut raw: &[u8]) -> Option<Vec<T>> {
+        let mut values = Vec::new();
+        while !raw.is_empty() {
+            match read_part(&mut raw) {
+                Some(value) => values.push(value),
+                None => return None,
+            }
+        }
+        Some(values)
+    }
+}
+
+macro_rules! value_tuple {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: Value),*> Value for ($($t,)*) {
+            fn encode(&self) -> Vec<u8> {
+                let mut raw = Vec::new();
+                $(write_part(&mut raw, &self.$i);)*
+                raw
+            }
+
+            fn decode(mut raw: &[u8]) -> Option<($($t,)*)> {
+                let value = ($(match read_part::<$t>(&mut raw) {
+                    Some(part) => part,
+                    None => return None,
+                },)*);
+                if raw.is_empty() { Some(value) } else { None }
+            }
+        }
+    }
+}
+
+value_tuple!(A 0, B 1);
+value_tuple!(A 0, B 1, C 2);
+value_tuple!(A 0, B 1, C 2, D 3);
+value_tuple!(A 0, B 1, C 2, D 3, E 4);
+
+#[cfg(test)]
+fn round_trip<T: Value + PartialEq + ::std::fmt::Debug>(value: T) {
+    assert_eq!(T::decode(&value.encode()), Some(value));
+}
+
+#[test]
+fn storage_values() {
+    round_trip("héllo".to_string());
+    round_trip(-5i64);
+    round_trip(true);
+    round_trip(Some(3u32));
+    round_trip(None::<String>);
+    round_trip(vec!["a:b".to_string(), "".to_string(), "12:".to_string()]);
+    round_trip((1500000000i64, "#miau-dev".to_string(), Some("bye".to_string())));
+    round_trip(vec![(1u64, vec![true, false]), (2, vec![])]);
+
+    assert_eq!((1i64, "fig".to_string()).encode(), b"1:13:fig".to_vec());
+    assert_eq!(i64::decode(b"twelve"), None);
+    assert_eq!(<(i64, String)>::decode(b"1:1"), None);
+    assert_eq!(<(i64, String)>::decode(b"1:13:fig1:x"), None);
+    assert_eq!(<Option<i64>>::decode(b"-1"), None);
+}
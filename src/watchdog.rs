@@ -0,0 +1,170 @@
+//! Noticing when the connection to the server has died.
+//!
+//! If nothing has been received for `bot.ping_interval` seconds, we send a
+//! `PING` of our own, and if there's still no traffic after another
+//! `bot.ping_timeout` seconds, the connection is considered dead. The round
+//! trip time of our pings is recorded as the lag.
+
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use environment::Env;
+
+pub struct Watchdog {
+    interval: Duration,
+    timeout: Duration,
+    last_activity: Instant,
+    ping: Option<(String, Instant)>,
+}
+
+/// What the watchdog wants done.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Nothing, until the given point.
+    Wait(Instant),
+    /// The connection has been idle, so a `PING` with this token should be
+    /// sent to check on it.
+    Ping(String),
+    /// Our `PING` went unanswered, and the connection should be dropped.
+    TimedOut,
+}
+
+impl Watchdog {
+    pub fn new(interval: Duration, timeout: Duration, now: Instant) -> Watchdog {
+        Watchdog {
+            interval: interval,
+            timeout: timeout,
+            last_activity: now,
+            ping: None,
+        }
+    }
+
+    /// Creates a watchdog configured from `bot.ping_interval` and
+    /// `bot.ping_timeout`.
+    pub fn from_env(env: &Env, now: Instant) -> Watchdog {
+        Watchdog::new(
+            env.conf_duration_or("bot.ping_interval", Duration::from_secs(120)),
+            env.conf_duration_or("bot.ping_timeout", Duration::from_secs(60)),
+            now,
+        )
+    }
+
+    /// Notes that something was received from the server.
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// Handles a `PONG`, returning the lag if it was the answer to our ping.
+    pub fn pong(&mut self, server: &str, token: Option<&str>, now: Instant) -> Option<Duration> {
+        let lag = match self.ping {
+            Some((ref ours, sent)) if token == Some(&ours[..]) || server == ours => now - sent,
+            _ => return None,
+        };
+        self.ping = None;
+        Some(lag)
+    }
+
+    /// The point at which the watchdog next needs to act. This is either the
+    /// end of the idle interval, or if there's already a ping out with no
+    /// traffic since, the point at which we give up waiting for it.
+    fn deadline(&self) -> Instant {
+        match self.ping {
+            Some((_, sent)) if self.last_activity < sent => sent + self.timeout,
+            Some((_, sent)) => self.last_activity.max(sent) + self.interval,
+            None => self.last_activity + self.interval,
+        }
+    }
+
+    /// Works out what needs doing at `now`. A `Ping` is taken to have been
+    /// sent right away.
+    pub fn check(&mut self, now: Instant) -> Action {
+        let deadline = self.deadline();
+        if now < deadline {
+            return Action::Wait(deadline);
+        }
+
+        match self.ping {
+            Some((_, sent)) if self.last_activity < sent => Action::TimedOut,
+            _ => {
+                let token = ping_token();
+                self.ping = Some((token.clone(), now));
+                Action::Ping(token)
+            },
+        }
+    }
+}
+
+/// Makes a token for our own pings that's unlikely to collide with anything
+/// the server would send on its own.
+fn ping_token() -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+    format!("miau-{}", since_epoch.as_secs())
+}
+
+#[cfg(test)]
+fn test_watchdog(start: Instant) -> Watchdog {
+    Watchdog::new(Duration::from_secs(120), Duration::from_secs(60), start)
+}
+
+#[cfg(test)]
+fn secs(start: Instant, n: u64) -> Instant {
+    start + Duration::from_secs(n)
+}
+
+#[test]
+fn watchdog_pings_when_idle() {
+    let start = Instant::now();
+    let mut w = test_watchdog(start);
+
+    assert_eq!(w.check(start), Action::Wait(secs(start, 120)));
+    w.activity(secs(start, 100));
+    assert_eq!(w.check(secs(start, 120)), Action::Wait(secs(start, 220)));
+
+    match w.check(secs(start, 220)) {
+        Action::Ping(ref token) => assert!(token.starts_with("miau-")),
+        other => panic!("expected a ping, got {:?}", other),
+    }
+    assert_eq!(w.check(secs(start, 220)), Action::Wait(secs(start, 280)));
+}
+
+#[test]
+fn watchdog_pong_clears_ping() {
+    let start = Instant::now();
+    let mut w = test_watchdog(start);
+
+    let token = match w.check(secs(start, 120)) {
+        Action::Ping(token) => token,
+        other => panic!("expected a ping, got {:?}", other),
+    };
+
+    w.activity(secs(start, 123));
+    assert_eq!(w.pong("irc.test", Some("something else"), secs(start, 123)), None);
+    assert_eq!(w.pong("irc.test", Some(&token), secs(start, 123)), Some(Duration::from_secs(3)));
+    assert_eq!(w.pong("irc.test", Some(&token), secs(start, 124)), None);
+    assert_eq!(w.check(secs(start, 200)), Action::Wait(secs(start, 243)));
+}
+
+#[test]
+fn watchdog_traffic_postpones_timeout() {
+    let start = Instant::now();
+    let mut w = test_watchdog(start);
+
+    assert!(match w.check(secs(start, 120)) { Action::Ping(_) => true, _ => false });
+    w.activity(secs(start, 150));
+    assert_eq!(w.check(secs(start, 180)), Action::Wait(secs(start, 270)));
+    assert!(match w.check(secs(start, 270)) { Action::Ping(_) => true, _ => false });
+}
+
+#[test]
+fn watchdog_times_out() {
+    let start = Instant::now();
+    let mut w = test_watchdog(start);
+
+    assert!(match w.check(secs(start, 120)) { Action::Ping(_) => true, _ => false });
+    assert_eq!(w.check(secs(start, 179)), Action::Wait(secs(start, 180)));
+    assert_eq!(w.check(secs(start, 180)), Action::TimedOut);
+}